@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE sessions (
+    id UUID NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL,
+    user_agent VARCHAR (255),
+    ip VARCHAR (64)
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- every session is one refresh token family, tokens are stored as sha256 hex
+CREATE TABLE refresh_tokens (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+    token_hash VARCHAR (64) UNIQUE NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX refresh_tokens_session_id_idx ON refresh_tokens (session_id);
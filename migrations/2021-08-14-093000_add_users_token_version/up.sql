@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- bumped whenever credentials, email or clearance change so old tokens stop working
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- mails are written here in the same transaction as the change that caused them
+-- and sent afterwards, so a crash in between does not lose them
+CREATE TABLE email_outbox (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    recipient VARCHAR (100) NOT NULL,
+    subject VARCHAR (255) NOT NULL,
+    text_body TEXT NOT NULL,
+    html_body TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    send_after TIMESTAMP NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    sent_at TIMESTAMP
+);
+
+CREATE INDEX email_outbox_pending_idx ON email_outbox (send_after) WHERE sent_at IS NULL;
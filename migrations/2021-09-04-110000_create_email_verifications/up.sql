@@ -0,0 +1,17 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP;
+-- accounts from before verification existed count as verified
+UPDATE users SET email_verified_at = created_at;
+
+-- single use tokens proving the user owns `email`, stored as sha256 hex
+CREATE TABLE email_verifications (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email VARCHAR (100) NOT NULL,
+    token_hash VARCHAR (64) UNIQUE NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX email_verifications_user_id_idx ON email_verifications (user_id);
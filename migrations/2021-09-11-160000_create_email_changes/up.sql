@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- pending email changes, the address only changes once the token sent to it is used
+CREATE TABLE email_changes (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    new_email VARCHAR (100) NOT NULL,
+    token_hash VARCHAR (64) UNIQUE NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX email_changes_user_id_idx ON email_changes (user_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sessions DROP COLUMN reauth_expires_at;
+ALTER TABLE sessions DROP COLUMN reauth_token_hash;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- step-up token from POST /auth/reauth, stored as sha256 hex
+ALTER TABLE sessions ADD COLUMN reauth_token_hash VARCHAR (64);
+ALTER TABLE sessions ADD COLUMN reauth_expires_at TIMESTAMP;
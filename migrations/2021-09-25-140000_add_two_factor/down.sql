@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE settings;
+DROP TABLE recovery_codes;
+ALTER TABLE sessions DROP COLUMN mfa;
+ALTER TABLE users DROP COLUMN totp_last_step;
+ALTER TABLE users DROP COLUMN totp_enabled_at;
+ALTER TABLE users DROP COLUMN totp_secret;
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+-- totp_secret is set on enrolment, 2fa is only on once totp_enabled_at is set
+ALTER TABLE users ADD COLUMN totp_secret VARCHAR (64);
+ALTER TABLE users ADD COLUMN totp_enabled_at TIMESTAMP;
+-- last time step a code was accepted for, codes can not be used twice
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- true if the login behind the session passed the second factor
+ALTER TABLE sessions ADD COLUMN mfa BOOLEAN NOT NULL DEFAULT false;
+
+-- one time recovery codes, stored as sha256 hex
+CREATE TABLE recovery_codes (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash VARCHAR (64) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    used_at TIMESTAMP
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
+
+-- switches admins can flip at runtime
+CREATE TABLE settings (
+    name VARCHAR (64) NOT NULL PRIMARY KEY,
+    value VARCHAR (255) NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
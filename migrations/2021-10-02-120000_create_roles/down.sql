@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users ADD COLUMN clearance BOOLEAN NOT NULL DEFAULT false;
+
+UPDATE users SET clearance = true
+WHERE id IN (
+    SELECT user_roles.user_id FROM user_roles
+    JOIN roles ON roles.id = user_roles.role_id
+    WHERE roles.name = 'admin'
+);
+
+DROP TABLE user_roles;
+DROP TABLE role_permissions;
+DROP TABLE permissions;
+DROP TABLE roles;
@@ -0,0 +1,46 @@
+-- Your SQL goes here
+CREATE TABLE roles (
+    id SERIAL NOT NULL PRIMARY KEY,
+    name VARCHAR (64) NOT NULL UNIQUE,
+    description VARCHAR NOT NULL DEFAULT ''
+);
+
+CREATE TABLE permissions (
+    id SERIAL NOT NULL PRIMARY KEY,
+    name VARCHAR (64) NOT NULL UNIQUE,
+    description VARCHAR NOT NULL DEFAULT ''
+);
+
+CREATE TABLE role_permissions (
+    role_id INTEGER NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    permission_id INTEGER NOT NULL REFERENCES permissions (id) ON DELETE CASCADE,
+    PRIMARY KEY (role_id, permission_id)
+);
+
+CREATE TABLE user_roles (
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role_id INTEGER NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, role_id)
+);
+
+CREATE INDEX user_roles_role_id_idx ON user_roles (role_id);
+
+INSERT INTO roles (name, description) VALUES
+    ('admin', 'full access to user management and settings');
+
+INSERT INTO permissions (name, description) VALUES
+    ('users:read', 'list all users'),
+    ('users:write', 'change other users accounts'),
+    ('roles:assign', 'give and take roles'),
+    ('settings:read', 'read runtime settings'),
+    ('settings:write', 'change runtime settings');
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id FROM roles, permissions WHERE roles.name = 'admin';
+
+-- clearance = true meant admin
+INSERT INTO user_roles (user_id, role_id)
+SELECT users.id, roles.id FROM users, roles WHERE users.clearance AND roles.name = 'admin';
+
+ALTER TABLE users DROP COLUMN clearance;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- no foreign keys, the history has to outlive the users it is about
+CREATE TABLE audit_events (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    event_type VARCHAR (64) NOT NULL,
+    actor_id BIGINT,
+    target_id BIGINT,
+    data JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_events_target_id_idx ON audit_events (target_id);
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM permissions WHERE name = 'audit:read';
+
+DROP INDEX audit_events_created_at_idx;
+DROP INDEX audit_events_event_type_idx;
+DROP INDEX audit_events_actor_id_idx;
+
+ALTER TABLE audit_events DROP COLUMN request_id;
+ALTER TABLE audit_events DROP COLUMN user_agent;
+ALTER TABLE audit_events DROP COLUMN ip;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE audit_events ADD COLUMN ip VARCHAR (64);
+ALTER TABLE audit_events ADD COLUMN user_agent VARCHAR (255);
+ALTER TABLE audit_events ADD COLUMN request_id VARCHAR (64);
+
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id);
+CREATE INDEX audit_events_event_type_idx ON audit_events (event_type);
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+
+INSERT INTO permissions (name, description) VALUES ('audit:read', 'read the audit log');
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id FROM roles, permissions
+WHERE roles.name = 'admin' AND permissions.name = 'audit:read';
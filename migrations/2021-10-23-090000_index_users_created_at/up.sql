@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- keyset pagination of GET /users by created_at
+CREATE INDEX users_created_at_id_idx ON users (created_at, id);
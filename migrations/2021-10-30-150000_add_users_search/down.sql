@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_search_tsv_idx;
+DROP INDEX users_search_trgm_idx;
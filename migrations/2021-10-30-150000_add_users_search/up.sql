@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- trigram matching for typos, levenshtein for ranking swapped letters
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE EXTENSION IF NOT EXISTS fuzzystrmatch;
+
+-- the expressions have to match the ones in dbmethods::search_users
+CREATE INDEX users_search_trgm_idx ON users
+    USING GIN (lower(name || ' ' || email) gin_trgm_ops);
+
+CREATE INDEX users_search_tsv_idx ON users
+    USING GIN (to_tsvector('simple', name || ' ' || regexp_replace(email, '[^[:alnum:]]+', ' ', 'g')));
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- the id everything outside the api sees, users.id stays internal.
+-- gen_random_uuid() is built in from postgres 13, before that it comes with pgcrypto
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+ALTER TABLE users ADD COLUMN public_id UUID NOT NULL DEFAULT gen_random_uuid();
+CREATE UNIQUE INDEX users_public_id_idx ON users (public_id);
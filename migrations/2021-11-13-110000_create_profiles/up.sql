@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- avatar and who gets to see which profile field, users without a row get the defaults
+CREATE TABLE profiles (
+    user_id BIGINT NOT NULL PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    avatar_url VARCHAR (2048),
+    email_visibility VARCHAR (16) NOT NULL DEFAULT 'self',
+    name_visibility VARCHAR (16) NOT NULL DEFAULT 'authenticated',
+    joined_visibility VARCHAR (16) NOT NULL DEFAULT 'authenticated',
+    avatar_visibility VARCHAR (16) NOT NULL DEFAULT 'authenticated',
+    updated_at TIMESTAMP NOT NULL DEFAULT now(),
+    CHECK (email_visibility IN ('public', 'authenticated', 'admins', 'self')),
+    CHECK (name_visibility IN ('public', 'authenticated', 'admins', 'self')),
+    CHECK (joined_visibility IN ('public', 'authenticated', 'admins', 'self')),
+    CHECK (avatar_visibility IN ('public', 'authenticated', 'admins', 'self'))
+);
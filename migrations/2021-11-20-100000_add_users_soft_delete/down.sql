@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+-- tombstones could share an email with a live account, they have to go before the constraint is back
+DELETE FROM users WHERE deleted_at IS NOT NULL;
+DROP INDEX users_purge_after_idx;
+DROP INDEX users_email_idx;
+DROP INDEX users_email_live_idx;
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
+ALTER TABLE users DROP COLUMN deleted_at, DROP COLUMN purge_after;
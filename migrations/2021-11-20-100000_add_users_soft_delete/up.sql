@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- deleted accounts stay as tombstones until purge_after, the owner can still cancel until then
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMP, ADD COLUMN purge_after TIMESTAMP;
+
+-- a tombstone keeps its email but does not block a new account from taking it
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+CREATE UNIQUE INDEX users_email_live_idx ON users (email) WHERE deleted_at IS NULL;
+CREATE INDEX users_email_idx ON users (email);
+
+CREATE INDEX users_purge_after_idx ON users (purge_after) WHERE deleted_at IS NOT NULL;
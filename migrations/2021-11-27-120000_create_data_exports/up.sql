@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- personal data exports too big to build during the request, made by the export job.
+-- the archive is dropped after the one download or once expires_at passes
+CREATE TABLE data_exports (
+    id UUID NOT NULL PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    format VARCHAR (8) NOT NULL,
+    status VARCHAR (16) NOT NULL DEFAULT 'pending',
+    token_hash VARCHAR (64) UNIQUE NOT NULL,
+    archive BYTEA,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    finished_at TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    downloaded_at TIMESTAMP,
+    CHECK (format IN ('json', 'zip')),
+    CHECK (status IN ('pending', 'running', 'ready', 'failed', 'downloaded', 'expired'))
+);
+
+CREATE INDEX data_exports_user_id_idx ON data_exports (user_id);
+CREATE INDEX data_exports_pending_idx ON data_exports (created_at) WHERE status = 'pending';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE oauth_tokens;
+DROP TABLE oauth_codes;
+DROP TABLE oauth_clients;
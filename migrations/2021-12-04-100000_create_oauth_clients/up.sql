@@ -0,0 +1,41 @@
+-- Your SQL goes here
+-- applications that log their users in through us with openid connect
+CREATE TABLE oauth_clients (
+    client_id VARCHAR (64) NOT NULL PRIMARY KEY,
+    name VARCHAR (100) NOT NULL,
+    -- null for public clients (spa, native apps), they can not keep a secret and only have pkce
+    secret_hash VARCHAR (64),
+    -- compared as is, no prefix or wildcard matching
+    redirect_uris TEXT [] NOT NULL,
+    created_by BIGINT REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- authorization codes, only the sha256 is kept and every code works once
+CREATE TABLE oauth_codes (
+    code_hash VARCHAR (64) NOT NULL PRIMARY KEY,
+    client_id VARCHAR (64) NOT NULL REFERENCES oauth_clients (client_id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    redirect_uri TEXT NOT NULL,
+    scope VARCHAR (255) NOT NULL,
+    nonce VARCHAR (255),
+    code_challenge VARCHAR (128) NOT NULL,
+    auth_time TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX oauth_codes_expires_at_idx ON oauth_codes (expires_at);
+
+-- access tokens for the userinfo endpoint, revoked with their code if the code is used twice
+CREATE TABLE oauth_tokens (
+    token_hash VARCHAR (64) NOT NULL PRIMARY KEY,
+    code_hash VARCHAR (64) NOT NULL REFERENCES oauth_codes (code_hash) ON DELETE CASCADE,
+    client_id VARCHAR (64) NOT NULL REFERENCES oauth_clients (client_id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    scope VARCHAR (255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX oauth_tokens_code_hash_idx ON oauth_tokens (code_hash);
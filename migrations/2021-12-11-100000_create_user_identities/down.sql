@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE provider_logins;
+DROP TABLE user_identities;
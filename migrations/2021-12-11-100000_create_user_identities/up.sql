@@ -0,0 +1,25 @@
+-- Your SQL goes here
+-- accounts at upstream openid connect providers, a user logs in with any of them or the password
+CREATE TABLE user_identities (
+    id BIGSERIAL NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- name of the provider in the settings
+    provider VARCHAR(64) NOT NULL,
+    -- the sub claim, only unique per provider
+    subject VARCHAR(255) NOT NULL,
+    -- what the provider said the email was at the last login
+    email VARCHAR(100),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    last_login_at TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (provider, subject),
+    UNIQUE (user_id, provider)
+);
+
+-- logins started at a provider and not back yet, the state is only stored as its sha256
+CREATE TABLE provider_logins (
+    state_hash VARCHAR(64) NOT NULL PRIMARY KEY,
+    provider VARCHAR(64) NOT NULL,
+    nonce VARCHAR(64) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- personal access tokens for scripts, sent like access tokens but checked here on every request
+CREATE TABLE api_tokens (
+    id UUID NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    -- the start of the token, to find it and to show which one it is
+    prefix VARCHAR(16) NOT NULL UNIQUE,
+    token_hash VARCHAR(64) NOT NULL,
+    -- permissions the token may use, as far as the user still has them
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    -- created from a login that passed the second factor
+    mfa BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    -- null never expires
+    expires_at TIMESTAMP,
+    last_used_at TIMESTAMP,
+    last_used_ip VARCHAR(64),
+    UNIQUE (user_id, name)
+);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- wrong passwords per user, for logins, step-up and everything else that asks for the password
+CREATE TABLE password_failures (
+    user_id BIGINT NOT NULL PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    failures INTEGER NOT NULL DEFAULT 0,
+    -- failures only count for a while after the first one
+    window_started_at TIMESTAMP NOT NULL DEFAULT now()
+);
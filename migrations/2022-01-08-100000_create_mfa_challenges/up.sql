@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- every mfa token belongs to one of these, it counts the wrong codes tried with it.
+-- it ran as 2021-12-25-100000 before, databases that have it already keep theirs
+CREATE TABLE IF NOT EXISTS mfa_challenges (
+    id UUID NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    failures INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL,
+    -- set once a code passed, the token does not work again
+    used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS mfa_challenges_user_id_created_at_idx ON mfa_challenges (user_id, created_at);
@@ -0,0 +1,125 @@
+//security relevant events. models::dbmethods records them next to the change they
+//describe, inside the same transaction where there is one, so handlers never have to
+//write to the audit log themselves
+use actix_web::web;
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{
+    db::db::Pool,
+    errors::ServiceError,
+    models::audit::{
+        AuditContext, AuditEntry, AuditEvent, AuditEventInsert, AuditEventType, AuditPage,
+        AuditQuery,
+    },
+};
+
+const DEFAULT_PAGE: i64 = 50;
+const MAX_PAGE: i64 = 200;
+
+pub struct AuditLog;
+
+impl AuditLog {
+    pub fn record(
+        conn: &PgConnection,
+        ctx: &AuditContext,
+        event: AuditEventType,
+        target: Option<i64>,
+        data: serde_json::Value,
+    ) -> Result<(), ServiceError> {
+        use crate::schema::audit_events::dsl::audit_events;
+        diesel::insert_into(audit_events)
+            .values(&AuditEventInsert {
+                event_type: event.as_str().to_owned(),
+                actor_id: ctx.actor_id,
+                target_id: target,
+                data,
+                ip: ctx.ip.clone(),
+                user_agent: ctx.user_agent.clone(),
+                request_id: ctx.request_id.clone(),
+            })
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn search(query: AuditQuery, pool: web::Data<Pool>) -> Result<AuditPage, ServiceError> {
+        use crate::schema::audit_events::dsl::*;
+        let conn = &pool.get().unwrap();
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+        let mut items = audit_events.into_boxed();
+        //a public id nobody has matches no events
+        if let Some(actor) = query.actor {
+            items = items.filter(actor_id.eq(internal_id(actor, conn)?));
+        }
+        if let Some(target) = query.target {
+            items = items.filter(target_id.eq(internal_id(target, conn)?));
+        }
+        if let Some(event) = query.event {
+            items = items.filter(event_type.eq(event.as_str()));
+        }
+        if let Some(from) = query.from {
+            items = items.filter(created_at.ge(from));
+        }
+        if let Some(to) = query.to {
+            items = items.filter(created_at.lt(to));
+        }
+        if let Some(cursor) = query.cursor {
+            items = items.filter(id.lt(cursor));
+        }
+        //one extra row tells if there is another page
+        let mut events = items
+            .order(id.desc())
+            .limit(limit + 1)
+            .load::<AuditEvent>(conn)?;
+        let next_cursor = if events.len() as i64 > limit {
+            events.truncate(limit as usize);
+            events.last().map(|event| event.id)
+        } else {
+            None
+        };
+        let users = events
+            .iter()
+            .flat_map(|event| vec![event.actor_id, event.target_id])
+            .flatten()
+            .collect::<Vec<_>>();
+        let public_ids = {
+            use crate::schema::users::dsl::{id, public_id, users as users_table};
+            users_table
+                .filter(id.eq_any(users))
+                .select((id, public_id))
+                .load::<(i64, Uuid)>(conn)?
+                .into_iter()
+                .collect::<HashMap<_, _>>()
+        };
+        let public = |user: Option<i64>| user.and_then(|user| public_ids.get(&user).copied());
+        Ok(AuditPage {
+            events: events
+                .into_iter()
+                .map(|event| AuditEntry {
+                    id: event.id,
+                    event_type: event.event_type,
+                    actor_id: public(event.actor_id),
+                    target_id: public(event.target_id),
+                    data: event.data,
+                    created_at: event.created_at,
+                    ip: event.ip,
+                    user_agent: event.user_agent,
+                    request_id: event.request_id,
+                })
+                .collect(),
+            next_cursor,
+        })
+    }
+}
+
+//the sequential id behind a public one, -1 (no user) for an unknown one
+fn internal_id(user: Uuid, conn: &PgConnection) -> Result<i64, ServiceError> {
+    use crate::schema::users::dsl::{id, public_id, users};
+    Ok(users
+        .filter(public_id.eq(user))
+        .select(id)
+        .first::<i64>(conn)
+        .optional()?
+        .unwrap_or(-1))
+}
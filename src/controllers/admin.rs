@@ -0,0 +1,83 @@
+use actix_web::{web, HttpResponse};
+
+use crate::{
+    audit::AuditLog,
+    db::db::Pool,
+    errors::ServiceError,
+    models::{
+        audit::{AuditContext, AuditQuery},
+        dbmethods,
+        oauth::{ClientData, ClientView},
+        two_factor::TwoFactorPolicy,
+    },
+    utils::KEYS,
+};
+
+//route handles
+//GET /admin/2fa-policy
+pub async fn get_2fa_policy(pool: web::Data<Pool>) -> Result<HttpResponse, ServiceError> {
+    let require_for_admins = dbmethods::admin_2fa_required(pool)?;
+    Ok(HttpResponse::Ok().json(TwoFactorPolicy { require_for_admins }))
+}
+
+//PUT /admin/2fa-policy
+//admins without 2fa keep their tokens but routes behind RequirePermission refuse them
+pub async fn set_2fa_policy(
+    policy: web::Json<TwoFactorPolicy>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    dbmethods::set_setting(
+        dbmethods::REQUIRE_ADMIN_2FA,
+        &policy.require_for_admins.to_string(),
+        &audit,
+        pool,
+    )?;
+    Ok(HttpResponse::Ok().json(policy.into_inner()))
+}
+
+//GET /admin/clients
+pub async fn get_clients(pool: web::Data<Pool>) -> Result<HttpResponse, ServiceError> {
+    let clients: Vec<ClientView> = dbmethods::list_clients(pool)?
+        .into_iter()
+        .map(ClientView::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "clients": clients })))
+}
+
+//POST /admin/clients
+//the client_secret is only in this answer
+pub async fn post_client(
+    client: web::Json<ClientData>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    if !KEYS.signs_with_public_key() {
+        return Err(ServiceError::BadRequest(
+            "openid connect needs an asymmetric jwt.signing_key".to_owned(),
+        ));
+    }
+    let (client, secret) = dbmethods::create_client(client.into_inner(), &audit, pool)?;
+    let mut body = serde_json::to_value(ClientView::from(client)).unwrap_or_default();
+    body["client_secret"] = serde_json::json!(secret);
+    Ok(HttpResponse::Created().json(body))
+}
+
+//DELETE /admin/clients/{client_id}
+pub async fn delete_client(
+    client_id: web::Path<String>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    dbmethods::delete_client(&client_id, &audit, pool)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "msg": "client deleted" })))
+}
+
+//GET /admin/audit
+pub async fn get_audit_events(
+    query: web::Query<AuditQuery>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let page = AuditLog::search(query.into_inner(), pool)?;
+    Ok(HttpResponse::Ok().json(page))
+}
@@ -1,25 +1,205 @@
 use actix_web::http::header;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 
 use crate::db::db::Pool;
 use crate::errors::ServiceError;
-use crate::models::{dbmethods::login_user, user::AuthData};
+use crate::extractors::{AuthenticatedUser, RestrictedUser};
+use crate::mailer::{outbox, templates, Mailer};
+use crate::models::{
+    audit::AuditContext,
+    dbmethods::{self, login_user},
+    password_reset::{ResetConfirm, ResetRequest},
+    session::{ClientInfo, ReauthData, RefreshData, Session, TokenPair},
+    two_factor::MfaLogin,
+    user::{AuthData, FindBy, RestoreData, User},
+};
 use crate::utils;
 
 //route handles
 //DELETE /auth
-pub async fn logout() -> HttpResponse {
-    HttpResponse::Ok()
+pub async fn logout(
+    pool: web::Data<Pool>,
+    RestrictedUser(auth): RestrictedUser,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    dbmethods::delete_session(auth.token_id, &audit, pool)?;
+    Ok(HttpResponse::Ok()
         .set_header(header::AUTHORIZATION, "")
-        .finish()
+        .finish())
 }
 
 //POST /auth
+//users with 2fa get a challenge token for POST /auth/2fa instead of tokens
 pub async fn login(
     user_data: web::Json<AuthData>,
     pool: web::Data<Pool>,
+    req: HttpRequest,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let user = login_user(user_data.into_inner(), &audit, pool.clone())?;
+    finish_login(&user, &req, pool)
+}
+
+//what a checked first factor gets: the 2fa challenge, the restore challenge or tokens
+pub fn finish_login(
+    user: &User,
+    req: &HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    if user.totp_enabled_at.is_some() {
+        let challenge = dbmethods::start_mfa_challenge(user.id, pool)?;
+        let (mfa_token, expires_at) = utils::create_mfa_token(user, challenge)?;
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "mfa_required": true,
+            "mfa_token": mfa_token,
+            "expires_at": expires_at,
+        })));
+    }
+    if user.deleted_at.is_some() {
+        return restore_challenge(user);
+    }
+    let session =
+        dbmethods::create_session(user.id, ClientInfo::from_request(req), false, pool.clone())?;
+    let tokens = issue_tokens(user, &session, pool)?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+//POST /auth/2fa
+pub async fn login_2fa(
+    mfa_data: web::Json<MfaLogin>,
+    pool: web::Data<Pool>,
+    req: HttpRequest,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let claims = utils::decode_mfa_token(&mfa_data.mfa_token)?;
+    let user = dbmethods::verify_second_factor(
+        claims.jti.ok_or(ServiceError::Unauthorized)?,
+        claims.sub,
+        claims.ver,
+        &mfa_data.code,
+        &audit,
+        pool.clone(),
+    )?;
+    if user.deleted_at.is_some() {
+        return restore_challenge(&user);
+    }
+    let session =
+        dbmethods::create_session(user.id, ClientInfo::from_request(&req), true, pool.clone())?;
+    let tokens = issue_tokens(&user, &session, pool)?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+//POST /auth/restore
+//cancels the deletion of the account the restore token is for and logs in
+pub async fn restore(
+    restore_data: web::Json<RestoreData>,
+    pool: web::Data<Pool>,
+    req: HttpRequest,
+    audit: AuditContext,
 ) -> Result<HttpResponse, ServiceError> {
-    let user = login_user(user_data.into_inner(), pool)?;
-    let token = utils::create_jwt(user)?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "token": token })))
+    let claims = utils::decode_restore_token(&restore_data.restore_token)?;
+    let user = dbmethods::restore_account(
+        claims.sub,
+        Some(claims.ver),
+        &audit.as_actor(claims.sub),
+        pool.clone(),
+    )?;
+    //with 2fa on the restore token is only handed out after the second factor
+    let mfa = user.totp_enabled_at.is_some();
+    let session =
+        dbmethods::create_session(user.id, ClientInfo::from_request(&req), mfa, pool.clone())?;
+    let tokens = issue_tokens(&user, &session, pool)?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+//a deleted account only gets a token to cancel the deletion with
+fn restore_challenge(user: &User) -> Result<HttpResponse, ServiceError> {
+    let (restore_token, expires_at) = utils::create_restore_token(user)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "deletion_pending": true,
+        "purge_after": user.purge_after,
+        "restore_token": restore_token,
+        "expires_at": expires_at,
+    })))
+}
+
+//POST /auth/refresh
+pub async fn refresh(
+    refresh_data: web::Json<RefreshData>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let session =
+        dbmethods::redeem_refresh_token(&refresh_data.refresh_token, &audit, pool.clone())?;
+    let user = dbmethods::find_by(FindBy::Id(session.user_id), pool.clone())?;
+    let tokens = issue_tokens(&user, &session, pool)?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+//POST /auth/reauth
+//step-up token for sensitive changes like PATCH /user with a new email or password
+pub async fn reauth(
+    reauth_data: web::Json<ReauthData>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    let (token, expires_at) =
+        dbmethods::reauthenticate(auth.id, auth.token_id, &reauth_data.password, &audit, pool)?;
+    Ok(HttpResponse::Ok()
+        .json(serde_json::json!({ "reauth_token": token, "expires_at": expires_at })))
+}
+
+//POST /auth/password-reset
+//always accepted so nobody can find out which emails have accounts
+pub async fn request_password_reset(
+    reset_data: web::Json<ResetRequest>,
+    pool: web::Data<Pool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let locale = templates::preferred_locale(&req);
+    if dbmethods::create_password_reset(&reset_data.email, &locale, pool.clone())? {
+        outbox::send_soon(mailer, pool);
+    }
+    Ok(HttpResponse::Accepted().json(
+        serde_json::json!({ "msg": "if the email belongs to an account a reset link was sent" }),
+    ))
+}
+
+//POST /auth/password-reset/confirm
+pub async fn confirm_password_reset(
+    confirm_data: web::Json<ResetConfirm>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let confirm_data = confirm_data.into_inner();
+    dbmethods::reset_password(&confirm_data.token, &confirm_data.password, &audit, pool)?;
+    Ok(HttpResponse::Ok()
+        .json(serde_json::json!({ "msg": "password changed, please login again" })))
+}
+
+pub fn issue_tokens(
+    user: &User,
+    session: &Session,
+    pool: web::Data<Pool>,
+) -> Result<TokenPair, ServiceError> {
+    let access = dbmethods::load_access(user.id, pool.clone())?;
+    let (token, expires_at) = utils::create_jwt(user, session, &access)?;
+    let (refresh_token, stored) = dbmethods::issue_refresh_token(session, pool)?;
+    Ok(TokenPair {
+        token,
+        expires_at,
+        refresh_token,
+        refresh_expires_at: stored.expires_at,
+    })
+}
+
+//GET /.well-known/jwks.json
+pub async fn jwks() -> HttpResponse {
+    HttpResponse::Ok()
+        .set_header(header::CACHE_CONTROL, "public, max-age=300")
+        .json(utils::KEYS.jwks())
 }
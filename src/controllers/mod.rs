@@ -1,3 +1,6 @@
+pub mod admin;
 pub mod auth;
+pub mod oidc;
+pub mod providers;
 pub mod user;
 pub mod users;
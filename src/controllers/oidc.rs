@@ -0,0 +1,437 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::db::db::Pool;
+use crate::errors::{OAuthError, ServiceError};
+use crate::mailer::templates::{escape_html, fill};
+use crate::models::{
+    audit::AuditContext,
+    dbmethods,
+    oauth::{
+        granted_scope, has_scope, AuthorizeForm, AuthorizeRequest, Grant, OAuthClient,
+        TokenRequest, TokenResponse, UserClaims, SCOPES,
+    },
+    user::AuthData,
+};
+use crate::utils::{self, hash_token, ACCESS_TOKEN_MINUTES, KEYS};
+
+const AUTHORIZE_PAGE: &str = include_str!("../../templates/oidc/authorize.html");
+const ERROR_PAGE: &str = include_str!("../../templates/oidc/error.html");
+
+//an authorization request that passed every check
+struct Checked {
+    client: OAuthClient,
+    redirect_uri: String,
+    scope: String,
+    code_challenge: String,
+}
+
+//route handles
+//GET /.well-known/openid-configuration
+pub async fn discovery() -> HttpResponse {
+    let issuer = utils::issuer();
+    HttpResponse::Ok()
+        .set_header(header::CACHE_CONTROL, "public, max-age=300")
+        .json(serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/oauth/authorize", issuer),
+            "token_endpoint": format!("{}/oauth/token", issuer),
+            "userinfo_endpoint": format!("{}/oauth/userinfo", issuer),
+            "jwks_uri": format!("{}/.well-known/jwks.json", issuer),
+            "response_types_supported": ["code"],
+            "response_modes_supported": ["query"],
+            "grant_types_supported": ["authorization_code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": [KEYS.algorithm()],
+            "scopes_supported": SCOPES,
+            "claims_supported": [
+                "sub", "iss", "aud", "exp", "iat", "auth_time", "nonce",
+                "name", "email", "email_verified",
+            ],
+            "token_endpoint_auth_methods_supported": [
+                "client_secret_basic", "client_secret_post", "none",
+            ],
+            "code_challenge_methods_supported": ["S256"],
+            "authorization_response_iss_parameter_supported": true,
+        }))
+}
+
+//GET /oauth/authorize
+//we keep no login between requests, so the page always asks for the password
+pub async fn authorize(
+    request: web::Query<AuthorizeRequest>,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let request = request.into_inner();
+    match check_request(&request, pool) {
+        Ok(checked) => consent_page(StatusCode::OK, &checked, &request, "", ""),
+        Err(response) => response,
+    }
+}
+
+//POST /oauth/authorize
+//the login and consent form, sends the user back to the client with a code
+pub async fn authorize_submit(
+    form: web::Form<AuthorizeForm>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> HttpResponse {
+    let AuthorizeForm {
+        request,
+        email,
+        password,
+        code,
+        decision,
+    } = form.into_inner();
+    let checked = match check_request(&request, pool.clone()) {
+        Ok(checked) => checked,
+        Err(response) => return response,
+    };
+    let state = request.state.as_deref();
+    if decision.as_deref() != Some("allow") {
+        return error_redirect(
+            &checked.redirect_uri,
+            state,
+            "access_denied",
+            "login declined",
+        );
+    }
+    let login = AuthData {
+        email: email.unwrap_or_default(),
+        password: password.unwrap_or_default(),
+    };
+    let email = login.email.clone();
+    let retry = |status, message: &str| consent_page(status, &checked, &request, &email, message);
+    let user = match dbmethods::login_user(login, &audit, pool.clone()) {
+        Ok(user) => user,
+        Err(ServiceError::Unauthorized) => {
+            return retry(StatusCode::UNAUTHORIZED, "wrong email or password")
+        }
+        Err(ServiceError::Forbidden(msg)) => return retry(StatusCode::FORBIDDEN, &msg),
+        Err(ServiceError::TooManyRequests(msg)) => {
+            return retry(StatusCode::TOO_MANY_REQUESTS, &msg)
+        }
+        Err(_) => return error_page(StatusCode::INTERNAL_SERVER_ERROR, "something went wrong"),
+    };
+    if user.deleted_at.is_some() {
+        return retry(
+            StatusCode::FORBIDDEN,
+            "this account is being deleted, log in to the app to restore it",
+        );
+    }
+    //the email goes out in the id token, so it has to be confirmed
+    if user.email_verified_at.is_none() {
+        return retry(StatusCode::FORBIDDEN, "please verify your email first");
+    }
+    if user.totp_enabled_at.is_some() {
+        let code = code.unwrap_or_default();
+        if code.trim().is_empty() {
+            return retry(
+                StatusCode::UNAUTHORIZED,
+                "enter the code from your authenticator app",
+            );
+        }
+        //every form post is a challenge of its own, the per user lock still holds
+        let verified =
+            dbmethods::start_mfa_challenge(user.id, pool.clone()).and_then(|challenge| {
+                dbmethods::verify_second_factor(
+                    challenge,
+                    user.id,
+                    user.token_version,
+                    &code,
+                    &audit,
+                    pool.clone(),
+                )
+            });
+        match verified {
+            Ok(_) => {}
+            Err(ServiceError::TooManyRequests(msg)) => {
+                return retry(StatusCode::TOO_MANY_REQUESTS, &msg)
+            }
+            Err(_) => return retry(StatusCode::UNAUTHORIZED, "wrong 2fa code"),
+        }
+    }
+    let grant = Grant {
+        client_id: checked.client.client_id.clone(),
+        user_id: user.id,
+        redirect_uri: checked.redirect_uri.clone(),
+        scope: checked.scope.clone(),
+        nonce: request.nonce.clone().filter(|n| !n.is_empty()),
+        code_challenge: checked.code_challenge.clone(),
+    };
+    match dbmethods::issue_authorization_code(grant, &audit, pool) {
+        Ok(code) => {
+            let issuer = utils::issuer();
+            let mut params = vec![("code", code.as_str()), ("iss", issuer.as_str())];
+            params.extend(state.map(|s| ("state", s)));
+            redirect(&checked.redirect_uri, &params)
+        }
+        Err(_) => error_redirect(&checked.redirect_uri, state, "server_error", "try again"),
+    }
+}
+
+//POST /oauth/token
+pub async fn token(
+    form: web::Form<TokenRequest>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, OAuthError> {
+    let form = form.into_inner();
+    let (client_id, secret) = client_credentials(&req, &form)?;
+    let client = dbmethods::find_client(&client_id, pool.clone())
+        .map_err(|_| OAuthError::new("invalid_client", "unknown client"))?;
+    match (&client.secret_hash, secret) {
+        (Some(stored), Some(secret)) if *stored == hash_token(&secret) => {}
+        (None, None) => {}
+        _ => {
+            return Err(OAuthError::new(
+                "invalid_client",
+                "client authentication failed",
+            ))
+        }
+    }
+    if form.grant_type.as_deref() != Some("authorization_code") {
+        return Err(OAuthError::new(
+            "unsupported_grant_type",
+            "only authorization_code is supported",
+        ));
+    }
+    let missing =
+        |field: &str| OAuthError::new("invalid_request", &format!("{} is missing", field));
+    let code = form.code.ok_or_else(|| missing("code"))?;
+    let redirect_uri = form.redirect_uri.ok_or_else(|| missing("redirect_uri"))?;
+    let code_verifier = form.code_verifier.ok_or_else(|| missing("code_verifier"))?;
+    let (code, user, access_token) = dbmethods::redeem_authorization_code(
+        &code,
+        &client.client_id,
+        &redirect_uri,
+        &code_verifier,
+        &audit,
+        pool,
+    )?
+    .ok_or_else(|| {
+        OAuthError::new(
+            "invalid_grant",
+            "the code is invalid, expired or was already used",
+        )
+    })?;
+    let id_token = utils::create_id_token(&user, &code)?;
+    Ok(HttpResponse::Ok()
+        .set_header(header::CACHE_CONTROL, "no-store")
+        .set_header(header::PRAGMA, "no-cache")
+        .json(TokenResponse {
+            access_token,
+            token_type: "Bearer".to_owned(),
+            expires_in: *ACCESS_TOKEN_MINUTES * 60,
+            id_token,
+            scope: code.scope,
+        }))
+}
+
+//GET and POST /oauth/userinfo
+pub async fn userinfo(req: HttpRequest, pool: web::Data<Pool>) -> Result<HttpResponse, OAuthError> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.strip_prefix("Bearer ")
+                .or_else(|| v.strip_prefix("bearer "))
+        })
+        .ok_or_else(|| OAuthError::new("invalid_token", "the access token is missing"))?;
+    let (user, scope) = dbmethods::userinfo(token.trim(), pool).map_err(|err| match err {
+        ServiceError::Unauthorized => {
+            OAuthError::new("invalid_token", "the access token is invalid or expired")
+        }
+        other => other.into(),
+    })?;
+    Ok(HttpResponse::Ok()
+        .set_header(header::CACHE_CONTROL, "no-store")
+        .json(UserClaims::new(&user, &scope)))
+}
+
+//problems with the client or redirect_uri are shown on an error page, sending the user on to an
+//unchecked redirect_uri would make us an open redirect. everything else goes back to the client
+fn check_request(
+    request: &AuthorizeRequest,
+    pool: web::Data<Pool>,
+) -> Result<Checked, HttpResponse> {
+    let client = request
+        .client_id
+        .as_deref()
+        .and_then(|id| dbmethods::find_client(id, pool).ok())
+        .ok_or_else(|| error_page(StatusCode::BAD_REQUEST, "the app is not registered here"))?;
+    let redirect_uri = request
+        .redirect_uri
+        .clone()
+        .filter(|uri| client.redirect_uris.contains(uri))
+        .ok_or_else(|| {
+            error_page(
+                StatusCode::BAD_REQUEST,
+                "the app asked to send you to an address it did not register",
+            )
+        })?;
+    let state = request.state.as_deref();
+    let reject = |error, description| Err(error_redirect(&redirect_uri, state, error, description));
+    if request.response_type.as_deref() != Some("code") {
+        return reject(
+            "unsupported_response_type",
+            "only the code response type is supported",
+        );
+    }
+    let scope = match request.scope.as_deref().and_then(granted_scope) {
+        Some(scope) => scope,
+        None => return reject("invalid_scope", "the openid scope is required"),
+    };
+    let code_challenge = match request.code_challenge.clone() {
+        Some(challenge)
+            if request.code_challenge_method.as_deref() == Some("S256")
+                && (43..=128).contains(&challenge.len()) =>
+        {
+            challenge
+        }
+        _ => return reject("invalid_request", "pkce with S256 is required"),
+    };
+    if request
+        .prompt
+        .as_deref()
+        .is_some_and(|prompt| prompt.split_whitespace().any(|p| p == "none"))
+    {
+        return reject("login_required", "the user has to log in");
+    }
+    Ok(Checked {
+        client,
+        redirect_uri,
+        scope,
+        code_challenge,
+    })
+}
+
+fn consent_page(
+    status: StatusCode,
+    checked: &Checked,
+    request: &AuthorizeRequest,
+    email: &str,
+    message: &str,
+) -> HttpResponse {
+    let mut wanted = vec!["your account id"];
+    if has_scope(&checked.scope, "profile") {
+        wanted.push("your name");
+    }
+    if has_scope(&checked.scope, "email") {
+        wanted.push("your email address");
+    }
+    let scopes = match wanted.split_last() {
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => String::new(),
+    };
+    let field = |value: &Option<String>| value.clone().unwrap_or_default();
+    let page = fill(
+        AUTHORIZE_PAGE,
+        &[
+            ("client", &checked.client.name),
+            ("scopes", &scopes),
+            ("message", message),
+            ("email", email),
+            ("response_type", "code"),
+            ("client_id", &checked.client.client_id),
+            ("redirect_uri", &checked.redirect_uri),
+            ("scope", &checked.scope),
+            ("state", &field(&request.state)),
+            ("nonce", &field(&request.nonce)),
+            ("code_challenge", &checked.code_challenge),
+            ("code_challenge_method", "S256"),
+        ],
+        escape_html,
+    );
+    html(status, page)
+}
+
+fn error_page(status: StatusCode, message: &str) -> HttpResponse {
+    html(
+        status,
+        fill(ERROR_PAGE, &[("message", message)], escape_html),
+    )
+}
+
+//the pages must not be framed by other sites, that would let them click allow for the user
+fn html(status: StatusCode, page: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .set_header(header::CACHE_CONTROL, "no-store")
+        .set_header(header::X_FRAME_OPTIONS, "DENY")
+        .set_header(
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; style-src 'unsafe-inline'; frame-ancestors 'none'",
+        )
+        .body(page)
+}
+
+fn error_redirect(
+    redirect_uri: &str,
+    state: Option<&str>,
+    error: &str,
+    description: &str,
+) -> HttpResponse {
+    let issuer = utils::issuer();
+    let mut params = vec![
+        ("error", error),
+        ("error_description", description),
+        ("iss", issuer.as_str()),
+    ];
+    params.extend(state.map(|s| ("state", s)));
+    redirect(redirect_uri, &params)
+}
+
+fn redirect(uri: &str, params: &[(&str, &str)]) -> HttpResponse {
+    let query = params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    HttpResponse::Found()
+        .set_header(header::LOCATION, format!("{}{}{}", uri, separator, query))
+        .finish()
+}
+
+//client_secret_basic, or client_secret_post and none with the id in the body
+fn client_credentials(
+    req: &HttpRequest,
+    form: &TokenRequest,
+) -> Result<(String, Option<String>), OAuthError> {
+    let basic = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "));
+    if let Some(encoded) = basic {
+        let invalid = || OAuthError::new("invalid_client", "malformed basic authorization");
+        let decoded = STANDARD.decode(encoded.trim()).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (id, secret) = decoded.split_once(':').ok_or_else(invalid)?;
+        let id = urlencoding::decode(id).map_err(|_| invalid())?.into_owned();
+        let secret = urlencoding::decode(secret)
+            .map_err(|_| invalid())?
+            .into_owned();
+        if form
+            .client_id
+            .as_ref()
+            .is_some_and(|form_id| *form_id != id)
+        {
+            return Err(OAuthError::new(
+                "invalid_request",
+                "client_id does not match the authorization header",
+            ));
+        }
+        return Ok((id, Some(secret)));
+    }
+    let id = form
+        .client_id
+        .clone()
+        .ok_or_else(|| OAuthError::new("invalid_client", "client_id is missing"))?;
+    Ok((id, form.client_secret.clone()))
+}
@@ -0,0 +1,111 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::controllers::auth::finish_login;
+use crate::db::db::Pool;
+use crate::errors::ServiceError;
+use crate::mailer::{outbox, templates, Mailer};
+use crate::models::{
+    audit::AuditContext,
+    dbmethods,
+    identity::{IdentityLogin, LinkData, ProviderCallback},
+};
+use crate::providers::Providers;
+use crate::utils;
+
+//route handles
+//GET /auth/providers
+pub async fn get_providers(providers: web::Data<Providers>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "providers": providers.list() }))
+}
+
+//POST /auth/providers/{name}
+//the app sends the browser to authorization_url and keeps the state to check it when it comes back
+pub async fn start_login(
+    name: web::Path<String>,
+    providers: web::Data<Providers>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let provider = providers.get(&name)?;
+    let (state, login) = dbmethods::start_provider_login(&provider.settings.name, pool)?;
+    let url = provider
+        .authorization_url(
+            &state,
+            &login.nonce,
+            &utils::pkce_challenge(&login.code_verifier),
+        )
+        .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "authorization_url": url,
+        "state": state,
+        "expires_at": login.expires_at,
+    })))
+}
+
+//POST /auth/providers/{name}/callback
+//answers like POST /auth, or with a link token when the email already has an account
+pub async fn callback(
+    name: web::Path<String>,
+    callback_data: web::Json<ProviderCallback>,
+    providers: web::Data<Providers>,
+    pool: web::Data<Pool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let provider = providers.get(&name)?;
+    let login = dbmethods::take_provider_login(
+        &provider.settings.name,
+        &callback_data.state,
+        pool.clone(),
+    )?;
+    let identity = provider
+        .exchange(&callback_data.code, &login.code_verifier, &login.nonce)
+        .await?;
+    let locale = templates::preferred_locale(&req);
+    match dbmethods::login_with_identity(
+        &provider.settings,
+        identity,
+        &locale,
+        &audit,
+        pool.clone(),
+    )? {
+        IdentityLogin::Linked(user) => finish_login(&user, &req, pool),
+        IdentityLogin::Created(user) => {
+            if user.email_verified_at.is_none() {
+                outbox::send_soon(mailer, pool.clone());
+            }
+            finish_login(&user, &req, pool)
+        }
+        IdentityLogin::LinkRequired(user, identity) => {
+            let (link_token, expires_at) = utils::create_link_token(
+                &user,
+                &provider.settings.name,
+                &identity.subject,
+                identity.email.as_deref(),
+            )?;
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "link_required": true,
+                "email": user.email,
+                "link_token": link_token,
+                "expires_at": expires_at,
+            })))
+        }
+    }
+}
+
+//POST /auth/providers/{name}/link
+//links the provider login to the account its email belongs to and logs in
+pub async fn link(
+    name: web::Path<String>,
+    link_data: web::Json<LinkData>,
+    pool: web::Data<Pool>,
+    req: HttpRequest,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let claims = utils::decode_link_token(&link_data.link_token)?;
+    if claims.provider != *name {
+        return Err(ServiceError::Unauthorized);
+    }
+    let user = dbmethods::link_identity(claims, &link_data.password, &audit, pool.clone())?;
+    finish_login(&user, &req, pool)
+}
@@ -1,67 +1,293 @@
-use actix_web::HttpRequest;
-use actix_web::{web, HttpResponse};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
 
+use super::auth::issue_tokens;
 use crate::{
     db::db::Pool,
     errors::ServiceError,
+    export,
+    extractors::{AuthenticatedUser, OptionalUser, RestrictedUser},
+    jobs,
+    mailer::{outbox, templates, Mailer},
     models::{
+        api_token::ApiTokenData,
+        audit::AuditContext,
         dbmethods,
-        user::{FindBy, SlimUser, UserChange},
+        export::{DownloadQuery, ExportFormat, ExportQuery, ExportView},
+        profile::{FullProfile, ProfileChange, ProfileView, Relation},
+        session::ClientInfo,
+        two_factor::TotpCode,
+        user::{FindBy, UserChangeRequest, VerifyData},
     },
-    utils::parse_request,
+    totp,
+    utils::{APP_URL, TOTP_ISSUER},
 };
 
 //route handlers
 //GET /user
-pub async fn get_me(pool: web::Data<Pool>, req: HttpRequest) -> Result<HttpResponse, ServiceError> {
-    let (email, _) = parse_request(req);
-    let user = dbmethods::find_by(FindBy::Email(email), pool)?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": user.id, "email": user.email, "joined": user.created_at.date() , "name": user.name, "admin": user.clearance })))
+pub async fn get_me(
+    pool: web::Data<Pool>,
+    RestrictedUser(auth): RestrictedUser,
+) -> Result<HttpResponse, ServiceError> {
+    let user = dbmethods::find_by(FindBy::Id(auth.id), pool)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": user.public_id, "email": user.email, "joined": user.created_at.date() , "name": user.name, "roles": auth.access.roles, "email_verified": user.email_verified_at.is_some() })))
 }
 
 //GET /user/{id}
+//works without a token too, each field is only there if its owner lets the viewer see it
 pub async fn get_user_by_id(
     id: web::Path<String>,
     pool: web::Data<Pool>,
+    OptionalUser(viewer): OptionalUser,
+) -> Result<HttpResponse, ServiceError> {
+    let id = match uuid::Uuid::parse_str(&id.into_inner()) {
+        Ok(v) => v,
+        Err(_) => return Err(ServiceError::BadRequest("invalid id".to_owned())),
+    };
+    let user = dbmethods::find_by(FindBy::PublicId(id), pool.clone())?;
+    let settings = dbmethods::profile_settings(user.id, pool.clone())?;
+    let viewer = Relation::of(viewer.as_ref(), user.id);
+    match viewer {
+        Relation::Admin => {
+            let access = dbmethods::load_access(user.id, pool)?;
+            Ok(HttpResponse::Ok().json(FullProfile::new(user, settings, access.roles)))
+        }
+        viewer => Ok(HttpResponse::Ok().json(ProfileView::project(user, settings, viewer))),
+    }
+}
+
+//GET /user/export
+//small exports come back right away, bigger ones are made by the export job and
+//fetched once from the download_url in the answer
+pub async fn export_data(
+    query: web::Query<ExportQuery>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let (user_id, format) = (auth.id, query.format);
+    let block_pool = pool.clone();
+    let archive =
+        web::block(move || dbmethods::export_now(user_id, format, &audit, block_pool)).await?;
+    if let Some(archive) = archive {
+        return Ok(download(format, archive));
+    }
+    let (export, token) = dbmethods::start_export(user_id, format, pool.clone())?;
+    jobs::export_soon(pool);
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "id": export.id,
+        "status": export.status,
+        "status_url": format!("{}/user/export/{}", *APP_URL, export.id),
+        "download_url": format!("{}/user/export/download?token={}", *APP_URL, token),
+        "expires_at": export.expires_at,
+    })))
+}
+
+//GET /user/export/{id}
+pub async fn export_status(
+    id: web::Path<String>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
 ) -> Result<HttpResponse, ServiceError> {
-    let id = match id.into_inner().parse::<i64>() {
+    let id = match uuid::Uuid::parse_str(&id.into_inner()) {
         Ok(v) => v,
         Err(_) => return Err(ServiceError::BadRequest("invalid id".to_owned())),
     };
-    let user = dbmethods::find_by(FindBy::Id(id), pool)?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": user.id, "email": user.email })))
+    let export = dbmethods::export_status(auth.id, id, pool)?;
+    Ok(HttpResponse::Ok().json(ExportView::from(export)))
+}
+
+//GET /user/export/download
+//works without a token, the link itself is the secret and only works once
+pub async fn download_export(
+    query: web::Query<DownloadQuery>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let (format, archive) = dbmethods::take_export(&query.token, &audit, pool)?;
+    Ok(download(format, archive))
+}
+
+fn download(format: ExportFormat, archive: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .set_header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", export::file_name(format)),
+        )
+        .set_header(header::CACHE_CONTROL, "no-store")
+        .body(archive)
+}
+
+//GET /user/profile
+pub async fn get_profile(
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+) -> Result<HttpResponse, ServiceError> {
+    let settings = dbmethods::profile_settings(auth.id, pool)?;
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+//PATCH /user/profile
+pub async fn update_profile(
+    change: web::Json<ProfileChange>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let settings = dbmethods::update_profile(auth.id, change.into_inner(), &audit, pool)?;
+    Ok(HttpResponse::Ok().json(settings))
 }
 
 // PATCH /user
+//a new email only becomes pending, it has to be confirmed at POST /user/email/confirm
 pub async fn update_user(
-    updates: web::Json<UserChange>,
+    updates: web::Json<UserChangeRequest>,
     pool: web::Data<Pool>,
+    mailer: web::Data<dyn Mailer>,
+    auth: AuthenticatedUser,
     req: HttpRequest,
+    audit: AuditContext,
 ) -> Result<HttpResponse, ServiceError> {
-    let (email, clearance) = parse_request(req);
     let updates = updates.into_inner();
-    let clearance = if clearance == "admin" { true } else { false };
-    let user = SlimUser { email, clearance };
-    let changed = dbmethods::user_update(user, updates, pool)?;
+    if updates.changes_credentials() {
+        auth.require_login()?;
+        dbmethods::check_reauth(
+            auth.id,
+            auth.token_id,
+            updates.current_password.as_deref(),
+            updates.reauth_token.as_deref(),
+            &audit,
+            pool.clone(),
+        )?;
+    }
+    let pending_email = updates.email.clone();
+    let locale = templates::preferred_locale(&req);
+    let changed = dbmethods::user_update(auth.id, updates.into(), &locale, &audit, pool.clone())?;
+    //the mails only go out once everything is saved
+    if pending_email.is_some() {
+        outbox::send_soon(mailer, pool);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "name": changed.name,
+        "email": changed.email,
+        "password": changed.password,
+        "pending_email": pending_email,
+    })))
+}
+
+//POST /user/email/confirm
+//logs out every other session and returns fresh tokens for this one
+pub async fn confirm_email_change(
+    confirm_data: web::Json<VerifyData>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+    req: HttpRequest,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    let user = dbmethods::confirm_email_change(auth.id, &confirm_data.token, &audit, pool.clone())?;
+    let session = dbmethods::create_session(
+        user.id,
+        ClientInfo::from_request(&req),
+        auth.mfa,
+        pool.clone(),
+    )?;
+    let tokens = issue_tokens(&user, &session, pool)?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
 
-    Ok(HttpResponse::Ok().json(changed))
+//POST /user/2fa
+//the secret only counts after POST /user/2fa/confirm
+pub async fn start_2fa(
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    let (user, secret) = dbmethods::start_totp(auth.id, pool)?;
+    let uri = totp::otpauth_uri(&secret, &TOTP_ISSUER, &user.email);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "secret": secret, "otpauth_uri": uri })))
+}
+
+//POST /user/2fa/confirm
+//the recovery codes are only shown here
+pub async fn confirm_2fa(
+    code_data: web::Json<TotpCode>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    let codes = dbmethods::confirm_totp(auth.id, &code_data.code, &audit, pool)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "recovery_codes": codes })))
 }
 
 //DELETE /user
 pub async fn remove_account(
     pool: web::Data<Pool>,
-    req: HttpRequest,
+    RestrictedUser(auth): RestrictedUser,
+    audit: AuditContext,
 ) -> Result<HttpResponse, ServiceError> {
-    let (email, _) = parse_request(req);
-    let b = dbmethods::delete_account(email, pool)?;
+    auth.require_login()?;
+    let purge_after = dbmethods::delete_account(auth.id, &audit, pool)?;
 
-    if b {
-        Ok(HttpResponse::Ok().json(serde_json::json!({ "msg": "account deleted successfully" })))
+    if let Some(purge_after) = purge_after {
+        Ok(HttpResponse::Ok().json(serde_json::json!({
+            "msg": "account deleted, login before purge_after to cancel",
+            "purge_after": purge_after,
+        })))
     } else {
         Ok(HttpResponse::Ok().json(serde_json::json!({ "msg": "could not delete account" })))
     }
 }
 
+//POST /user/tokens
+//the token is only shown here
+pub async fn create_token(
+    token_data: web::Json<ApiTokenData>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    let (stored, token) =
+        dbmethods::create_api_token(auth.id, auth.mfa, token_data.into_inner(), &audit, pool)?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "id": stored.id,
+        "name": stored.name,
+        "token": token,
+        "prefix": stored.prefix,
+        "scopes": stored.scopes,
+        "expires_at": stored.expires_at,
+    })))
+}
+
+//GET /user/tokens
+pub async fn get_tokens(
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    let tokens = dbmethods::list_api_tokens(auth.id, pool)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tokens": tokens })))
+}
+
+//DELETE /user/tokens/{id}
+pub async fn delete_token(
+    id: web::Path<String>,
+    pool: web::Data<Pool>,
+    auth: AuthenticatedUser,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    auth.require_login()?;
+    let id = match uuid::Uuid::parse_str(&id.into_inner()) {
+        Ok(v) => v,
+        Err(_) => return Err(ServiceError::BadRequest("invalid id".to_owned())),
+    };
+    dbmethods::delete_api_token(auth.id, id, &audit, pool)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "msg": "token deleted" })))
+}
+
 pub async fn test_route(pool: web::Data<Pool>) -> Result<HttpResponse, ServiceError> {
     let users = dbmethods::test_raw(&pool)?;
     Ok(HttpResponse::Ok().json(&users))
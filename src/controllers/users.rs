@@ -3,8 +3,13 @@ use actix_web::{web, HttpRequest, HttpResponse};
 use crate::{
     db::db::Pool,
     errors::ServiceError,
-    models::{dbmethods, user::UserData},
-    utils::parse_request,
+    mailer::{outbox, templates, Mailer},
+    models::{
+        audit::AuditContext,
+        dbmethods,
+        role::RoleAssignment,
+        user::{EmailData, FindBy, SearchHit, SearchQuery, UserData, UserQuery, VerifyData},
+    },
 };
 
 //route handles
@@ -12,38 +17,91 @@ use crate::{
 pub async fn post_user(
     user_data: web::Json<UserData>,
     pool: web::Data<Pool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+    audit: AuditContext,
 ) -> Result<HttpResponse, ServiceError> {
-    let user = dbmethods::insert_user(user_data.into_inner(), pool)?;
+    let locale = templates::preferred_locale(&req);
+    let user = dbmethods::insert_user(user_data.into_inner(), &locale, &audit, pool.clone())?;
+    outbox::send_soon(mailer, pool);
     Ok(HttpResponse::Created().body(serde_json::json!({ "email": user.email })))
 }
 
-//GET /users
-pub async fn get_users(
+//POST /users/verify-email
+pub async fn verify_email(
+    verify_data: web::Json<VerifyData>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    dbmethods::verify_email(&verify_data.token, &audit, pool)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "msg": "email verified" })))
+}
+
+//POST /users/verify-email/resend
+pub async fn resend_verification(
+    email_data: web::Json<EmailData>,
     pool: web::Data<Pool>,
+    mailer: web::Data<dyn Mailer>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ServiceError> {
-    let (_, clearance) = parse_request(req);
-    if !(clearance == "admin") {
-        return Err(ServiceError::Unauthorized);
+    let locale = templates::preferred_locale(&req);
+    if dbmethods::resend_verification(&email_data.email, &locale, pool.clone())? {
+        outbox::send_soon(mailer, pool);
     }
-    let users = dbmethods::get_all_users(pool)?;
-    Ok(HttpResponse::Ok().json(&users))
+    Ok(HttpResponse::Accepted()
+        .json(serde_json::json!({ "msg": "if the email needs verifying a new link was sent" })))
 }
 
-//PATCH /users/{id}
-pub async fn change_account_type(
+//GET /users
+pub async fn get_users(
+    query: web::Query<UserQuery>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let page = dbmethods::get_users(query.into_inner(), pool)?;
+    Ok(HttpResponse::Ok().json(&page))
+}
+
+//GET /users/search
+pub async fn search_users(
+    query: web::Query<SearchQuery>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let query = query.into_inner();
+    let hits: Vec<SearchHit> = dbmethods::search_users(&query.q, query.limit, pool)?
+        .into_iter()
+        .map(SearchHit::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "results": hits })))
+}
+
+//PUT /users/{id}/role
+pub async fn assign_role(
     user_id: web::Path<String>,
+    assignment: web::Json<RoleAssignment>,
     pool: web::Data<Pool>,
-    req: HttpRequest,
+    audit: AuditContext,
 ) -> Result<HttpResponse, ServiceError> {
-    let (clearance, _) = parse_request(req);
-    if !(clearance == "admin") {
-        return Err(ServiceError::Unauthorized);
-    }
-    let user_id = match user_id.into_inner().parse::<i64>() {
+    let user_id = match uuid::Uuid::parse_str(&user_id.into_inner()) {
+        Ok(v) => v,
+        Err(_) => return Err(ServiceError::BadRequest("invalid user id".to_owned())),
+    };
+    let user = dbmethods::find_by(FindBy::PublicId(user_id), pool.clone())?;
+    let access = dbmethods::assign_role(user.id, assignment.role.as_deref(), &audit, pool)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": user.public_id, "roles": access.roles })))
+}
+
+//POST /users/{id}/restore
+pub async fn restore_user(
+    user_id: web::Path<String>,
+    pool: web::Data<Pool>,
+    audit: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let user_id = match uuid::Uuid::parse_str(&user_id.into_inner()) {
         Ok(v) => v,
         Err(_) => return Err(ServiceError::BadRequest("invalid user id".to_owned())),
     };
-    let msg = dbmethods::change_account(user_id, pool)?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "msg": msg })))
+    let user = dbmethods::find_with_deleted(user_id, pool.clone())?;
+    let user = dbmethods::restore_account(user.id, None, &audit, pool)?;
+    Ok(HttpResponse::Ok()
+        .json(serde_json::json!({ "id": user.public_id, "msg": "account restored" })))
 }
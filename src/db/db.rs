@@ -1,19 +1,26 @@
-use std::env;
+use std::time::Duration;
 
 use diesel::{
     r2d2::{self, ConnectionManager},
     PgConnection,
 };
 
+use crate::settings;
+
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
 pub fn create_connection_pool() -> Pool {
-    dotenv::dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
+    let config = &settings::get().database;
     //create connection manager for pool
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    let manager = ConnectionManager::<PgConnection>::new(config.url.as_str());
+    //0 turns the idle and lifetime limits off
+    let limit = |secs: u64| Some(Duration::from_secs(secs)).filter(|_| secs > 0);
     //connection pool
     r2d2::Pool::builder()
+        .max_size(config.pool_size)
+        .connection_timeout(Duration::from_secs(config.connection_timeout_secs))
+        .idle_timeout(limit(config.idle_timeout_secs))
+        .max_lifetime(limit(config.max_lifetime_secs))
         .build(manager)
         .expect("failed to create pool")
 }
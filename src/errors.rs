@@ -1,6 +1,10 @@
 //custom errors
 use actix_web::{
-    dev::HttpResponseBuilder, error::ResponseError, http::header, http::StatusCode, HttpResponse,
+    dev::HttpResponseBuilder,
+    error::{BlockingError, ResponseError},
+    http::header,
+    http::StatusCode,
+    HttpResponse,
 };
 use derive_more::Display;
 use diesel::result::{DatabaseErrorKind, Error as DBError};
@@ -16,6 +20,17 @@ pub enum ServiceError {
     BadRequest(String),
     #[display(fmt = "Unauthorized")]
     Unauthorized,
+    #[display(fmt = "Forbidden: {}", _0)]
+    Forbidden(String),
+    #[display(
+        fmt = "ReauthenticationRequired: send current_password or a reauth_token from POST /auth/reauth"
+    )]
+    ReauthenticationRequired,
+    #[display(fmt = "TooManyRequests: {}", _0)]
+    TooManyRequests(String),
+    //an openid connect provider we log users in with did not answer properly
+    #[display(fmt = "BadGateway: {}", _0)]
+    BadGateway(String),
     #[display(fmt = "NotFound")]
     NotFound,
     #[display(fmt = "jsonwebtoken error")]
@@ -45,6 +60,61 @@ impl ResponseError for ServiceError {
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
             Self::NotFound => StatusCode::NOT_FOUND,
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) | Self::ReauthenticationRequired => StatusCode::FORBIDDEN,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::BadGateway(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+//errors of the oauth endpoints, they have their own format (rfc 6749 section 5.2)
+#[derive(Debug, Display)]
+#[display(fmt = "{}: {}", error, description)]
+pub struct OAuthError {
+    pub error: &'static str,
+    pub description: String,
+}
+
+impl OAuthError {
+    pub fn new(error: &'static str, description: &str) -> Self {
+        Self {
+            error,
+            description: description.to_owned(),
+        }
+    }
+}
+
+impl ResponseError for OAuthError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponseBuilder::new(self.status_code());
+        if self.error == "invalid_client" {
+            response.set_header(header::WWW_AUTHENTICATE, "Basic realm=\"oauth\"");
+        }
+        if self.error == "invalid_token" {
+            response.set_header(header::WWW_AUTHENTICATE, "Bearer error=\"invalid_token\"");
+        }
+        response
+            .set_header(header::CACHE_CONTROL, "no-store")
+            .json(serde_json::json!({
+                "error": self.error,
+                "error_description": self.description,
+            }))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self.error {
+            "invalid_client" | "invalid_token" => StatusCode::UNAUTHORIZED,
+            "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<ServiceError> for OAuthError {
+    fn from(error: ServiceError) -> Self {
+        match error {
+            ServiceError::BadRequest(msg) => Self::new("invalid_request", &msg),
+            _ => Self::new("server_error", &error.to_string()),
         }
     }
 }
@@ -70,3 +140,13 @@ impl From<JWTError> for ServiceError {
         Self::JsonWebTokenError
     }
 }
+
+//for work moved off the worker with web::block
+impl From<BlockingError<ServiceError>> for ServiceError {
+    fn from(error: BlockingError<ServiceError>) -> Self {
+        match error {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => ServiceError::InternalServerError,
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//everything we store about a user in one document, for GET /user/export.
+//secrets (password and token hashes, the totp secret, recovery codes) are left out,
+//they say nothing about the person and would only help someone who gets the file
+use diesel::prelude::*;
+use std::io::Write;
+
+use crate::{
+    errors::ServiceError,
+    models::{
+        api_token::ApiToken,
+        audit::AuditEvent,
+        dbmethods,
+        email_verification::EmailChange,
+        export::ExportFormat,
+        identity::UserIdentity,
+        profile::{Profile, ProfileSettings},
+        session::Session,
+        user::User,
+    },
+};
+
+//exports with more rows than this are left to the export job
+pub const INLINE_EXPORT_ROWS: i64 = 1000;
+
+pub fn row_count(user_id: i64, conn: &PgConnection) -> Result<i64, ServiceError> {
+    use crate::schema::{audit_events, sessions};
+    let events = audit_events::table
+        .filter(
+            audit_events::target_id
+                .eq(user_id)
+                .or(audit_events::actor_id.eq(user_id)),
+        )
+        .select(diesel::dsl::count_star())
+        .get_result::<i64>(conn)?;
+    let sessions = sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .select(diesel::dsl::count_star())
+        .get_result::<i64>(conn)?;
+    Ok(events + sessions)
+}
+
+pub fn collect(user_id: i64, conn: &PgConnection) -> Result<serde_json::Value, ServiceError> {
+    use crate::schema::{
+        api_tokens, audit_events, email_changes, profiles, sessions, user_identities, users,
+    };
+    let user = users::table.find(user_id).get_result::<User>(conn)?;
+    let access = dbmethods::access_of(user_id, conn)?;
+    let profile = profiles::table
+        .find(user_id)
+        .get_result::<Profile>(conn)
+        .optional()?
+        .map(ProfileSettings::from)
+        .unwrap_or_default();
+    let sessions = sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .order(sessions::created_at)
+        .load::<Session>(conn)?;
+    let identities = user_identities::table
+        .filter(user_identities::user_id.eq(user_id))
+        .order(user_identities::created_at)
+        .load::<UserIdentity>(conn)?;
+    let tokens = api_tokens::table
+        .filter(api_tokens::user_id.eq(user_id))
+        .order(api_tokens::created_at)
+        .load::<ApiToken>(conn)?;
+    let email_changes = email_changes::table
+        .filter(email_changes::user_id.eq(user_id))
+        .order(email_changes::created_at)
+        .load::<EmailChange>(conn)?
+        .into_iter()
+        .map(|change| {
+            serde_json::json!({
+                "new_email": change.new_email,
+                "requested_at": change.created_at,
+                "confirmed_at": change.used_at,
+            })
+        })
+        .collect::<Vec<_>>();
+    let events = audit_events::table
+        .filter(
+            audit_events::target_id
+                .eq(user_id)
+                .or(audit_events::actor_id.eq(user_id)),
+        )
+        .order(audit_events::id)
+        .load::<AuditEvent>(conn)?
+        .into_iter()
+        .map(|event| {
+            //where someone else acted from is theirs, not the users
+            let by_you = event.actor_id == Some(user_id);
+            serde_json::json!({
+                "event": event.event_type,
+                "at": event.created_at,
+                "by_you": by_you,
+                "about_you": event.target_id == Some(user_id),
+                "data": event.data,
+                "ip": event.ip.filter(|_| by_you),
+                "user_agent": event.user_agent.filter(|_| by_you),
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(serde_json::json!({
+        "exported_at": chrono::Utc::now().naive_utc(),
+        "user": {
+            "id": user.public_id,
+            "name": user.name,
+            "email": user.email,
+            "created_at": user.created_at,
+            "email_verified_at": user.email_verified_at,
+            "two_factor_enabled_at": user.totp_enabled_at,
+            "deleted_at": user.deleted_at,
+            "purge_after": user.purge_after,
+        },
+        "roles": access.roles,
+        "profile": profile,
+        "sessions": sessions,
+        "identities": identities,
+        "api_tokens": tokens,
+        "email_changes": email_changes,
+        "audit_events": events,
+    }))
+}
+
+//the zip holds the same json as a single file
+pub fn package(data: &serde_json::Value, format: ExportFormat) -> Result<Vec<u8>, ServiceError> {
+    let json = serde_json::to_vec_pretty(data).map_err(|_| ServiceError::InternalServerError)?;
+    match format {
+        ExportFormat::Json => Ok(json),
+        ExportFormat::Zip => {
+            let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            let options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+            zip.start_file("personal_data.json", options)
+                .map_err(|_| ServiceError::InternalServerError)?;
+            zip.write_all(&json)
+                .map_err(|_| ServiceError::InternalServerError)?;
+            let cursor = zip
+                .finish()
+                .map_err(|_| ServiceError::InternalServerError)?;
+            Ok(cursor.into_inner())
+        }
+    }
+}
+
+pub fn file_name(format: ExportFormat) -> String {
+    format!(
+        "personal_data_{}.{}",
+        chrono::Utc::now().format("%Y%m%d"),
+        format.as_str()
+    )
+}
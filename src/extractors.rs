@@ -0,0 +1,124 @@
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use uuid::Uuid;
+
+use crate::{
+    errors::ServiceError,
+    models::{
+        audit::AuditContext,
+        role::{Access, ADMIN_ROLE},
+        session::ClientInfo,
+    },
+};
+
+//identity of the caller, put into request extensions by the auth middleware
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub id: i64,
+    pub email: String,
+    pub access: Access,
+    //id of the session the token belongs to, or of the personal access token
+    pub token_id: Uuid,
+    pub email_verified: bool,
+    //the login passed the second factor
+    pub mfa: bool,
+    //the caller sent a personal access token instead of logging in
+    pub api_token: bool,
+}
+
+impl AuthenticatedUser {
+    pub fn is_admin(&self) -> bool {
+        self.access.has_role(ADMIN_ROLE)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.access.has_permission(permission)
+    }
+
+    //for what a leaked personal access token must not be able to do: credentials, 2fa, tokens, the account itself
+    pub fn require_login(&self) -> Result<(), ServiceError> {
+        if self.api_token {
+            return Err(ServiceError::Forbidden(
+                "this needs a login, not a personal access token".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Config = ();
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match req.extensions().get::<AuthenticatedUser>() {
+            Some(user) if user.email_verified => Ok(user.clone()),
+            Some(_) => Err(ServiceError::Forbidden(
+                "please verify your email first".to_owned(),
+            )),
+            None => Err(ServiceError::Unauthorized),
+        })
+    }
+}
+
+//like AuthenticatedUser but also lets in users that did not verify their email yet,
+//for the few routes a restricted token opens
+#[derive(Debug, Clone)]
+pub struct RestrictedUser(pub AuthenticatedUser);
+
+impl FromRequest for RestrictedUser {
+    type Config = ();
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<AuthenticatedUser>()
+                .cloned()
+                .map(RestrictedUser)
+                .ok_or(ServiceError::Unauthorized),
+        )
+    }
+}
+
+//for routes that work with or without a logged in user
+#[derive(Debug, Clone)]
+pub struct OptionalUser(pub Option<AuthenticatedUser>);
+
+impl FromRequest for OptionalUser {
+    type Config = ();
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(OptionalUser(
+            req.extensions().get::<AuthenticatedUser>().cloned(),
+        )))
+    }
+}
+
+//caller, ip, user agent and request id for the audit log. the request id comes
+//from a proxy in front of us through x-request-id, else a new one is made up
+impl FromRequest for AuditContext {
+    type Config = ();
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let client = ClientInfo::from_request(req);
+        let request_id = req
+            .headers()
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(64).collect())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        ready(Ok(AuditContext {
+            actor_id: req.extensions().get::<AuthenticatedUser>().map(|u| u.id),
+            ip: client.ip,
+            user_agent: client.user_agent,
+            request_id: Some(request_id),
+        }))
+    }
+}
@@ -0,0 +1,42 @@
+//background work that runs next to the server on its own thread
+use actix_web::web;
+use std::{thread, time};
+
+use crate::{db::db::Pool, models::dbmethods};
+
+//erases deleted accounts once their grace period is over, expired openid connect grants,
+//provider logins nobody came back from and old 2fa challenges
+pub fn spawn_purge(pool: Pool, every: time::Duration) {
+    thread::spawn(move || loop {
+        if let Err(err) = dbmethods::purge_deleted_accounts(&pool) {
+            log::error!("purge job: purging deleted accounts failed: {}", err);
+        }
+        if let Err(err) = dbmethods::purge_oauth_grants(&pool) {
+            log::error!("purge job: removing expired oauth grants failed: {}", err);
+        }
+        if let Err(err) = dbmethods::purge_provider_logins(&pool) {
+            log::error!("purge job: removing stale provider logins failed: {}", err);
+        }
+        if let Err(err) = dbmethods::purge_mfa_challenges(&pool) {
+            log::error!("purge job: removing old 2fa challenges failed: {}", err);
+        }
+        thread::sleep(every);
+    });
+}
+
+//builds queued personal data exports
+pub fn spawn_exports(pool: Pool, every: time::Duration) {
+    thread::spawn(move || loop {
+        if let Err(err) = dbmethods::run_pending_exports(&pool) {
+            log::error!("export job: building exports failed: {}", err);
+        }
+        thread::sleep(every);
+    });
+}
+
+//starts on a new export right away instead of waiting for the export job
+pub fn export_soon(pool: web::Data<Pool>) {
+    actix_web::rt::spawn(async move {
+        let _ = web::block(move || dbmethods::run_pending_exports(&pool)).await;
+    });
+}
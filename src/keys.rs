@@ -0,0 +1,205 @@
+//keys access tokens are signed and checked with.
+//with jwt.signing_key unset tokens are HS512 with auth.secret_key like before, otherwise they are signed
+//with that key and carry its kid. every key in jwt.keys is published on GET /.well-known/jwks.json and
+//accepted, so a new key can be published before it signs and an old one kept until its tokens expired
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+        RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use simple_asn1::ASN1Block;
+use std::{collections::HashMap, fs};
+
+use crate::{
+    errors::ServiceError,
+    settings::{JwtKey, Settings},
+};
+
+pub struct KeyRing {
+    header: Header,
+    signing: EncodingKey,
+    //by kid, None is the shared secret for tokens without one
+    verifying: HashMap<Option<String>, (Algorithm, DecodingKey)>,
+    jwks: JwkSet,
+}
+
+impl KeyRing {
+    pub fn load(settings: &Settings) -> Result<Self, String> {
+        let jwt = &settings.jwt;
+        let mut verifying = HashMap::new();
+        let mut jwks = JwkSet { keys: Vec::new() };
+        for key in &jwt.keys {
+            if verifying.contains_key(&Some(key.kid.clone())) {
+                return Err(format!("jwt key {} is listed twice", key.kid));
+            }
+            let jwk = public_jwk(key).map_err(|e| format!("jwt key {}: {}", key.kid, e))?;
+            let decoding =
+                DecodingKey::from_jwk(&jwk).map_err(|e| format!("jwt key {}: {}", key.kid, e))?;
+            verifying.insert(Some(key.kid.clone()), (key.algorithm, decoding));
+            jwks.keys.push(jwk);
+        }
+        let secret = settings.auth.secret_key.as_bytes();
+        let (header, signing) = if jwt.signing_key.is_empty() {
+            verifying.insert(None, (Algorithm::HS512, DecodingKey::from_secret(secret)));
+            (
+                Header::new(Algorithm::HS512),
+                EncodingKey::from_secret(secret),
+            )
+        } else {
+            let key = jwt
+                .keys
+                .iter()
+                .find(|k| k.kid == jwt.signing_key)
+                .ok_or(format!(
+                    "jwt.signing_key {} is not in jwt.keys",
+                    jwt.signing_key
+                ))?;
+            let mut header = Header::new(key.algorithm);
+            header.kid = Some(key.kid.clone());
+            (header, private_key(key)?)
+        };
+        let ring = Self {
+            header,
+            signing,
+            verifying,
+            jwks,
+        };
+        //a private key that does not belong to its public key would only show on the first login
+        let probe = serde_json::json!({ "exp": chrono::Utc::now().timestamp() + 60 });
+        let token = ring
+            .sign(&probe)
+            .map_err(|_| "jwt signing key can not sign".to_owned())?;
+        ring.verify::<serde_json::Value>(&token).map_err(|_| {
+            format!(
+                "private key of {} does not match its public key",
+                jwt.signing_key
+            )
+        })?;
+        Ok(ring)
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, ServiceError> {
+        Ok(encode(&self.header, claims, &self.signing)?)
+    }
+
+    //the kid picks the key and the key picks the algorithm, whatever else the header says
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<TokenData<T>, ServiceError> {
+        let header = decode_header(token)?;
+        let (algorithm, key) = self
+            .verifying
+            .get(&header.kid)
+            .ok_or(ServiceError::Unauthorized)?;
+        Ok(decode::<T>(token, key, &Validation::new(*algorithm))?)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.header.alg
+    }
+
+    //false with the HS512 default, then nobody without the secret can check our tokens
+    pub fn signs_with_public_key(&self) -> bool {
+        self.header.kid.is_some()
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+fn private_key(key: &JwtKey) -> Result<EncodingKey, String> {
+    let path = key
+        .private_key
+        .as_ref()
+        .ok_or(format!("jwt key {} signs but has no private_key", key.kid))?;
+    let pem = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let encoding = match key.algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(&pem),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+        _ => EncodingKey::from_rsa_pem(&pem),
+    };
+    encoding.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+//the jwk of a pem encoded SubjectPublicKeyInfo
+fn public_jwk(key: &JwtKey) -> Result<Jwk, String> {
+    let path = &key.public_key;
+    let text = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let pem = pem::parse(text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if pem.tag != "PUBLIC KEY" {
+        return Err(format!("{} is not a PUBLIC KEY pem", path.display()));
+    }
+    let bits = match der(&pem.contents)?.as_slice() {
+        [ASN1Block::Sequence(_, spki)] => match spki.as_slice() {
+            [_, ASN1Block::BitString(_, _, bits)] => bits.clone(),
+            _ => return Err("public key is not a SubjectPublicKeyInfo".to_owned()),
+        },
+        _ => return Err("public key is not a SubjectPublicKeyInfo".to_owned()),
+    };
+    let b64 = |bytes: &[u8]| URL_SAFE_NO_PAD.encode(bytes);
+    let algorithm = match key.algorithm {
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => match der(&bits)?.as_slice() {
+            [ASN1Block::Sequence(_, parts)] => match parts.as_slice() {
+                [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => {
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: b64(&n.to_bytes_be().1),
+                        e: b64(&e.to_bytes_be().1),
+                    })
+                }
+                _ => return Err("not an rsa public key".to_owned()),
+            },
+            _ => return Err("not an rsa public key".to_owned()),
+        },
+        Algorithm::ES256 | Algorithm::ES384 => {
+            let (curve, size) = match key.algorithm {
+                Algorithm::ES256 => (EllipticCurve::P256, 32),
+                _ => (EllipticCurve::P384, 48),
+            };
+            //uncompressed point, 0x04 then x then y
+            if bits.len() != 1 + 2 * size || bits[0] != 4 {
+                return Err(format!("not a {:?} public key", curve));
+            }
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve,
+                x: b64(&bits[1..=size]),
+                y: b64(&bits[size + 1..]),
+            })
+        }
+        Algorithm::EdDSA => {
+            if bits.len() != 32 {
+                return Err("not an ed25519 public key".to_owned());
+            }
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: b64(&bits),
+            })
+        }
+        other => return Err(format!("{:?} is not an asymmetric algorithm", other)),
+    };
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(key.algorithm),
+            key_id: Some(key.kid.clone()),
+            ..Default::default()
+        },
+        algorithm,
+    })
+}
+
+fn der(bytes: &[u8]) -> Result<Vec<ASN1Block>, String> {
+    simple_asn1::from_der(bytes).map_err(|e| format!("bad public key: {}", e))
+}
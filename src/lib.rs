@@ -1,11 +1,23 @@
+//diesel 1.4 table! and derive macros expand to nested impls
+#![allow(non_local_definitions)]
+
 #[macro_use]
 extern crate diesel;
 
+pub mod audit;
 pub mod controllers;
 pub mod db;
 pub mod errors;
+pub mod export;
+pub mod extractors;
+pub mod jobs;
+pub mod keys;
+pub mod mailer;
 pub mod middlewares;
 pub mod models;
+pub mod providers;
 pub mod routes;
 pub mod schema;
+pub mod settings;
+pub mod totp;
 pub mod utils;
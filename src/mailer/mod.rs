@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use crate::{
+    errors::ServiceError,
+    settings::{MailSettings, MailTransport},
+};
+
+pub mod outbox;
+pub mod templates;
+pub mod transport;
+
+pub use transport::{FileMailer, MemoryMailer, SmtpMailer, StdoutMailer};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+//anything that can deliver an email, kept in app data as web::Data<dyn Mailer>.
+//code that sends mail because of a db change should go through outbox::queue
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: Email) -> Result<(), ServiceError>;
+    //for log lines
+    fn name(&self) -> &'static str;
+}
+
+//builds the transport mail.transport names, Settings::validate already refused dev only ones in production
+pub fn from_settings(settings: &MailSettings) -> Result<Arc<dyn Mailer>, ServiceError> {
+    Ok(match settings.transport {
+        MailTransport::Smtp => Arc::new(SmtpMailer::new(&settings.smtp)?),
+        MailTransport::File => Arc::new(FileMailer::new(&settings.dir)),
+        MailTransport::Memory => Arc::new(MemoryMailer::default()),
+        MailTransport::Stdout => Arc::new(StdoutMailer),
+    })
+}
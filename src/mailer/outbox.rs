@@ -0,0 +1,98 @@
+use std::{sync::Arc, thread, time};
+
+use actix_web::web;
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+
+use super::{Email, Mailer};
+use crate::{
+    db::db::Pool,
+    errors::ServiceError,
+    models::outbox::{OutboxEmail, OutboxInsert},
+};
+
+//after this many failed attempts a mail is left alone
+pub const MAX_ATTEMPTS: i32 = 8;
+const BATCH_SIZE: i64 = 20;
+
+//stores the mail for sending, call it inside the transaction of the change
+//the mail is about
+pub fn queue(email: Email, conn: &PgConnection) -> Result<(), ServiceError> {
+    use crate::schema::email_outbox::dsl::email_outbox;
+    diesel::insert_into(email_outbox)
+        .values(&OutboxInsert::from(email))
+        .execute(conn)?;
+    Ok(())
+}
+
+//sends what is due, returns how many mails went out.
+//rows are locked while sending so several workers can run this at once
+pub fn flush(mailer: &dyn Mailer, pool: &Pool) -> Result<usize, ServiceError> {
+    use crate::schema::email_outbox::dsl::{
+        attempts, email_outbox, id, last_error, send_after, sent_at,
+    };
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        let now = Utc::now().naive_utc();
+        let due = email_outbox
+            .filter(sent_at.is_null())
+            .filter(send_after.le(now))
+            .filter(attempts.lt(MAX_ATTEMPTS))
+            .order(id)
+            .limit(BATCH_SIZE)
+            .for_update()
+            .skip_locked()
+            .load::<OutboxEmail>(conn)?;
+        let mut sent = 0;
+        for row in due {
+            let row_id = row.id;
+            let tries = row.attempts + 1;
+            match mailer.send(row.into()) {
+                Ok(_) => {
+                    diesel::update(email_outbox.find(row_id))
+                        .set((sent_at.eq(Utc::now().naive_utc()), attempts.eq(tries)))
+                        .execute(conn)?;
+                    sent += 1;
+                }
+                Err(err) => {
+                    log::warn!(
+                        "outbox: mail {} via {} failed, attempt {} of {}: {}",
+                        row_id,
+                        mailer.name(),
+                        tries,
+                        MAX_ATTEMPTS,
+                        err
+                    );
+                    //back off a little more every time
+                    let wait = Duration::seconds(30 * 2i64.pow(tries as u32));
+                    diesel::update(email_outbox.find(row_id))
+                        .set((
+                            attempts.eq(tries),
+                            last_error.eq(err.to_string()),
+                            send_after.eq(now + wait),
+                        ))
+                        .execute(conn)?;
+                }
+            }
+        }
+        Ok(sent)
+    })
+}
+
+//flushes the outbox right away instead of waiting for the dispatcher,
+//without holding up the request
+pub fn send_soon(mailer: web::Data<dyn Mailer>, pool: web::Data<Pool>) {
+    actix_web::rt::spawn(async move {
+        let _ = web::block(move || flush(mailer.as_ref(), &pool)).await;
+    });
+}
+
+//keeps flushing the outbox in the background
+pub fn spawn_dispatcher(mailer: Arc<dyn Mailer>, pool: Pool, every: time::Duration) {
+    thread::spawn(move || loop {
+        if let Err(err) = flush(mailer.as_ref(), &pool) {
+            log::error!("outbox: flushing via {} failed: {}", mailer.name(), err);
+        }
+        thread::sleep(every);
+    });
+}
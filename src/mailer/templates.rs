@@ -0,0 +1,119 @@
+use actix_web::{http::header, HttpRequest};
+
+use super::Email;
+use crate::errors::ServiceError;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+//(name, locale, text, html), the first line of the text is the subject
+const TEMPLATES: &[(&str, &str, &str, &str)] = &[
+    (
+        "email_change",
+        "en",
+        include_str!("../../templates/email/email_change/en.txt"),
+        include_str!("../../templates/email/email_change/en.html"),
+    ),
+    (
+        "email_change",
+        "de",
+        include_str!("../../templates/email/email_change/de.txt"),
+        include_str!("../../templates/email/email_change/de.html"),
+    ),
+    (
+        "email_change_notice",
+        "en",
+        include_str!("../../templates/email/email_change_notice/en.txt"),
+        include_str!("../../templates/email/email_change_notice/en.html"),
+    ),
+    (
+        "email_change_notice",
+        "de",
+        include_str!("../../templates/email/email_change_notice/de.txt"),
+        include_str!("../../templates/email/email_change_notice/de.html"),
+    ),
+    (
+        "password_reset",
+        "en",
+        include_str!("../../templates/email/password_reset/en.txt"),
+        include_str!("../../templates/email/password_reset/en.html"),
+    ),
+    (
+        "password_reset",
+        "de",
+        include_str!("../../templates/email/password_reset/de.txt"),
+        include_str!("../../templates/email/password_reset/de.html"),
+    ),
+    (
+        "verify_email",
+        "en",
+        include_str!("../../templates/email/verify_email/en.txt"),
+        include_str!("../../templates/email/verify_email/en.html"),
+    ),
+    (
+        "verify_email",
+        "de",
+        include_str!("../../templates/email/verify_email/de.txt"),
+        include_str!("../../templates/email/verify_email/de.html"),
+    ),
+];
+
+//renders a template for `to`, falls back from "de-AT" to "de" to the default locale.
+//{{key}} is replaced with the value, html escaped in the html part
+pub fn render(
+    name: &str,
+    locale: &str,
+    to: &str,
+    vars: &[(&str, &str)],
+) -> Result<Email, ServiceError> {
+    let language = locale.split('-').next().unwrap_or(DEFAULT_LOCALE);
+    let (_, _, text, html) = [locale, language, DEFAULT_LOCALE]
+        .iter()
+        .find_map(|l| {
+            TEMPLATES
+                .iter()
+                .find(|(n, tl, _, _)| *n == name && tl.eq_ignore_ascii_case(l))
+        })
+        .ok_or(ServiceError::InternalServerError)?;
+    let text = fill(text, vars, |v| v.to_owned());
+    let (subject, text) = match text.split_once('\n') {
+        Some((first, rest)) => (
+            first.trim_start_matches("subject:").trim().to_owned(),
+            rest.trim_start().to_owned(),
+        ),
+        None => (String::new(), text),
+    };
+    Ok(Email {
+        to: to.to_owned(),
+        subject,
+        text,
+        html: Some(fill(html, vars, escape_html)),
+    })
+}
+
+//first language of the Accept-Language header
+pub fn preferred_locale(req: &HttpRequest) -> String {
+    req.headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.split(';').next().unwrap_or("").trim().to_owned())
+        .filter(|v| !v.is_empty() && v != "*")
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_owned())
+}
+
+pub(crate) fn fill(template: &str, vars: &[(&str, &str)], escape: fn(&str) -> String) -> String {
+    let mut out = template.to_owned();
+    for (key, value) in vars {
+        out = out.replace(&format!("{{{{{}}}}}", key), &escape(value));
+    }
+    out
+}
+
+pub(crate) fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
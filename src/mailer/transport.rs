@@ -0,0 +1,156 @@
+use std::{fs, path::PathBuf, sync::Mutex};
+
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+
+use super::{Email, Mailer};
+use crate::{
+    errors::ServiceError,
+    settings::{self, SmtpSettings},
+};
+
+lazy_static::lazy_static! {
+    pub static ref MAIL_FROM: String = settings::get().mail.from.clone();
+}
+
+//prints mails instead of sending them, for development
+pub struct StdoutMailer;
+
+impl Mailer for StdoutMailer {
+    fn send(&self, email: Email) -> Result<(), ServiceError> {
+        println!(
+            "to: {}\nsubject: {}\n\n{}\n",
+            email.to, email.subject, email.text
+        );
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+}
+
+//keeps sent mails around, for tests
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, email: Email) -> Result<(), ServiceError> {
+        self.sent.lock().unwrap().push(email);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+}
+
+//writes every mail as an .eml file into a directory, for development
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: Email) -> Result<(), ServiceError> {
+        let message = build_message(email)?;
+        let file = self.dir.join(format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            uuid::Uuid::new_v4()
+        ));
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&file, message.formatted()))
+            .map_err(|err| {
+                log::error!("file mailer: writing {} failed: {}", file.display(), err);
+                ServiceError::InternalServerError
+            })
+    }
+
+    fn name(&self) -> &'static str {
+        "file"
+    }
+}
+
+//without tls it talks plain smtp, like to a local mailhog
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(settings: &SmtpSettings) -> Result<Self, ServiceError> {
+        let mut builder = if settings.tls {
+            SmtpTransport::relay(&settings.host).map_err(|err| {
+                log::error!("smtp mailer: no tls relay for {}: {}", settings.host, err);
+                ServiceError::InternalServerError
+            })?
+        } else {
+            SmtpTransport::builder_dangerous(&settings.host).port(1025)
+        };
+        if settings.port != 0 {
+            builder = builder.port(settings.port);
+        }
+        if !settings.user.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                settings.user.clone(),
+                settings.password.clone(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: Email) -> Result<(), ServiceError> {
+        let message = build_message(email)?;
+        self.transport.send(&message).map(|_| ()).map_err(|err| {
+            log::error!("smtp mailer: sending failed: {}", err);
+            ServiceError::InternalServerError
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+}
+
+fn build_message(email: Email) -> Result<Message, ServiceError> {
+    let address = |a: &str| {
+        a.parse::<Mailbox>()
+            .map_err(|_| ServiceError::BadRequest(format!("invalid email address {}", a)))
+    };
+    let builder = Message::builder()
+        .from(address(&MAIL_FROM)?)
+        .to(address(&email.to)?)
+        .subject(email.subject);
+    let message = match email.html {
+        Some(html) => builder.multipart(MultiPart::alternative_plain_html(email.text, html)),
+        None => builder.singlepart(
+            SinglePart::builder()
+                .header(ContentType::TEXT_PLAIN)
+                .body(email.text),
+        ),
+    };
+    message.map_err(|err| {
+        log::error!("building the mail failed: {}", err);
+        ServiceError::InternalServerError
+    })
+}
@@ -1,8 +1,13 @@
 use actix_web::{middleware, web, App, HttpServer};
+use std::{sync::Arc, time::Duration};
 
 use server::{
+    jobs,
+    mailer::{self, outbox, Mailer},
     middlewares,
-    routes::{auth, not_found, user, users},
+    providers::Providers,
+    routes::{admin, auth, not_found, oidc, user, users},
+    settings::Settings,
 };
 
 #[cfg(test)]
@@ -10,24 +15,63 @@ mod tests;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let address = "0.0.0.0:8000";
-    std::env::set_var("RUST_LOG", "actix_web=info,actix_server=info");
-    env_logger::init();
+    let settings = match Settings::load() {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("invalid settings: {}", err);
+            std::process::exit(2);
+        }
+    };
+    env_logger::Builder::new()
+        .parse_filters(&settings.log.level)
+        .init();
+    let json_limit = settings.server.json_limit;
     let conn_pool = server::db::db::create_connection_pool();
-    HttpServer::new(move || {
+    let mail_transport: Arc<dyn Mailer> = match mailer::from_settings(&settings.mail) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("invalid mail settings: {}", err);
+            std::process::exit(2);
+        }
+    };
+    outbox::spawn_dispatcher(
+        mail_transport.clone(),
+        conn_pool.clone(),
+        Duration::from_secs(10),
+    );
+    jobs::spawn_purge(conn_pool.clone(), Duration::from_secs(60 * 60));
+    jobs::spawn_exports(conn_pool.clone(), Duration::from_secs(30));
+    let mailer: web::Data<dyn Mailer> = web::Data::from(mail_transport);
+    //id tokens signed with the secret could not be checked by any client
+    let oidc_enabled = server::utils::KEYS.signs_with_public_key();
+    let providers = web::Data::new(Providers::new(
+        &settings.providers,
+        &settings.server.app_url,
+    ));
+    let server = HttpServer::new(move || {
         App::new()
             .data(conn_pool.clone())
+            .app_data(mailer.clone())
+            .app_data(providers.clone())
             //enable logger middleware
             .wrap(middleware::Logger::default())
             .wrap(middlewares::auth::Auth)
             //limit the maximum amount of data that server will except
-            .data(web::JsonConfig::default().limit(4096))
+            .data(web::JsonConfig::default().limit(json_limit))
+            .configure(admin::admin_route_config)
             .configure(users::users_route_config)
             .configure(user::user_route_config)
             .configure(auth::auth_route_config)
+            .configure(|cfg| {
+                if oidc_enabled {
+                    oidc::oidc_route_config(cfg)
+                }
+            })
             .default_service(web::route().to(not_found::handle_404))
-    })
-    .bind(address)?
-    .run()
-    .await
+    });
+    let server = match settings.server.workers {
+        0 => server,
+        n => server.workers(n),
+    };
+    server.bind(&settings.server.bind)?.run().await
 }
@@ -2,7 +2,7 @@ use actix_service::{Service, Transform};
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse},
     http::header,
-    Error, HttpResponse,
+    web, Error, HttpMessage, HttpResponse,
 };
 use futures::{
     future::{ok, Ready},
@@ -10,8 +10,59 @@ use futures::{
 };
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use uuid::Uuid;
 
-use crate::utils::decode_jwt;
+use crate::{
+    db::db::Pool,
+    extractors::AuthenticatedUser,
+    models::{api_token::TOKEN_PREFIX, dbmethods, role::Access, user::Claims},
+    utils::decode_jwt,
+};
+
+//routes that work without a token
+const PUBLIC_POST_ROUTES: &[&str] = &[
+    //register and email verification
+    "/users",
+    "/users/verify-email",
+    "/users/verify-email/resend",
+    //login, second login step, cancelling a deletion, token refresh and password reset
+    "/auth",
+    "/auth/2fa",
+    "/auth/restore",
+    "/auth/refresh",
+    "/auth/password-reset",
+    "/auth/password-reset/confirm",
+    //openid connect, the clients bring their own credentials
+    "/oauth/authorize",
+    "/oauth/token",
+    "/oauth/userinfo",
+];
+
+//logins at openid connect providers: /auth/providers/{name}, its /callback and /link
+fn is_public_post(path: &str) -> bool {
+    PUBLIC_POST_ROUTES.contains(&path)
+        || path
+            .strip_prefix("/auth/providers/")
+            .is_some_and(|rest| match rest.split_once('/') {
+                Some((name, step)) => !name.is_empty() && matches!(step, "callback" | "link"),
+                None => !rest.is_empty(),
+            })
+}
+
+//GET /user/{id} answers without a token with the public fields only,
+//the export download link carries its own token, the jwks, openid connect and the login providers
+//are for everyone
+fn is_public_get(path: &str) -> bool {
+    path == "/user/export/download"
+        || path == "/auth/providers"
+        || path == "/.well-known/jwks.json"
+        || path == "/.well-known/openid-configuration"
+        || path == "/oauth/authorize"
+        || path == "/oauth/userinfo"
+        || path
+            .strip_prefix("/user/")
+            .is_some_and(|id| Uuid::parse_str(id).is_ok())
+}
 
 pub struct Auth;
 
@@ -54,32 +105,42 @@ where
 
     fn call(&mut self, mut req: Self::Request) -> Self::Future {
         let mut token_verified = false;
-        //skip for user regiter and login
-        if req.uri().path() == "/users" && req.method() == "POST"
-            || req.uri().path() == "/auth" && req.method() == "POST"
-        {
+        if req.method() == "POST" && is_public_post(req.uri().path()) {
             token_verified = true;
         }
-        if let Some(t) = req.headers_mut().get("AUTHORIZATION") {
+        if req.method() == "GET" && is_public_get(req.uri().path()) {
+            token_verified = true;
+        }
+        //identity only ever comes from the token, never from client headers
+        req.headers_mut().remove("user_email");
+        req.headers_mut().remove("user_clearance");
+        if let Some(t) = req.headers().get(header::AUTHORIZATION) {
             if let Ok(token) = t.to_str() {
                 if token.starts_with("bearer") || token.starts_with("Bearer") {
                     let token = token[6..].trim();
-                    if let Ok(data) = decode_jwt(token.to_owned()) {
-                        req.headers_mut().insert(
-                            header::HeaderName::from_static("user_email"),
-                            header::HeaderValue::from_str(&data.claims.email).unwrap(),
-                        );
-                        let user_type = if data.claims.clearance {
-                            "admin"
-                        } else {
-                            "non_admin"
-                        };
-                        req.headers_mut().insert(
-                            header::HeaderName::from_static("user_clearance"),
-                            header::HeaderValue::from_str(&user_type).unwrap(),
-                        );
-
-                        token_verified = true;
+                    if token.starts_with(TOKEN_PREFIX) {
+                        if let Some(user) = api_token_user(&req, token) {
+                            req.extensions_mut().insert(user);
+                            token_verified = true;
+                        }
+                    } else if let Ok(data) = decode_jwt(token.to_owned()) {
+                        let claims = data.claims;
+                        //a token is only good while its session exists
+                        if let Some(session_id) = live_session(&req, &claims) {
+                            req.extensions_mut().insert(AuthenticatedUser {
+                                id: claims.sub,
+                                email: claims.email,
+                                access: Access {
+                                    roles: claims.roles,
+                                    permissions: claims.permissions,
+                                },
+                                token_id: session_id,
+                                email_verified: claims.email_verified,
+                                mfa: claims.mfa,
+                                api_token: false,
+                            });
+                            token_verified = true;
+                        }
                     }
                 }
             }
@@ -102,3 +163,31 @@ where
         }
     }
 }
+
+//personal access tokens are looked up on every request, so a deleted one stops working right away
+fn api_token_user(req: &ServiceRequest, token: &str) -> Option<AuthenticatedUser> {
+    let pool = req.app_data::<web::Data<Pool>>()?;
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(|v| v.to_owned());
+    let (stored, user, access) = dbmethods::use_api_token(token, ip, pool.clone()).ok()??;
+    Some(AuthenticatedUser {
+        id: user.id,
+        email: user.email,
+        access,
+        token_id: stored.id,
+        email_verified: user.email_verified_at.is_some(),
+        mfa: stored.mfa,
+        api_token: true,
+    })
+}
+
+fn live_session(req: &ServiceRequest, claims: &Claims) -> Option<Uuid> {
+    let pool = req.app_data::<web::Data<Pool>>()?;
+    let session_id = Uuid::parse_str(&claims.jti).ok()?;
+    match dbmethods::touch_session(session_id, claims.sub, claims.ver, pool.clone()) {
+        Ok(true) => Some(session_id),
+        _ => None,
+    }
+}
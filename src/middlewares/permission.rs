@@ -0,0 +1,116 @@
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error, HttpMessage,
+};
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{
+    db::db::Pool,
+    errors::ServiceError,
+    extractors::AuthenticatedUser,
+    models::{dbmethods, role::ADMIN_ROLE},
+};
+
+//route guard, goes on a resource in routes::* like
+//web::resource("/users").wrap(RequirePermission::new("users:read"))
+//needs the Auth middleware in front of it
+pub struct RequirePermission {
+    permission: &'static str,
+}
+
+impl RequirePermission {
+    pub fn new(permission: &'static str) -> Self {
+        Self { permission }
+    }
+}
+
+impl<S, B> Transform<S> for RequirePermission
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = S::Response;
+    type Request = S::Request;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = RequirePermissionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequirePermissionMiddleware {
+            service,
+            permission: self.permission,
+        })
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: S,
+    permission: &'static str,
+}
+
+impl<S, B> Service for RequirePermissionMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = S::Response;
+    type Request = S::Request;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+    type Error = S::Error;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        match check(&req, self.permission) {
+            Ok(()) => Box::pin(self.service.call(req)),
+            Err(err) => Box::pin(async move { Ok(req.error_response(err)) }),
+        }
+    }
+}
+
+fn check(req: &ServiceRequest, permission: &str) -> Result<(), ServiceError> {
+    let user = req
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .cloned()
+        .ok_or(ServiceError::Unauthorized)?;
+    if !user.email_verified {
+        return Err(ServiceError::Forbidden(
+            "please verify your email first".to_owned(),
+        ));
+    }
+    if !user.has_permission(permission) {
+        return Err(ServiceError::Forbidden(format!(
+            "missing permission {}",
+            permission
+        )));
+    }
+    //the admin 2fa policy from PUT /admin/2fa-policy. tokens have no roles, for them the account counts
+    if !user.mfa {
+        let pool = req
+            .app_data::<web::Data<Pool>>()
+            .ok_or(ServiceError::InternalServerError)?;
+        let admin = if user.api_token {
+            dbmethods::load_access(user.id, pool.clone())?.has_role(ADMIN_ROLE)
+        } else {
+            user.is_admin()
+        };
+        if admin && dbmethods::admin_2fa_required(pool.clone())? {
+            return Err(ServiceError::Forbidden(
+                "admins have to login with two factor authentication".to_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+//every personal access token starts with this, the middleware tells them from access tokens by it
+pub const TOKEN_PREFIX: &str = "pat_";
+//tokens one user can have
+pub const MAX_API_TOKENS: i64 = 50;
+
+#[derive(Queryable, Serialize, Debug)]
+pub struct ApiToken {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    #[serde(skip)]
+    pub mfa: bool,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub last_used_ip: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_tokens"]
+pub struct ApiTokenInsert {
+    pub id: Uuid,
+    pub user_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub mfa: bool,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+//POST /user/tokens body
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiTokenData {
+    pub name: String,
+    //permissions of the user the token may use, none for a token that only acts on the own account
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    //null never expires
+    pub expires_in_days: Option<i64>,
+}
+
+//the part of a token we look it up by, pat_<lookup>_<secret> gives pat_<lookup>
+pub fn token_prefix(token: &str) -> Option<&str> {
+    let rest = token.strip_prefix(TOKEN_PREFIX)?;
+    let (lookup, secret) = rest.split_once('_')?;
+    if lookup.is_empty() || secret.is_empty() {
+        return None;
+    }
+    Some(&token[..TOKEN_PREFIX.len() + lookup.len()])
+}
@@ -0,0 +1,146 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Queryable, Debug)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub event_type: String,
+    //who did it, None for anonymous requests
+    pub actor_id: Option<i64>,
+    //who it happened to
+    pub target_id: Option<i64>,
+    pub data: serde_json::Value,
+    pub created_at: chrono::NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+//an event as GET /admin/audit shows it, actor and target by their public id
+#[derive(Serialize, Debug)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub event_type: String,
+    pub actor_id: Option<Uuid>,
+    pub target_id: Option<Uuid>,
+    pub data: serde_json::Value,
+    pub created_at: chrono::NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_events"]
+pub struct AuditEventInsert {
+    pub event_type: String,
+    pub actor_id: Option<i64>,
+    pub target_id: Option<i64>,
+    pub data: serde_json::Value,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventType {
+    Login,
+    LoginFailed,
+    ReauthFailed,
+    SecondFactorFailed,
+    Logout,
+    Registered,
+    EmailVerified,
+    ProfileUpdated,
+    EmailChangeRequested,
+    EmailChanged,
+    PasswordReset,
+    TwoFactorEnabled,
+    RoleChanged,
+    SettingChanged,
+    AccountDeleted,
+    AccountRestored,
+    AccountPurged,
+    DataExported,
+    ClientCreated,
+    ClientDeleted,
+    ClientAuthorized,
+    IdentityLinked,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+    TokensRevoked,
+    RefreshTokenReused,
+}
+
+impl AuditEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::LoginFailed => "login_failed",
+            Self::ReauthFailed => "reauth_failed",
+            Self::SecondFactorFailed => "second_factor_failed",
+            Self::Logout => "logout",
+            Self::Registered => "registered",
+            Self::EmailVerified => "email_verified",
+            Self::ProfileUpdated => "profile_updated",
+            Self::EmailChangeRequested => "email_change_requested",
+            Self::EmailChanged => "email_changed",
+            Self::PasswordReset => "password_reset",
+            Self::TwoFactorEnabled => "two_factor_enabled",
+            Self::RoleChanged => "role_changed",
+            Self::SettingChanged => "setting_changed",
+            Self::AccountDeleted => "account_deleted",
+            Self::AccountRestored => "account_restored",
+            Self::AccountPurged => "account_purged",
+            Self::DataExported => "data_exported",
+            Self::ClientCreated => "client_created",
+            Self::ClientDeleted => "client_deleted",
+            Self::ClientAuthorized => "client_authorized",
+            Self::IdentityLinked => "identity_linked",
+            Self::ApiTokenCreated => "api_token_created",
+            Self::ApiTokenRevoked => "api_token_revoked",
+            Self::TokensRevoked => "tokens_revoked",
+            Self::RefreshTokenReused => "refresh_token_reused",
+        }
+    }
+}
+
+//who is doing something and from where, the request side of every audit event
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub actor_id: Option<i64>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl AuditContext {
+    //for events where the caller only just proved who they are, like login
+    pub fn as_actor(&self, actor: i64) -> Self {
+        Self {
+            actor_id: Some(actor),
+            ..self.clone()
+        }
+    }
+}
+
+//GET /admin/audit query, newest first, cursor is the id of the last event of the previous page
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    //public ids of users
+    pub actor: Option<Uuid>,
+    pub target: Option<Uuid>,
+    pub event: Option<AuditEventType>,
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub events: Vec<AuditEntry>,
+    pub next_cursor: Option<i64>,
+}
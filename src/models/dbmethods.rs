@@ -1,72 +1,750 @@
 use actix_web::web;
+use chrono::{Duration, TimeZone, Utc};
+use diesel::pg::Pg;
 use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
 
+use crate::audit::AuditLog;
 use crate::db::db::Pool;
 use crate::errors::ServiceError;
-use crate::models::user::{AuthData, FindBy, SlimUser, User, UserChange, UserData, UserInsert};
-use crate::utils::{hash_password, verify_hash};
+use crate::mailer::{outbox, templates};
+use crate::models::api_token::{
+    token_prefix, ApiToken, ApiTokenData, ApiTokenInsert, MAX_API_TOKENS, TOKEN_PREFIX,
+};
+use crate::models::audit::{AuditContext, AuditEventType};
+use crate::models::email_verification::{
+    EmailChange, EmailChangeInsert, EmailVerification, EmailVerificationInsert,
+};
+use crate::models::export::{DataExport, DataExportInsert, ExportFormat, ExportStatus};
+use crate::models::identity::{
+    IdentityLogin, LinkClaims, ProviderLogin, ProviderLoginInsert, UpstreamIdentity,
+    UserIdentityInsert,
+};
+use crate::models::oauth::{
+    valid_redirect_uri, AuthCode, AuthCodeInsert, ClientData, Grant, OAuthClient,
+    OAuthClientInsert, OAuthToken, OAuthTokenInsert,
+};
+use crate::models::password_reset::{PasswordReset, PasswordResetInsert};
+use crate::models::profile::{Profile, ProfileChange, ProfileInsert, ProfileSettings};
+use crate::models::role::{Access, UserRoleInsert, ADMIN_ROLE};
+use crate::models::session::{
+    ClientInfo, RefreshToken, RefreshTokenInsert, Session, SessionInsert,
+};
+use crate::models::two_factor::{MfaChallengeInsert, RecoveryCode, RecoveryCodeInsert};
+use crate::models::user::{
+    AuthData, FindBy, SearchRow, SlimUser, User, UserChange, UserData, UserInsert, UserPage,
+    UserQuery, UserSort,
+};
+use crate::settings::ProviderSettings;
+use crate::utils::{
+    generate_token, hash_password, hash_token, pkce_matches, verify_hash, ACCESS_TOKEN_MINUTES,
+    APP_URL, AUTHORIZATION_CODE_SECONDS, DELETION_GRACE_DAYS, EXPORT_DOWNLOAD_HOURS, HASH_POLICY,
+    MFA_CHALLENGE_FAILURES, MFA_LOCK_MINUTES, MFA_TOKEN_MINUTES, MFA_USER_FAILURES,
+    PASSWORD_FAILURES, PASSWORD_LOCK_MINUTES, PROVIDER_LOGIN_MINUTES, REAUTH_MINUTES,
+    RECOVERY_CODES, RESET_TOKEN_MINUTES, SESSION_DAYS, UNVERIFIED_LOGIN, VERIFY_RESEND_SECONDS,
+    VERIFY_TOKEN_HOURS,
+};
 
 //route handles helper function
-pub fn login_user(user_data: AuthData, pool: web::Data<Pool>) -> Result<SlimUser, ServiceError> {
-    use crate::schema::users::dsl::{email, users};
+//with 2fa on the login is only recorded once the second factor passed
+pub fn login_user(
+    user_data: AuthData,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<User, ServiceError> {
+    use crate::schema::users::dsl::{deleted_at, email, id, password, users};
     let conn = &pool.get().unwrap();
-    let mut items = users
+    //a deleted account can share its email with a new one, the live account goes first
+    let items = users
         .filter(email.eq(&user_data.email))
+        .order((deleted_at.desc(), id.desc()))
         .load::<User>(conn)?;
-    if let Some(user) = items.pop() {
-        if let Ok(matching) = verify_hash(&user.password, &user_data.password) {
-            if matching {
-                return Ok(user.into());
-            }
+    let target = items.first().map(|user| user.id);
+    if let Some(target) = target {
+        let data = serde_json::json!({ "email": user_data.email });
+        count_password_try(target, AuditEventType::LoginFailed, data, audit, conn)?;
+    }
+    let found = items
+        .into_iter()
+        .find(|user| verify_hash(&user.password, &user_data.password).unwrap_or(false));
+    let user = match found {
+        Some(user) => user,
+        None => {
+            AuditLog::record(
+                conn,
+                audit,
+                AuditEventType::LoginFailed,
+                target,
+                serde_json::json!({ "email": user_data.email, "reason": "wrong credentials" }),
+            )?;
+            return Err(ServiceError::Unauthorized);
+        }
+    };
+    if let Some(target) = target {
+        forget_password_tries(target, conn)?;
+    }
+    //move old hashes to the current policy while we have the password
+    if HASH_POLICY.is_weaker(&user.password) {
+        if let Ok(new_hash) = hash_password(&user_data.password) {
+            let _ = diesel::update(users.find(user.id))
+                .set(password.eq(new_hash))
+                .execute(conn);
+        }
+    }
+    admit_login(user, None, audit, conn)
+}
+
+//what every way of logging in ends with, provider is set for logins at an openid connect provider
+fn admit_login(
+    user: User,
+    provider: Option<&str>,
+    audit: &AuditContext,
+    conn: &PgConnection,
+) -> Result<User, ServiceError> {
+    if !UNVERIFIED_LOGIN.admits(&user) {
+        let mut data = serde_json::json!({ "email": user.email, "reason": "email not verified" });
+        if let Some(provider) = provider {
+            data["provider"] = provider.into();
+        }
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::LoginFailed,
+            Some(user.id),
+            data,
+        )?;
+        return Err(ServiceError::Forbidden(
+            "please verify your email first".to_owned(),
+        ));
+    }
+    if user.totp_enabled_at.is_none() {
+        let mut data = serde_json::json!({ "mfa": false });
+        if let Some(provider) = provider {
+            data["provider"] = provider.into();
         }
+        AuditLog::record(
+            conn,
+            &audit.as_actor(user.id),
+            AuditEventType::Login,
+            Some(user.id),
+            data,
+        )?;
+    }
+    Ok(user)
+}
+
+//counts a password try before the password is checked, so parallel guesses can not go past
+//PASSWORD_FAILURES. logins, step-up and linking a provider all share the count
+fn count_password_try(
+    user: i64,
+    event: AuditEventType,
+    data: serde_json::Value,
+    audit: &AuditContext,
+    conn: &PgConnection,
+) -> Result<(), ServiceError> {
+    use crate::schema::password_failures::dsl::{
+        failures, password_failures, user_id, window_started_at,
+    };
+    let now = Utc::now().naive_utc();
+    diesel::insert_into(password_failures)
+        .values(user_id.eq(user))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    //a window that ran out starts over
+    diesel::update(password_failures.find(user))
+        .filter(window_started_at.le(now - Duration::minutes(PASSWORD_LOCK_MINUTES)))
+        .set((failures.eq(0), window_started_at.eq(now)))
+        .execute(conn)?;
+    let counted = diesel::update(password_failures.find(user))
+        .filter(failures.lt(PASSWORD_FAILURES))
+        .set(failures.eq(failures + 1))
+        .execute(conn)?;
+    if counted == 0 {
+        let mut data = data;
+        data["reason"] = "locked".into();
+        AuditLog::record(conn, audit, event, Some(user), data)?;
+        return Err(ServiceError::TooManyRequests(format!(
+            "too many wrong passwords, try again in {} minutes",
+            PASSWORD_LOCK_MINUTES
+        )));
+    }
+    Ok(())
+}
+
+//the right password was given, the count starts over
+fn forget_password_tries(user: i64, conn: &PgConnection) -> Result<(), ServiceError> {
+    use crate::schema::password_failures::dsl::password_failures;
+    diesel::delete(password_failures.find(user)).execute(conn)?;
+    Ok(())
+}
+
+//a password check that counts towards the lock, wrong passwords end up in the audit log as event
+fn check_password(
+    user: &User,
+    passwd: &str,
+    event: AuditEventType,
+    data: serde_json::Value,
+    audit: &AuditContext,
+    conn: &PgConnection,
+) -> Result<(), ServiceError> {
+    count_password_try(user.id, event, data.clone(), audit, conn)?;
+    if verify_hash(&user.password, passwd).unwrap_or(false) {
+        return forget_password_tries(user.id, conn);
     }
+    let mut data = data;
+    data["reason"] = "wrong credentials".into();
+    AuditLog::record(conn, audit, event, Some(user.id), data)?;
     Err(ServiceError::Unauthorized)
 }
 
+//a login at an openid connect provider, the state comes back with the browser.
+//returns the state, the nonce and the pkce verifier
+pub fn start_provider_login(
+    provider: &str,
+    pool: web::Data<Pool>,
+) -> Result<(String, ProviderLogin), ServiceError> {
+    use crate::schema::provider_logins::dsl::provider_logins;
+    let state = generate_token();
+    let new_login = ProviderLoginInsert {
+        state_hash: hash_token(&state),
+        provider: provider.to_owned(),
+        nonce: generate_token(),
+        code_verifier: generate_token(),
+        expires_at: (Utc::now() + Duration::minutes(*PROVIDER_LOGIN_MINUTES)).naive_utc(),
+    };
+    let conn = &pool.get().unwrap();
+    let stored = diesel::insert_into(provider_logins)
+        .values(&new_login)
+        .get_result::<ProviderLogin>(conn)?;
+    Ok((state, stored))
+}
+
+//each state works once, and only with the provider it was started for
+pub fn take_provider_login(
+    provider_name: &str,
+    state: &str,
+    pool: web::Data<Pool>,
+) -> Result<ProviderLogin, ServiceError> {
+    use crate::schema::provider_logins::dsl::{expires_at, provider, provider_logins, state_hash};
+    let conn = &pool.get().unwrap();
+    diesel::delete(provider_logins)
+        .filter(state_hash.eq(hash_token(state)))
+        .filter(provider.eq(provider_name))
+        .filter(expires_at.gt(Utc::now().naive_utc()))
+        .get_result::<ProviderLogin>(conn)
+        .optional()?
+        .ok_or_else(|| ServiceError::BadRequest("invalid or expired state".to_owned()))
+}
+
+//the account an identity from a provider belongs to. an email that already has an account is
+//never linked here, whoever controls the provider account could otherwise take it over
+pub fn login_with_identity(
+    settings: &ProviderSettings,
+    identity: UpstreamIdentity,
+    locale: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<IdentityLogin, ServiceError> {
+    use crate::schema::user_identities::dsl as identities;
+    use crate::schema::users::dsl as users;
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    conn.transaction(|| {
+        let linked = identities::user_identities
+            .inner_join(users::users)
+            .filter(identities::provider.eq(&settings.name))
+            .filter(identities::subject.eq(&identity.subject))
+            .select(users::users::all_columns())
+            .first::<User>(conn)
+            .optional()?;
+        if let Some(user) = linked {
+            diesel::update(identities::user_identities)
+                .filter(identities::provider.eq(&settings.name))
+                .filter(identities::subject.eq(&identity.subject))
+                .set((
+                    identities::last_login_at.eq(now),
+                    identities::email.eq(&identity.email),
+                ))
+                .execute(conn)?;
+            return Ok(IdentityLogin::Linked(admit_login(
+                user,
+                Some(&settings.name),
+                audit,
+                conn,
+            )?));
+        }
+        let address = identity.email.clone().ok_or_else(|| {
+            ServiceError::BadRequest("the provider did not share an email address".to_owned())
+        })?;
+        if address.len() > 100 {
+            return Err(ServiceError::BadRequest(
+                "the email address from the provider is too long".to_owned(),
+            ));
+        }
+        let owner = users::users
+            .filter(users::email.eq(&address))
+            .filter(users::deleted_at.is_null())
+            .first::<User>(conn)
+            .optional()?;
+        if let Some(user) = owner {
+            return Ok(IdentityLogin::LinkRequired(user, identity));
+        }
+        if !settings.create_users {
+            return Err(ServiceError::Forbidden(format!(
+                "there is no account for this {} login",
+                settings.name
+            )));
+        }
+        let name = identity
+            .name
+            .clone()
+            .unwrap_or_else(|| address.split('@').next().unwrap_or("").to_owned())
+            .chars()
+            .take(100)
+            .collect::<String>();
+        //nobody knows this password, a password reset sets one
+        let new_user = UserInsert::from_details(name, address, hash_password(&generate_token())?);
+        let mut user = diesel::insert_into(users::users)
+            .values(&new_user)
+            .get_result::<User>(conn)?;
+        if identity.email_verified {
+            user = diesel::update(users::users.find(user.id))
+                .set(users::email_verified_at.eq(now))
+                .get_result::<User>(conn)?;
+        } else {
+            queue_verification(&user, locale, conn)?;
+        }
+        diesel::insert_into(identities::user_identities)
+            .values(&UserIdentityInsert {
+                user_id: user.id,
+                provider: settings.name.clone(),
+                subject: identity.subject,
+                email: identity.email,
+            })
+            .execute(conn)?;
+        AuditLog::record(
+            conn,
+            &audit.as_actor(user.id),
+            AuditEventType::Registered,
+            Some(user.id),
+            serde_json::json!({ "email": user.email, "provider": settings.name }),
+        )?;
+        Ok(IdentityLogin::Created(admit_login(
+            user,
+            Some(&settings.name),
+            audit,
+            conn,
+        )?))
+    })
+}
+
+//the owner of the account proves it with the password, then the identity logs in as them
+pub fn link_identity(
+    claims: LinkClaims,
+    user_password: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<User, ServiceError> {
+    use crate::schema::user_identities::dsl::user_identities;
+    use crate::schema::users::dsl::users;
+    let conn = &pool.get().unwrap();
+    let user = users
+        .find(claims.sub)
+        .first::<User>(conn)
+        .optional()?
+        .filter(|user| user.token_version == claims.ver && user.deleted_at.is_none())
+        .ok_or(ServiceError::Unauthorized)?;
+    check_password(
+        &user,
+        user_password,
+        AuditEventType::LoginFailed,
+        serde_json::json!({ "email": user.email, "provider": claims.provider }),
+        audit,
+        conn,
+    )?;
+    conn.transaction(|| {
+        diesel::insert_into(user_identities)
+            .values(&UserIdentityInsert {
+                user_id: user.id,
+                provider: claims.provider.clone(),
+                subject: claims.subject.clone(),
+                email: claims.email,
+            })
+            .execute(conn)?;
+        AuditLog::record(
+            conn,
+            &audit.as_actor(user.id),
+            AuditEventType::IdentityLinked,
+            Some(user.id),
+            serde_json::json!({ "provider": claims.provider, "subject": claims.subject }),
+        )?;
+        admit_login(user, Some(&claims.provider), audit, conn)
+    })
+}
+
+//logins that never came back from the provider
+pub fn purge_provider_logins(pool: &Pool) -> Result<usize, ServiceError> {
+    use crate::schema::provider_logins::dsl::{expires_at, provider_logins};
+    let conn = &pool.get().unwrap();
+    Ok(
+        diesel::delete(provider_logins.filter(expires_at.le(Utc::now().naive_utc())))
+            .execute(conn)?,
+    )
+}
+
+//mfa challenges that no longer count towards a lock
+pub fn purge_mfa_challenges(pool: &Pool) -> Result<usize, ServiceError> {
+    use crate::schema::mfa_challenges::dsl::{created_at, expires_at, mfa_challenges};
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    Ok(diesel::delete(
+        mfa_challenges
+            .filter(expires_at.le(now))
+            .filter(created_at.le(now - Duration::minutes(MFA_LOCK_MINUTES))),
+    )
+    .execute(conn)?)
+}
+
 //route handler helpers
-pub fn delete_account(user_email: String, pool: web::Data<Pool>) -> Result<bool, ServiceError> {
-    use crate::schema::users::dsl::{email, users};
+//only marks the account, purge_deleted_accounts erases it once the grace period is over.
+//until then the owner can login to cancel and admins can restore it
+pub fn delete_account(
+    user_id: i64,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<Option<chrono::NaiveDateTime>, ServiceError> {
+    use crate::schema::password_resets::dsl::{password_resets, user_id as reset_user};
+    use crate::schema::users::dsl::{deleted_at, purge_after, users};
     let conn = &pool.get().unwrap();
-    let result = diesel::delete(users)
-        .filter(email.eq_all(user_email))
-        .execute(conn)?;
-    if result > 0 {
-        Ok(true)
-    } else {
-        Ok(false)
-    }
+    let now = Utc::now().naive_utc();
+    let purge_at = now + Duration::days(*DELETION_GRACE_DAYS);
+    conn.transaction(|| {
+        let result = diesel::update(users.find(user_id))
+            .filter(deleted_at.is_null())
+            .set((deleted_at.eq(now), purge_after.eq(purge_at)))
+            .execute(conn)?;
+        if result == 0 {
+            return Ok(None);
+        }
+        diesel::delete(password_resets)
+            .filter(reset_user.eq(user_id))
+            .execute(conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::AccountDeleted,
+            Some(user_id),
+            serde_json::json!({ "purge_after": purge_at }),
+        )?;
+        revoke_user_tokens(user_id, "account deleted", audit, conn)?;
+        Ok(Some(purge_at))
+    })
+}
+
+//takes back a deletion. with a version it is the owner cancelling through a restore
+//token, which is only good for the token_version it was made for
+pub fn restore_account(
+    user_id: i64,
+    version: Option<i32>,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<User, ServiceError> {
+    use crate::schema::users::dsl::{deleted_at, email, purge_after, users};
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        let user = users
+            .find(user_id)
+            .for_update()
+            .get_result::<User>(conn)
+            .optional()?
+            .ok_or(ServiceError::NotFound)?;
+        if version.is_some_and(|v| v != user.token_version) {
+            return Err(ServiceError::Unauthorized);
+        }
+        if user.deleted_at.is_none() {
+            return Err(ServiceError::BadRequest(
+                "the account is not deleted".to_owned(),
+            ));
+        }
+        //the purge job may already be on it
+        if user
+            .purge_after
+            .is_some_and(|at| at <= Utc::now().naive_utc())
+        {
+            return Err(ServiceError::NotFound);
+        }
+        let taken = users
+            .filter(email.eq(&user.email))
+            .filter(deleted_at.is_null())
+            .select(diesel::dsl::count_star())
+            .get_result::<i64>(conn)?;
+        if taken > 0 {
+            return Err(ServiceError::BadRequest(
+                "a new account uses the email of this one by now".to_owned(),
+            ));
+        }
+        let restored = diesel::update(users.find(user_id))
+            .set((
+                deleted_at.eq(None::<chrono::NaiveDateTime>),
+                purge_after.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .get_result::<User>(conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::AccountRestored,
+            Some(user_id),
+            serde_json::json!({ "by": if version.is_some() { "owner" } else { "admin" } }),
+        )?;
+        Ok(restored)
+    })
 }
 
+//erases the accounts past their grace period, everything pointing at them goes with
+//them. their audit events stay but lose the data that could identify the person
+pub fn purge_deleted_accounts(pool: &Pool) -> Result<usize, ServiceError> {
+    use crate::schema::audit_events::dsl as audit_events;
+    use crate::schema::users::dsl::{deleted_at, id, purge_after, users};
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        let purged = diesel::delete(users)
+            .filter(deleted_at.is_not_null())
+            .filter(purge_after.le(Utc::now().naive_utc()))
+            .returning(id)
+            .get_results::<i64>(conn)?;
+        if purged.is_empty() {
+            return Ok(0);
+        }
+        diesel::update(audit_events::audit_events)
+            .filter(audit_events::target_id.eq_any(&purged))
+            .set((
+                audit_events::data.eq(serde_json::json!({})),
+                audit_events::ip.eq(None::<String>),
+                audit_events::user_agent.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        for user in &purged {
+            AuditLog::record(
+                conn,
+                &AuditContext::default(),
+                AuditEventType::AccountPurged,
+                Some(*user),
+                serde_json::json!({}),
+            )?;
+        }
+        Ok(purged.len())
+    })
+}
+
+//a new email only becomes pending, see queue_email_change. everything happens in one
+//transaction so a failing part leaves no mails behind
 pub fn user_update(
-    user: SlimUser,
+    user_id: i64,
     updates: UserChange,
+    locale: &str,
+    audit: &AuditContext,
     pool: web::Data<Pool>,
 ) -> Result<UserChange, ServiceError> {
-    use crate::schema::users::dsl::{email, users};
+    use crate::schema::users::dsl::users;
     let mut updates = updates;
+    let pending_email = updates.email.take();
     if let Some(ref mut passwd) = updates.password {
-        *passwd = hash_password(&passwd)?;
+        *passwd = hash_password(passwd)?;
+    }
+    let credentials_changed = updates.password.is_some();
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        if let Some(ref new_email) = pending_email {
+            queue_email_change(user_id, new_email, locale, audit, conn)?;
+        }
+        let before = users.find(user_id).get_result::<UserChange>(conn)?;
+        if updates.name.is_none() && !credentials_changed {
+            return Ok(before);
+        }
+        let result = diesel::update(users.find(user_id))
+            .set(&updates)
+            .get_result::<UserChange>(conn)?;
+        //never the password itself, not even hashed
+        let mut diff = serde_json::Map::new();
+        if before.name != result.name {
+            diff.insert(
+                "name".to_owned(),
+                serde_json::json!({ "from": before.name, "to": result.name }),
+            );
+        }
+        if credentials_changed {
+            diff.insert("password".to_owned(), serde_json::json!("changed"));
+        }
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::ProfileUpdated,
+            Some(user_id),
+            diff.into(),
+        )?;
+        if credentials_changed {
+            revoke_user_tokens(user_id, "password changed", audit, conn)?;
+        }
+        Ok(result)
+    })
+}
+
+//the new address only takes effect after confirm_email_change, a confirmation
+//goes to the new address and a notice to the current one
+fn queue_email_change(
+    user_id: i64,
+    new_email: &str,
+    locale: &str,
+    audit: &AuditContext,
+    conn: &PgConnection,
+) -> Result<(), ServiceError> {
+    use crate::schema::email_changes::dsl::{email_changes, used_at};
+    use crate::schema::users::dsl::{deleted_at, email, users};
+    let user = users.find(user_id).get_result::<User>(conn)?;
+    if user.email == new_email {
+        return Err(ServiceError::BadRequest(
+            "that is already your email".to_owned(),
+        ));
+    }
+    let taken = users
+        .filter(email.eq(new_email))
+        .filter(deleted_at.is_null())
+        .select(diesel::dsl::count_star())
+        .get_result::<i64>(conn)?;
+    if taken > 0 {
+        return Err(ServiceError::BadRequest("email already in use".to_owned()));
     }
-    dbg!(&updates);
+    let raw_token = generate_token();
+    diesel::delete(email_changes)
+        .filter(crate::schema::email_changes::user_id.eq(user_id))
+        .filter(used_at.is_null())
+        .execute(conn)?;
+    diesel::insert_into(email_changes)
+        .values(&EmailChangeInsert {
+            user_id,
+            new_email: new_email.to_owned(),
+            token_hash: hash_token(&raw_token),
+            expires_at: (Utc::now() + Duration::hours(*VERIFY_TOKEN_HOURS)).naive_utc(),
+        })
+        .get_result::<EmailChange>(conn)?;
+    let link = format!("{}/confirm-email?token={}", *APP_URL, raw_token);
+    let confirm = templates::render(
+        "email_change",
+        locale,
+        new_email,
+        &[
+            ("name", &user.name),
+            ("hours", &VERIFY_TOKEN_HOURS.to_string()),
+            ("link", &link),
+        ],
+    )?;
+    let notice = templates::render(
+        "email_change_notice",
+        locale,
+        &user.email,
+        &[("name", &user.name), ("new_email", new_email)],
+    )?;
+    outbox::queue(confirm, conn)?;
+    outbox::queue(notice, conn)?;
+    AuditLog::record(
+        conn,
+        audit,
+        AuditEventType::EmailChangeRequested,
+        Some(user_id),
+        serde_json::json!({ "from": user.email, "to": new_email }),
+    )
+}
+
+//swaps in the pending address. the users old tokens stop working
+pub fn confirm_email_change(
+    user_id: i64,
+    token: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<User, ServiceError> {
+    use crate::schema::email_changes::dsl::{
+        email_changes, expires_at, new_email, token_hash, used_at,
+    };
+    use crate::schema::users;
     let conn = &pool.get().unwrap();
-    let result = diesel::update(users)
-        .filter(email.eq(user.email))
-        .set(&updates)
-        .get_result::<UserChange>(conn)?;
-    Ok(result)
+    let now = Utc::now().naive_utc();
+    conn.transaction(|| {
+        let address = diesel::update(email_changes)
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(crate::schema::email_changes::user_id.eq(user_id))
+            .filter(used_at.is_null())
+            .filter(expires_at.gt(now))
+            .set(used_at.eq(now))
+            .returning(new_email)
+            .get_result::<String>(conn)
+            .optional()?
+            .ok_or_else(|| ServiceError::BadRequest("invalid or expired token".to_owned()))?;
+        let old_address = users::table
+            .find(user_id)
+            .select(users::email)
+            .get_result::<String>(conn)?;
+        diesel::update(users::table.find(user_id))
+            .set((users::email.eq(&address), users::email_verified_at.eq(now)))
+            .execute(conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::EmailChanged,
+            Some(user_id),
+            serde_json::json!({ "from": old_address, "to": address }),
+        )?;
+        revoke_user_tokens(user_id, "email changed", audit, conn)?;
+        Ok(users::table.find(user_id).get_result::<User>(conn)?)
+    })
+}
+
+//bumps the users token version and drops their sessions, so every token
+//they hold stops working
+fn revoke_user_tokens(
+    user: i64,
+    reason: &str,
+    audit: &AuditContext,
+    conn: &PgConnection,
+) -> Result<(), ServiceError> {
+    use crate::schema::api_tokens::dsl as pat;
+    use crate::schema::sessions::dsl::{sessions, user_id};
+    use crate::schema::users::dsl::{token_version, users};
+    diesel::update(users.find(user))
+        .set(token_version.eq(token_version + 1))
+        .execute(conn)?;
+    let ended = diesel::delete(sessions.filter(user_id.eq(user))).execute(conn)?;
+    //personal access tokens don't carry the token version, so they go too
+    let deleted = diesel::delete(pat::api_tokens.filter(pat::user_id.eq(user))).execute(conn)?;
+    AuditLog::record(
+        conn,
+        audit,
+        AuditEventType::TokensRevoked,
+        Some(user),
+        serde_json::json!({ "reason": reason, "sessions": ended, "api_tokens": deleted }),
+    )
 }
 
 pub fn find_by(data: FindBy, pool: web::Data<Pool>) -> Result<User, ServiceError> {
-    use crate::schema::users::dsl::{email, id, users};
+    use crate::schema::users::dsl::{deleted_at, email, id, public_id, users};
     let conn = &pool.get().unwrap();
     let mut user;
     match data {
         FindBy::Email(e) => {
-            user = users.filter(email.eq(&e)).get_results::<User>(conn)?;
+            user = users
+                .filter(email.eq(&e))
+                .filter(deleted_at.is_null())
+                .get_results::<User>(conn)?;
         }
         FindBy::Id(v) => {
             user = users.filter(id.eq(&v)).get_results::<User>(conn)?;
         }
+        FindBy::PublicId(v) => {
+            user = users
+                .filter(public_id.eq(&v))
+                .filter(deleted_at.is_null())
+                .get_results::<User>(conn)?;
+        }
     }
     if let Some(u) = user.pop() {
         return Ok(u);
@@ -74,46 +752,851 @@ pub fn find_by(data: FindBy, pool: web::Data<Pool>) -> Result<User, ServiceError
     Err(ServiceError::NotFound)
 }
 
-//route handles helper function
-pub fn change_account(user_id: i64, pool: web::Data<Pool>) -> Result<String, ServiceError> {
-    use crate::schema::users::dsl::{clearance, users};
+//like FindBy::PublicId, but deleted accounts in their grace period are found too. only for restoring them
+pub fn find_with_deleted(
+    user_public_id: Uuid,
+    pool: web::Data<Pool>,
+) -> Result<User, ServiceError> {
+    use crate::schema::users::dsl::{public_id, users};
     let conn = &pool.get().unwrap();
+    users
+        .filter(public_id.eq(user_public_id))
+        .first::<User>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound)
+}
 
-    let mut return_string = String::new();
-    let target = users.find(user_id);
-    let current_clearance = target.select(clearance).get_result::<bool>(conn)?;
-    if current_clearance {
-        return_string.push_str("change account type from admin to normal user");
-        let _ = diesel::update(target)
-            .set(clearance.eq_all(false))
-            .execute(conn)?;
+//sets the users roles to exactly the given one (none for None), so sending the
+//same assignment twice or from two admins at once ends the same way
+pub fn assign_role(
+    target: i64,
+    role: Option<&str>,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<Access, ServiceError> {
+    use crate::schema::{roles, user_roles, users};
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        users::table
+            .find(target)
+            .select(users::id)
+            .for_update()
+            .get_result::<i64>(conn)
+            .optional()?
+            .ok_or(ServiceError::NotFound)?;
+        let new_role = match role {
+            Some(name) => Some(
+                roles::table
+                    .filter(roles::name.eq(name))
+                    .select(roles::id)
+                    .get_result::<i32>(conn)
+                    .optional()?
+                    .ok_or_else(|| ServiceError::BadRequest(format!("unknown role {}", name)))?,
+            ),
+            None => None,
+        };
+        //admin rows are locked so two demotions can not both see another admin left
+        let admins = user_roles::table
+            .inner_join(roles::table)
+            .filter(roles::name.eq(ADMIN_ROLE))
+            .select(user_roles::user_id)
+            .for_update()
+            .load::<i64>(conn)?;
+        if admins.contains(&target)
+            && role != Some(ADMIN_ROLE)
+            && admins.iter().all(|admin| *admin == target)
+        {
+            return Err(ServiceError::Forbidden(if audit.actor_id == Some(target) {
+                "you can not demote yourself while you are the only admin".to_owned()
+            } else {
+                "can not remove the last admin".to_owned()
+            }));
+        }
+        let before = access_of(target, conn)?;
+        if before.roles.iter().map(String::as_str).eq(role) {
+            return Ok(before);
+        }
+        diesel::delete(user_roles::table.filter(user_roles::user_id.eq(target))).execute(conn)?;
+        if let Some(role_id) = new_role {
+            diesel::insert_into(user_roles::table)
+                .values(&UserRoleInsert {
+                    user_id: target,
+                    role_id,
+                })
+                .execute(conn)?;
+        }
+        let after = access_of(target, conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::RoleChanged,
+            Some(target),
+            serde_json::json!({ "from": before.roles, "to": after.roles }),
+        )?;
+        //tokens carry the old roles
+        revoke_user_tokens(target, "roles changed", audit, conn)?;
+        Ok(after)
+    })
+}
+
+//roles of the user and every permission they grant
+pub fn load_access(user: i64, pool: web::Data<Pool>) -> Result<Access, ServiceError> {
+    let conn = &pool.get().unwrap();
+    access_of(user, conn)
+}
+
+pub(crate) fn access_of(user: i64, conn: &PgConnection) -> Result<Access, ServiceError> {
+    use crate::schema::{permissions, role_permissions, roles, user_roles};
+    let roles = user_roles::table
+        .inner_join(roles::table)
+        .filter(user_roles::user_id.eq(user))
+        .select(roles::name)
+        .order(roles::name)
+        .load::<String>(conn)?;
+    let permissions = user_roles::table
+        .inner_join(roles::table.inner_join(role_permissions::table.inner_join(permissions::table)))
+        .filter(user_roles::user_id.eq(user))
+        .select(permissions::name)
+        .distinct()
+        .order(permissions::name)
+        .load::<String>(conn)?;
+    Ok(Access { roles, permissions })
+}
+
+const DEFAULT_USERS_PAGE: i64 = 50;
+const MAX_USERS_PAGE: i64 = 200;
+
+//keyset pagination, the cursor is the sort key of the last user on the page
+//so pages stay stable while users sign up
+pub fn get_users(query: UserQuery, pool: web::Data<Pool>) -> Result<UserPage, ServiceError> {
+    use crate::schema::users::dsl::{created_at, id};
+    let conn = &pool.get().unwrap();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_USERS_PAGE)
+        .clamp(1, MAX_USERS_PAGE);
+    let total = if query.count {
+        Some(filtered_users(&query).count().get_result::<i64>(conn)?)
+    } else {
+        None
+    };
+    let mut items = filtered_users(&query);
+    if let Some(ref cursor) = query.cursor {
+        let invalid = || ServiceError::BadRequest("invalid cursor".to_owned());
+        items = match query.sort {
+            UserSort::IdAsc => items.filter(id.gt(cursor.parse::<i64>().map_err(|_| invalid())?)),
+            UserSort::IdDesc => items.filter(id.lt(cursor.parse::<i64>().map_err(|_| invalid())?)),
+            UserSort::CreatedAsc | UserSort::CreatedDesc => {
+                let (at, after) = parse_created_cursor(cursor).ok_or_else(invalid)?;
+                if query.sort == UserSort::CreatedAsc {
+                    items.filter(created_at.gt(at).or(created_at.eq(at).and(id.gt(after))))
+                } else {
+                    items.filter(created_at.lt(at).or(created_at.eq(at).and(id.lt(after))))
+                }
+            }
+        };
+    }
+    items = match query.sort {
+        UserSort::IdAsc => items.order(id.asc()),
+        UserSort::IdDesc => items.order(id.desc()),
+        UserSort::CreatedAsc => items.order((created_at.asc(), id.asc())),
+        UserSort::CreatedDesc => items.order((created_at.desc(), id.desc())),
+    };
+    //one extra row tells if there is another page
+    let mut page = items.limit(limit + 1).load::<User>(conn)?;
+    let next_cursor = if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        page.last().map(|last| match query.sort {
+            UserSort::IdAsc | UserSort::IdDesc => last.id.to_string(),
+            UserSort::CreatedAsc | UserSort::CreatedDesc => format!(
+                "{}_{}",
+                last.created_at.and_utc().timestamp_micros(),
+                last.id
+            ),
+        })
+    } else {
+        None
+    };
+    let mut assigned = roles_of(page.iter().map(|u| u.id).collect(), conn)?;
+    Ok(UserPage {
+        users: page
+            .into_iter()
+            .map(|u| {
+                let roles = assigned.remove(&u.id).unwrap_or_default();
+                SlimUser::new(u, roles)
+            })
+            .collect(),
+        next_cursor,
+        total,
+    })
+}
+
+const DEFAULT_SEARCH_RESULTS: i64 = 20;
+const MAX_SEARCH_RESULTS: i64 = 100;
+//how close a word has to be to count, 1 is the same word
+const SEARCH_MIN_SCORE: f32 = 0.5;
+
+//typo tolerant search over name and email. the trigram and full text indexes from
+//the add_users_search migration find the candidates, then every query word is
+//scored against the closest word of the user (trigrams, levenshtein for swapped
+//letters, prefixes) and the average is the rank
+pub fn search_users(
+    q: &str,
+    limit: Option<i64>,
+    pool: web::Data<Pool>,
+) -> Result<Vec<SearchRow>, ServiceError> {
+    use diesel::sql_types::{Array, BigInt, Float, Text};
+    let words: Vec<String> = q
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .take(8)
+        .map(|w| w.chars().take(64).collect())
+        .collect();
+    if words.is_empty() {
+        return Err(ServiceError::BadRequest(
+            "q needs at least one letter or digit".to_owned(),
+        ));
+    }
+    //every word as a prefix, the words are alphanumeric so this is valid tsquery syntax
+    let prefixes = words
+        .iter()
+        .map(|w| format!("{}:*", w))
+        .collect::<Vec<_>>()
+        .join(" & ");
+    let limit = limit
+        .unwrap_or(DEFAULT_SEARCH_RESULTS)
+        .clamp(1, MAX_SEARCH_RESULTS);
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        //the default 0.6 misses most typos, the ranking sorts out the noise
+        diesel::sql_query("SET LOCAL pg_trgm.word_similarity_threshold = 0.3").execute(conn)?;
+        Ok(diesel::sql_query(
+            "SELECT c.public_id, c.name, c.email, c.created_at, s.score, s.matched
+        FROM (
+            SELECT u.id, u.public_id, u.name, u.email, u.created_at
+            FROM users u
+            WHERE u.deleted_at IS NULL
+                AND (to_tsvector('simple', u.name || ' ' || regexp_replace(u.email, '[^[:alnum:]]+', ' ', 'g'))
+                    @@ to_tsquery('simple', $3)
+                    OR $1 <% lower(u.name || ' ' || u.email))
+        ) c
+        CROSS JOIN LATERAL (
+            SELECT avg(best.score)::real AS score,
+                coalesce(array_agg(best.word) FILTER (WHERE best.score >= $5), '{}') AS matched
+            FROM unnest($2::text[]) qw
+            CROSS JOIN LATERAL (
+                SELECT fw AS word,
+                    CASE WHEN fw LIKE qw || '%' THEN 0.5 + 0.5 * length(qw)::real / length(fw)
+                        ELSE greatest(similarity(qw, fw), 1 - levenshtein(qw, fw)::real / greatest(length(qw), length(fw)))
+                    END AS score
+                FROM regexp_split_to_table(lower(c.name || ' ' || c.email), '[^[:alnum:]]+') fw
+                WHERE fw <> ''
+                ORDER BY score DESC
+                LIMIT 1
+            ) best
+        ) s
+        WHERE s.score >= $5
+        ORDER BY s.score DESC, c.id
+        LIMIT $4",
+        )
+        .bind::<Text, _>(words.join(" "))
+        .bind::<Array<Text>, _>(&words)
+        .bind::<Text, _>(prefixes)
+        .bind::<BigInt, _>(limit)
+        .bind::<Float, _>(SEARCH_MIN_SCORE)
+        .load::<SearchRow>(conn)?)
+    })
+}
+
+//the filters of GET /users, shared by the page and the total count
+fn filtered_users(query: &UserQuery) -> crate::schema::users::BoxedQuery<'static, Pg> {
+    use crate::schema::{roles, user_roles, users};
+    let mut items = users::table.into_boxed();
+    items = if query.deleted {
+        items.filter(users::deleted_at.is_not_null())
     } else {
-        return_string.push_str("change account type from normal user to admin");
-        let _ = diesel::update(target)
-            .set(clearance.eq_all(true))
+        items.filter(users::deleted_at.is_null())
+    };
+    if let Some(ref role) = query.role {
+        items = items.filter(
+            users::id.eq_any(
+                user_roles::table
+                    .inner_join(roles::table)
+                    .filter(roles::name.eq(role.clone()))
+                    .select(user_roles::user_id),
+            ),
+        );
+    }
+    if let Some(from) = query.created_from {
+        items = items.filter(users::created_at.ge(from));
+    }
+    if let Some(to) = query.created_to {
+        items = items.filter(users::created_at.lt(to));
+    }
+    if let Some(ref q) = query.q {
+        //the search text is matched literally
+        let pattern = format!(
+            "%{}%",
+            q.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        items = items.filter(
+            users::name
+                .ilike(pattern.clone())
+                .or(users::email.ilike(pattern)),
+        );
+    }
+    items
+}
+
+//"<created_at in microseconds>_<id>"
+fn parse_created_cursor(cursor: &str) -> Option<(chrono::NaiveDateTime, i64)> {
+    let (micros, id) = cursor.split_once('_')?;
+    let at = Utc.timestamp_micros(micros.parse().ok()?).single()?;
+    Some((at.naive_utc(), id.parse().ok()?))
+}
+
+fn roles_of(
+    user_ids: Vec<i64>,
+    conn: &PgConnection,
+) -> Result<HashMap<i64, Vec<String>>, ServiceError> {
+    use crate::schema::{roles, user_roles};
+    Ok(user_roles::table
+        .inner_join(roles::table)
+        .filter(user_roles::user_id.eq_any(user_ids))
+        .select((user_roles::user_id, roles::name))
+        .order(roles::name)
+        .load::<(i64, String)>(conn)?
+        .into_iter()
+        .fold(HashMap::new(), |mut map, (user, role)| {
+            map.entry(user).or_insert_with(Vec::new).push(role);
+            map
+        }))
+}
+
+const MAX_AVATAR_URL: usize = 2048;
+
+//users without a profiles row have the defaults
+pub fn profile_settings(
+    user_id: i64,
+    pool: web::Data<Pool>,
+) -> Result<ProfileSettings, ServiceError> {
+    use crate::schema::profiles::dsl::profiles;
+    let conn = &pool.get().unwrap();
+    Ok(profiles
+        .find(user_id)
+        .get_result::<Profile>(conn)
+        .optional()?
+        .map(ProfileSettings::from)
+        .unwrap_or_default())
+}
+
+pub fn update_profile(
+    user_id: i64,
+    change: ProfileChange,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<ProfileSettings, ServiceError> {
+    use crate::schema::profiles::dsl::profiles;
+    let avatar_url = match change.avatar_url.as_deref().map(str::trim) {
+        Some("") => Some(None),
+        Some(url) if url.starts_with("https://") && url.len() <= MAX_AVATAR_URL => {
+            Some(Some(url.to_owned()))
+        }
+        Some(_) => {
+            return Err(ServiceError::BadRequest(
+                "avatar_url has to be an https url".to_owned(),
+            ))
+        }
+        None => None,
+    };
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        let before = profiles
+            .find(user_id)
+            .for_update()
+            .get_result::<Profile>(conn)
+            .optional()?
+            .map(ProfileSettings::from)
+            .unwrap_or_default();
+        let mut after = before.clone();
+        if let Some(url) = avatar_url {
+            after.avatar_url = url;
+        }
+        let wanted = &change.visibility;
+        let visibility = &mut after.visibility;
+        visibility.email = wanted.email.unwrap_or(visibility.email);
+        visibility.name = wanted.name.unwrap_or(visibility.name);
+        visibility.joined = wanted.joined.unwrap_or(visibility.joined);
+        visibility.avatar = wanted.avatar.unwrap_or(visibility.avatar);
+        if after == before {
+            return Ok(after);
+        }
+        let row = ProfileInsert::new(user_id, &after);
+        diesel::insert_into(profiles)
+            .values(&row)
+            .on_conflict(crate::schema::profiles::user_id)
+            .do_update()
+            .set(&row)
             .execute(conn)?;
+        let mut diff = serde_json::Map::new();
+        if before.avatar_url != after.avatar_url {
+            diff.insert("avatar_url".to_owned(), serde_json::json!("changed"));
+        }
+        let fields = [
+            ("email", before.visibility.email, after.visibility.email),
+            ("name", before.visibility.name, after.visibility.name),
+            ("joined", before.visibility.joined, after.visibility.joined),
+            ("avatar", before.visibility.avatar, after.visibility.avatar),
+        ];
+        for (field, from, to) in fields.iter().filter(|(_, from, to)| from != to) {
+            diff.insert(
+                format!("{}_visibility", field),
+                serde_json::json!({ "from": from, "to": to }),
+            );
+        }
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::ProfileUpdated,
+            Some(user_id),
+            diff.into(),
+        )?;
+        Ok(after)
+    })
+}
+
+//also queues the email verification mail
+pub fn insert_user(
+    user_data: UserData,
+    locale: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<SlimUser, ServiceError> {
+    use crate::schema::users::dsl::users;
+    let password = hash_password(&user_data.password)?;
+
+    let new_user = UserInsert::from_details(user_data.name, user_data.email, password);
+
+    let conn = &pool.get().unwrap();
+    let inserted_user = conn.transaction::<_, ServiceError, _>(|| {
+        let inserted_user = diesel::insert_into(users)
+            .values(&new_user)
+            .get_result::<User>(conn)?;
+        queue_verification(&inserted_user, locale, conn)?;
+        AuditLog::record(
+            conn,
+            &audit.as_actor(inserted_user.id),
+            AuditEventType::Registered,
+            Some(inserted_user.id),
+            serde_json::json!({ "email": inserted_user.email }),
+        )?;
+        Ok(inserted_user)
+    })?;
+    Ok(SlimUser::new(inserted_user, Vec::new()))
+}
+
+//marks the address the token was sent to as verified
+pub fn verify_email(
+    token: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(), ServiceError> {
+    use crate::schema::email_verifications::dsl::{
+        email, email_verifications, expires_at, token_hash, used_at, user_id,
+    };
+    use crate::schema::users;
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    let invalid = || ServiceError::BadRequest("invalid or expired token".to_owned());
+    conn.transaction(|| {
+        let (target, address) = diesel::update(email_verifications)
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(used_at.is_null())
+            .filter(expires_at.gt(now))
+            .set(used_at.eq(now))
+            .returning((user_id, email))
+            .get_result::<(i64, String)>(conn)
+            .optional()?
+            .ok_or_else(invalid)?;
+        //the user may have changed the email since the mail went out
+        let verified = diesel::update(users::table.find(target))
+            .filter(users::email.eq(&address))
+            .set(users::email_verified_at.eq(now))
+            .execute(conn)?;
+        if verified == 0 {
+            return Err(invalid());
+        }
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::EmailVerified,
+            Some(target),
+            serde_json::json!({ "email": address }),
+        )
+    })
+}
+
+//false if there is nothing to verify for that email or a mail just went out. both answer
+//the same as a new mail so the route does not tell which addresses have an unverified account
+pub fn resend_verification(
+    user_email: &str,
+    locale: &str,
+    pool: web::Data<Pool>,
+) -> Result<bool, ServiceError> {
+    use crate::schema::email_verifications::dsl::{created_at, email_verifications, user_id};
+    use crate::schema::users::dsl::{deleted_at, email, users};
+    let conn = &pool.get().unwrap();
+    let user = match users
+        .filter(email.eq(user_email))
+        .filter(deleted_at.is_null())
+        .first::<User>(conn)
+        .optional()?
+    {
+        Some(u) if u.email_verified_at.is_none() => u,
+        _ => return Ok(false),
+    };
+    let last_sent = email_verifications
+        .filter(user_id.eq(user.id))
+        .select(created_at)
+        .order(created_at.desc())
+        .first::<chrono::NaiveDateTime>(conn)
+        .optional()?;
+    if let Some(sent) = last_sent {
+        if sent + Duration::seconds(VERIFY_RESEND_SECONDS) > Utc::now().naive_utc() {
+            return Ok(false);
+        }
     }
-    Ok(return_string)
+    conn.transaction(|| queue_verification(&user, locale, conn))?;
+    Ok(true)
+}
+
+//new verification token for the users current email, older ones stop working
+fn queue_verification(user: &User, locale: &str, conn: &PgConnection) -> Result<(), ServiceError> {
+    use crate::schema::email_verifications::dsl::{email_verifications, used_at, user_id};
+    let raw_token = generate_token();
+    diesel::delete(email_verifications)
+        .filter(user_id.eq(user.id))
+        .filter(used_at.is_null())
+        .execute(conn)?;
+    diesel::insert_into(email_verifications)
+        .values(&EmailVerificationInsert {
+            user_id: user.id,
+            email: user.email.clone(),
+            token_hash: hash_token(&raw_token),
+            expires_at: (Utc::now() + Duration::hours(*VERIFY_TOKEN_HOURS)).naive_utc(),
+        })
+        .get_result::<EmailVerification>(conn)?;
+    let link = format!("{}/verify-email?token={}", *APP_URL, raw_token);
+    let mail = templates::render(
+        "verify_email",
+        locale,
+        &user.email,
+        &[
+            ("name", &user.name),
+            ("hours", &VERIFY_TOKEN_HOURS.to_string()),
+            ("link", &link),
+        ],
+    )?;
+    outbox::queue(mail, conn)
 }
 
-pub fn get_all_users(pool: web::Data<Pool>) -> Result<Vec<SlimUser>, ServiceError> {
+//mfa is true if the login passed the second factor
+pub fn create_session(
+    user_id: i64,
+    client: ClientInfo,
+    mfa: bool,
+    pool: web::Data<Pool>,
+) -> Result<Session, ServiceError> {
+    use crate::schema::sessions::dsl::sessions;
+    let new_session = SessionInsert {
+        id: Uuid::new_v4(),
+        user_id,
+        expires_at: (Utc::now() + Duration::days(*SESSION_DAYS)).naive_utc(),
+        user_agent: client.user_agent,
+        ip: client.ip,
+        mfa,
+    };
+    let conn = &pool.get().unwrap();
+    Ok(diesel::insert_into(sessions)
+        .values(&new_session)
+        .get_result::<Session>(conn)?)
+}
+
+//true if the session is still alive and the token is not older than the
+//users last credential change, also marks the session as seen
+pub fn touch_session(
+    session_id: Uuid,
+    session_user: i64,
+    version: i32,
+    pool: web::Data<Pool>,
+) -> Result<bool, ServiceError> {
+    use crate::schema::sessions::dsl::{expires_at, id, last_seen_at, sessions, user_id};
+    use crate::schema::users;
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    let current_user = users::table
+        .select(users::id)
+        .filter(users::id.eq(session_user))
+        .filter(users::token_version.eq(version));
+    let result = diesel::update(sessions)
+        .filter(id.eq(session_id))
+        .filter(user_id.eq_any(current_user))
+        .filter(expires_at.gt(now))
+        .set(last_seen_at.eq(now))
+        .execute(conn)?;
+    Ok(result > 0)
+}
+
+//checks the password again and hands out a short lived step-up token bound to the session
+pub fn reauthenticate(
+    session_user: i64,
+    session_id: Uuid,
+    passwd: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(String, chrono::NaiveDateTime), ServiceError> {
+    use crate::schema::sessions::dsl::{reauth_expires_at, reauth_token_hash, sessions};
     use crate::schema::users::dsl::users;
     let conn = &pool.get().unwrap();
-    let all_users = users.load::<User>(conn)?;
-    Ok(all_users.into_iter().map(|u| u.into()).collect())
+    let user = users.find(session_user).get_result::<User>(conn)?;
+    check_password(
+        &user,
+        passwd,
+        AuditEventType::ReauthFailed,
+        serde_json::json!({ "via": "reauth" }),
+        audit,
+        conn,
+    )?;
+    let raw_token = generate_token();
+    let expires = (Utc::now() + Duration::minutes(*REAUTH_MINUTES)).naive_utc();
+    diesel::update(sessions.find(session_id))
+        .set((
+            reauth_token_hash.eq(hash_token(&raw_token)),
+            reauth_expires_at.eq(expires),
+        ))
+        .execute(conn)?;
+    Ok((raw_token, expires))
 }
 
-pub fn insert_user(user_data: UserData, pool: web::Data<Pool>) -> Result<SlimUser, ServiceError> {
+//ok if the password is right or the step-up token belongs to the session and is still fresh
+pub fn check_reauth(
+    session_user: i64,
+    session_id: Uuid,
+    current_password: Option<&str>,
+    reauth_token: Option<&str>,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(), ServiceError> {
+    use crate::schema::sessions::dsl::{
+        id, reauth_expires_at, reauth_token_hash, sessions, user_id,
+    };
     use crate::schema::users::dsl::users;
-    let password = crate::utils::hash_password(&user_data.password)?;
+    let conn = &pool.get().unwrap();
+    if let Some(passwd) = current_password {
+        let user = users.find(session_user).get_result::<User>(conn)?;
+        let data = serde_json::json!({ "via": "current_password" });
+        match check_password(
+            &user,
+            passwd,
+            AuditEventType::ReauthFailed,
+            data,
+            audit,
+            conn,
+        ) {
+            Ok(()) => return Ok(()),
+            //a wrong password can still come with a good reauth_token
+            Err(ServiceError::Unauthorized) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    if let Some(token) = reauth_token {
+        let fresh = sessions
+            .filter(id.eq(session_id))
+            .filter(user_id.eq(session_user))
+            .filter(reauth_token_hash.eq(hash_token(token)))
+            .filter(reauth_expires_at.gt(Utc::now().naive_utc()))
+            .select(diesel::dsl::count_star())
+            .get_result::<i64>(conn)?;
+        if fresh > 0 {
+            return Ok(());
+        }
+    }
+    Err(ServiceError::ReauthenticationRequired)
+}
 
-    let new_user = UserInsert::from_details(user_data.name, user_data.email, password);
+pub fn delete_session(
+    session_id: Uuid,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<bool, ServiceError> {
+    use crate::schema::sessions::dsl::{id, sessions};
+    let conn = &pool.get().unwrap();
+    let result = diesel::delete(sessions.filter(id.eq(session_id))).execute(conn)?;
+    AuditLog::record(
+        conn,
+        audit,
+        AuditEventType::Logout,
+        audit.actor_id,
+        serde_json::json!({ "session": session_id }),
+    )?;
+    Ok(result > 0)
+}
+
+//returns the raw token, only its hash is stored
+pub fn issue_refresh_token(
+    session: &Session,
+    pool: web::Data<Pool>,
+) -> Result<(String, RefreshToken), ServiceError> {
+    use crate::schema::refresh_tokens::dsl::refresh_tokens;
+    let raw_token = generate_token();
+    let new_token = RefreshTokenInsert {
+        session_id: session.id,
+        token_hash: hash_token(&raw_token),
+        expires_at: session.expires_at,
+    };
+    let conn = &pool.get().unwrap();
+    let stored = diesel::insert_into(refresh_tokens)
+        .values(&new_token)
+        .get_result::<RefreshToken>(conn)?;
+    Ok((raw_token, stored))
+}
+
+//trades a refresh token for its session, the token can not be used again.
+//presenting an already used token revokes the whole session
+pub fn redeem_refresh_token(
+    token: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<Session, ServiceError> {
+    use crate::schema::refresh_tokens::dsl::{refresh_tokens, token_hash, used_at};
+    use crate::schema::sessions::dsl::{expires_at, last_seen_at, sessions};
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    let stored = refresh_tokens
+        .filter(token_hash.eq(hash_token(token)))
+        .first::<RefreshToken>(conn)
+        .optional()?
+        .ok_or(ServiceError::Unauthorized)?;
+    let marked = diesel::update(refresh_tokens.find(stored.id))
+        .filter(used_at.is_null())
+        .set(used_at.eq(now))
+        .execute(conn)?;
+    if marked == 0 {
+        let revoked = diesel::delete(sessions.find(stored.session_id))
+            .returning(crate::schema::sessions::user_id)
+            .get_result::<i64>(conn)
+            .optional()?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::RefreshTokenReused,
+            revoked,
+            serde_json::json!({ "session": stored.session_id }),
+        )?;
+        return Err(ServiceError::Unauthorized);
+    }
+    if stored.expires_at <= now {
+        return Err(ServiceError::Unauthorized);
+    }
+    let session = diesel::update(sessions.find(stored.session_id))
+        .filter(expires_at.gt(now))
+        .set((
+            expires_at.eq(now + Duration::days(*SESSION_DAYS)),
+            last_seen_at.eq(now),
+        ))
+        .get_result::<Session>(conn)
+        .optional()?;
+    session.ok_or(ServiceError::Unauthorized)
+}
+
+//queues a reset link for the user with that email, false if nobody uses it.
+//older unused tokens of the user stop working
+pub fn create_password_reset(
+    user_email: &str,
+    locale: &str,
+    pool: web::Data<Pool>,
+) -> Result<bool, ServiceError> {
+    use crate::schema::password_resets::dsl::{password_resets, used_at, user_id};
+    use crate::schema::users::dsl::{deleted_at, email, users};
+    let conn = &pool.get().unwrap();
+    let user = match users
+        .filter(email.eq(user_email))
+        .filter(deleted_at.is_null())
+        .first::<User>(conn)
+        .optional()?
+    {
+        Some(u) => u,
+        None => return Ok(false),
+    };
+    let raw_token = generate_token();
+    let new_reset = PasswordResetInsert {
+        user_id: user.id,
+        token_hash: hash_token(&raw_token),
+        expires_at: (Utc::now() + Duration::minutes(*RESET_TOKEN_MINUTES)).naive_utc(),
+    };
+    conn.transaction::<_, ServiceError, _>(|| {
+        diesel::delete(password_resets)
+            .filter(user_id.eq(user.id))
+            .filter(used_at.is_null())
+            .execute(conn)?;
+        diesel::insert_into(password_resets)
+            .values(&new_reset)
+            .get_result::<PasswordReset>(conn)?;
+        let link = format!("{}/reset-password?token={}", *APP_URL, raw_token);
+        let mail = templates::render(
+            "password_reset",
+            locale,
+            &user.email,
+            &[
+                ("name", &user.name),
+                ("minutes", &RESET_TOKEN_MINUTES.to_string()),
+                ("link", &link),
+            ],
+        )?;
+        outbox::queue(mail, conn)
+    })?;
+    Ok(true)
+}
 
+//uses up the reset token, sets the new password and logs the user out everywhere
+pub fn reset_password(
+    token: &str,
+    new_password: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(), ServiceError> {
+    use crate::schema::password_resets::dsl::{
+        expires_at, password_resets, token_hash, used_at, user_id,
+    };
+    use crate::schema::users::dsl::{password, users};
+    let new_hash = hash_password(new_password)?;
     let conn = &pool.get().unwrap();
-    let inserted_user = diesel::insert_into(users)
-        .values(&new_user)
-        .get_result::<User>(conn)?;
-    Ok(inserted_user.into())
+    let now = Utc::now().naive_utc();
+    conn.transaction(|| {
+        let target = diesel::update(password_resets)
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(used_at.is_null())
+            .filter(expires_at.gt(now))
+            .set(used_at.eq(now))
+            .returning(user_id)
+            .get_result::<i64>(conn)
+            .optional()?
+            .ok_or_else(|| ServiceError::BadRequest("invalid or expired token".to_owned()))?;
+        diesel::update(users.find(target))
+            .set(password.eq(new_hash))
+            .execute(conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::PasswordReset,
+            Some(target),
+            serde_json::json!({}),
+        )?;
+        revoke_user_tokens(target, "password reset", audit, conn)
+    })
 }
 
 use crate::models::user::RawUser;
@@ -140,3 +1623,811 @@ pub fn test_raw(pool: &web::Data<Pool>) -> Result<Vec<RawUser>, ServiceError> {
     .bind::<Integer, _>(4)
     .load(conn)?)
 }
+
+//stores a new pending totp secret, 2fa is only on after confirm_totp
+pub fn start_totp(user_id: i64, pool: web::Data<Pool>) -> Result<(User, String), ServiceError> {
+    use crate::schema::users::dsl::{totp_enabled_at, totp_secret, users};
+    let conn = &pool.get().unwrap();
+    let user = users.find(user_id).get_result::<User>(conn)?;
+    if user.totp_enabled_at.is_some() {
+        return Err(ServiceError::BadRequest(
+            "two factor authentication is already on".to_owned(),
+        ));
+    }
+    let secret = crate::totp::generate_secret();
+    diesel::update(users.find(user_id))
+        .filter(totp_enabled_at.is_null())
+        .set(totp_secret.eq(&secret))
+        .execute(conn)?;
+    Ok((user, secret))
+}
+
+//turns 2fa on once the users app proved it has the secret, the returned
+//recovery codes are only stored hashed
+pub fn confirm_totp(
+    user_id: i64,
+    code: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<Vec<String>, ServiceError> {
+    use crate::schema::recovery_codes::dsl::recovery_codes;
+    use crate::schema::users::dsl::{totp_enabled_at, totp_last_step, users};
+    let conn = &pool.get().unwrap();
+    let user = users.find(user_id).get_result::<User>(conn)?;
+    if user.totp_enabled_at.is_some() {
+        return Err(ServiceError::BadRequest(
+            "two factor authentication is already on".to_owned(),
+        ));
+    }
+    let secret = user
+        .totp_secret
+        .ok_or_else(|| ServiceError::BadRequest("start with POST /user/2fa".to_owned()))?;
+    let step = crate::totp::verify(&secret, code, crate::totp::current_step())
+        .ok_or_else(|| ServiceError::BadRequest("wrong code".to_owned()))?;
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            let raw = hex::encode(rand::random::<[u8; 5]>());
+            format!("{}-{}", &raw[..5], &raw[5..])
+        })
+        .collect();
+    let new_codes: Vec<RecoveryCodeInsert> = codes
+        .iter()
+        .map(|code| RecoveryCodeInsert {
+            user_id,
+            code_hash: hash_token(code),
+        })
+        .collect();
+    conn.transaction::<_, ServiceError, _>(|| {
+        diesel::update(users.find(user_id))
+            .set((
+                totp_enabled_at.eq(Utc::now().naive_utc()),
+                totp_last_step.eq(step),
+            ))
+            .execute(conn)?;
+        diesel::delete(recovery_codes)
+            .filter(crate::schema::recovery_codes::user_id.eq(user_id))
+            .execute(conn)?;
+        diesel::insert_into(recovery_codes)
+            .values(&new_codes)
+            .execute(conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::TwoFactorEnabled,
+            Some(user_id),
+            serde_json::json!({}),
+        )
+    })?;
+    Ok(codes)
+}
+
+//what a password login of a user with 2fa gets, the id goes into the mfa token
+pub fn start_mfa_challenge(user_id: i64, pool: web::Data<Pool>) -> Result<Uuid, ServiceError> {
+    use crate::schema::mfa_challenges::dsl::{id, mfa_challenges};
+    let conn = &pool.get().unwrap();
+    Ok(diesel::insert_into(mfa_challenges)
+        .values(&MfaChallengeInsert {
+            id: Uuid::new_v4(),
+            user_id,
+            expires_at: (Utc::now() + Duration::minutes(*MFA_TOKEN_MINUTES)).naive_utc(),
+        })
+        .returning(id)
+        .get_result::<Uuid>(conn)?)
+}
+
+//second login step, takes a totp code or an unused recovery code.
+//a challenge takes MFA_CHALLENGE_FAILURES wrong codes and works once. a user with
+//MFA_USER_FAILURES wrong codes within MFA_LOCK_MINUTES has to wait, whatever the challenge
+pub fn verify_second_factor(
+    challenge: Uuid,
+    user_id: i64,
+    version: i32,
+    code: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<User, ServiceError> {
+    use crate::schema::mfa_challenges::dsl::{created_at, expires_at, failures, mfa_challenges};
+    use crate::schema::recovery_codes::dsl::{code_hash, recovery_codes, used_at};
+    use crate::schema::users::dsl::{totp_last_step, users};
+    let conn = &pool.get().unwrap();
+    let user = users.find(user_id).get_result::<User>(conn)?;
+    if user.token_version != version || user.totp_enabled_at.is_none() {
+        return Err(ServiceError::Unauthorized);
+    }
+    let now = Utc::now().naive_utc();
+    let recent = mfa_challenges
+        .filter(crate::schema::mfa_challenges::user_id.eq(user_id))
+        .filter(created_at.gt(now - Duration::minutes(MFA_LOCK_MINUTES)))
+        .select(diesel::dsl::sum(failures))
+        .first::<Option<i64>>(conn)?
+        .unwrap_or(0);
+    if recent >= MFA_USER_FAILURES {
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::SecondFactorFailed,
+            Some(user_id),
+            serde_json::json!({ "reason": "locked" }),
+        )?;
+        return Err(ServiceError::TooManyRequests(format!(
+            "too many wrong codes, try again in {} minutes",
+            MFA_LOCK_MINUTES
+        )));
+    }
+    //the try counts before the code is checked, so parallel guesses can not go past the limit
+    let attempt = diesel::update(mfa_challenges.find(challenge))
+        .filter(crate::schema::mfa_challenges::user_id.eq(user_id))
+        .filter(crate::schema::mfa_challenges::used_at.is_null())
+        .filter(expires_at.gt(now))
+        .filter(failures.lt(MFA_CHALLENGE_FAILURES))
+        .set(failures.eq(failures + 1))
+        .returning(failures)
+        .get_result::<i32>(conn)
+        .optional()?
+        .ok_or(ServiceError::Unauthorized)?;
+    let secret = user.totp_secret.clone().unwrap_or_default();
+    let passed = if let Some(step) = crate::totp::verify(&secret, code, crate::totp::current_step())
+    {
+        //only steps after the last accepted one, so a code works once
+        let accepted = diesel::update(users.find(user_id))
+            .filter(totp_last_step.is_null().or(totp_last_step.lt(step)))
+            .set(totp_last_step.eq(step))
+            .execute(conn)?;
+        (accepted > 0).then_some("totp")
+    } else {
+        diesel::update(recovery_codes)
+            .filter(crate::schema::recovery_codes::user_id.eq(user_id))
+            .filter(code_hash.eq(hash_token(&code.trim().to_lowercase())))
+            .filter(used_at.is_null())
+            .set(used_at.eq(Utc::now().naive_utc()))
+            .get_result::<RecoveryCode>(conn)
+            .optional()?
+            .map(|_| "recovery_code")
+    };
+    match passed {
+        Some(method) => {
+            diesel::update(mfa_challenges.find(challenge))
+                .set((
+                    failures.eq(failures - 1),
+                    crate::schema::mfa_challenges::used_at.eq(now),
+                ))
+                .execute(conn)?;
+            AuditLog::record(
+                conn,
+                &audit.as_actor(user_id),
+                AuditEventType::Login,
+                Some(user_id),
+                serde_json::json!({ "mfa": true, "method": method }),
+            )?;
+            Ok(user)
+        }
+        None => {
+            AuditLog::record(
+                conn,
+                audit,
+                AuditEventType::SecondFactorFailed,
+                Some(user_id),
+                serde_json::json!({ "attempt": attempt }),
+            )?;
+            Err(ServiceError::Unauthorized)
+        }
+    }
+}
+
+pub const REQUIRE_ADMIN_2FA: &str = "require_admin_2fa";
+
+pub fn get_setting(setting: &str, pool: web::Data<Pool>) -> Result<Option<String>, ServiceError> {
+    use crate::schema::settings::dsl::{name, settings, value};
+    let conn = &pool.get().unwrap();
+    Ok(settings
+        .filter(name.eq(setting))
+        .select(value)
+        .first::<String>(conn)
+        .optional()?)
+}
+
+pub fn set_setting(
+    setting: &str,
+    new_value: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(), ServiceError> {
+    use crate::schema::settings::dsl::{name, settings, updated_at, value};
+    let conn = &pool.get().unwrap();
+    diesel::insert_into(settings)
+        .values((name.eq(setting), value.eq(new_value)))
+        .on_conflict(name)
+        .do_update()
+        .set((value.eq(new_value), updated_at.eq(Utc::now().naive_utc())))
+        .execute(conn)?;
+    AuditLog::record(
+        conn,
+        audit,
+        AuditEventType::SettingChanged,
+        None,
+        serde_json::json!({ "name": setting, "value": new_value }),
+    )
+}
+
+pub fn admin_2fa_required(pool: web::Data<Pool>) -> Result<bool, ServiceError> {
+    Ok(get_setting(REQUIRE_ADMIN_2FA, pool)?.as_deref() == Some("true"))
+}
+
+//the export right away if it is small, None leaves it to start_export and the export job
+pub fn export_now(
+    user_id: i64,
+    format: ExportFormat,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<Option<Vec<u8>>, ServiceError> {
+    let conn = &pool.get().unwrap();
+    if crate::export::row_count(user_id, conn)? > crate::export::INLINE_EXPORT_ROWS {
+        return Ok(None);
+    }
+    let archive = crate::export::package(&crate::export::collect(user_id, conn)?, format)?;
+    AuditLog::record(
+        conn,
+        audit,
+        AuditEventType::DataExported,
+        Some(user_id),
+        serde_json::json!({ "format": format.as_str(), "inline": true }),
+    )?;
+    Ok(Some(archive))
+}
+
+//queues an export for the export job, the raw token is the one download link
+pub fn start_export(
+    user_id: i64,
+    format: ExportFormat,
+    pool: web::Data<Pool>,
+) -> Result<(DataExport, String), ServiceError> {
+    use crate::schema::data_exports::dsl::{data_exports, status, user_id as owner};
+    let conn = &pool.get().unwrap();
+    let open = data_exports
+        .filter(owner.eq(user_id))
+        .filter(status.eq_any(vec![
+            ExportStatus::Pending.as_str(),
+            ExportStatus::Running.as_str(),
+        ]))
+        .first::<DataExport>(conn)
+        .optional()?;
+    if let Some(open) = open {
+        return Err(ServiceError::TooManyRequests(format!(
+            "an export is already being made, see GET /user/export/{}",
+            open.id
+        )));
+    }
+    let raw_token = generate_token();
+    let export = diesel::insert_into(data_exports)
+        .values(&DataExportInsert {
+            user_id,
+            format: format.as_str().to_owned(),
+            token_hash: hash_token(&raw_token),
+            expires_at: (Utc::now() + Duration::hours(*EXPORT_DOWNLOAD_HOURS)).naive_utc(),
+        })
+        .get_result::<DataExport>(conn)?;
+    Ok((export, raw_token))
+}
+
+pub fn export_status(
+    user_id: i64,
+    export_id: Uuid,
+    pool: web::Data<Pool>,
+) -> Result<DataExport, ServiceError> {
+    use crate::schema::data_exports::dsl::{data_exports, user_id as owner};
+    let conn = &pool.get().unwrap();
+    data_exports
+        .find(export_id)
+        .filter(owner.eq(user_id))
+        .get_result::<DataExport>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound)
+}
+
+//hands out a finished archive once, it is gone from the database afterwards
+pub fn take_export(
+    token: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(ExportFormat, Vec<u8>), ServiceError> {
+    use crate::schema::data_exports::dsl::*;
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    conn.transaction(|| {
+        let export = data_exports
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(status.eq(ExportStatus::Ready.as_str()))
+            .filter(expires_at.gt(now))
+            .for_update()
+            .first::<DataExport>(conn)
+            .optional()?
+            .ok_or(ServiceError::NotFound)?;
+        diesel::update(data_exports.find(export.id))
+            .set((
+                status.eq(ExportStatus::Downloaded.as_str()),
+                downloaded_at.eq(now),
+                archive.eq(None::<Vec<u8>>),
+            ))
+            .execute(conn)?;
+        let export_format =
+            ExportFormat::from_name(&export.format).ok_or(ServiceError::InternalServerError)?;
+        AuditLog::record(
+            conn,
+            &audit.as_actor(export.user_id),
+            AuditEventType::DataExported,
+            Some(export.user_id),
+            serde_json::json!({ "format": export.format, "inline": false, "export": export.id }),
+        )?;
+        Ok((
+            export_format,
+            export.archive.ok_or(ServiceError::InternalServerError)?,
+        ))
+    })
+}
+
+//builds the queued exports one at a time, several workers never pick the same one.
+//archives nobody fetched in time are dropped
+pub fn run_pending_exports(pool: &Pool) -> Result<usize, ServiceError> {
+    use crate::schema::data_exports::dsl::*;
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    diesel::update(data_exports)
+        .filter(status.eq_any(vec![
+            ExportStatus::Pending.as_str(),
+            ExportStatus::Running.as_str(),
+            ExportStatus::Ready.as_str(),
+        ]))
+        .filter(expires_at.le(now))
+        .set((
+            status.eq(ExportStatus::Expired.as_str()),
+            archive.eq(None::<Vec<u8>>),
+        ))
+        .execute(conn)?;
+    let mut built = 0;
+    loop {
+        let next = conn.transaction::<_, ServiceError, _>(|| {
+            let next = data_exports
+                .filter(status.eq(ExportStatus::Pending.as_str()))
+                .order(created_at)
+                .for_update()
+                .skip_locked()
+                .first::<DataExport>(conn)
+                .optional()?;
+            if let Some(ref export) = next {
+                diesel::update(data_exports.find(export.id))
+                    .set(status.eq(ExportStatus::Running.as_str()))
+                    .execute(conn)?;
+            }
+            Ok(next)
+        })?;
+        let export = match next {
+            Some(export) => export,
+            None => return Ok(built),
+        };
+        let export_format = ExportFormat::from_name(&export.format).unwrap_or_default();
+        let result = crate::export::collect(export.user_id, conn)
+            .and_then(|data| crate::export::package(&data, export_format));
+        let finished = Utc::now().naive_utc();
+        match result {
+            Ok(bytes) => {
+                diesel::update(data_exports.find(export.id))
+                    .set((
+                        status.eq(ExportStatus::Ready.as_str()),
+                        archive.eq(Some(bytes)),
+                        finished_at.eq(finished),
+                    ))
+                    .execute(conn)?;
+                built += 1;
+            }
+            Err(err) => {
+                log::error!("export job: export {} failed: {}", export.id, err);
+                diesel::update(data_exports.find(export.id))
+                    .set((
+                        status.eq(ExportStatus::Failed.as_str()),
+                        finished_at.eq(finished),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+    }
+}
+
+//openid connect clients and what users granted them
+//the secret is only ever shown here, public clients get none
+pub fn create_client(
+    client: ClientData,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(OAuthClient, Option<String>), ServiceError> {
+    use crate::schema::oauth_clients::dsl::oauth_clients;
+    let name = client.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(ServiceError::BadRequest(
+            "name needs 1 to 100 characters".to_owned(),
+        ));
+    }
+    if client.redirect_uris.is_empty() || client.redirect_uris.len() > 10 {
+        return Err(ServiceError::BadRequest(
+            "give 1 to 10 redirect_uris".to_owned(),
+        ));
+    }
+    if let Some(uri) = client.redirect_uris.iter().find(|u| !valid_redirect_uri(u)) {
+        return Err(ServiceError::BadRequest(format!(
+            "{} is not an https url or a http url on localhost",
+            uri
+        )));
+    }
+    let secret = (!client.public).then(generate_token);
+    let new_client = OAuthClientInsert {
+        client_id: Uuid::new_v4().to_simple().to_string(),
+        name: name.to_owned(),
+        secret_hash: secret.as_deref().map(hash_token),
+        redirect_uris: client.redirect_uris,
+        created_by: audit.actor_id,
+    };
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        let stored = diesel::insert_into(oauth_clients)
+            .values(&new_client)
+            .get_result::<OAuthClient>(conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::ClientCreated,
+            None,
+            serde_json::json!({
+                "client_id": stored.client_id,
+                "name": stored.name,
+                "redirect_uris": stored.redirect_uris,
+                "public": stored.secret_hash.is_none(),
+            }),
+        )?;
+        Ok((stored, secret))
+    })
+}
+
+pub fn list_clients(pool: web::Data<Pool>) -> Result<Vec<OAuthClient>, ServiceError> {
+    use crate::schema::oauth_clients::dsl::{created_at, oauth_clients};
+    let conn = &pool.get().unwrap();
+    Ok(oauth_clients
+        .order(created_at.asc())
+        .load::<OAuthClient>(conn)?)
+}
+
+pub fn find_client(client: &str, pool: web::Data<Pool>) -> Result<OAuthClient, ServiceError> {
+    use crate::schema::oauth_clients::dsl::oauth_clients;
+    let conn = &pool.get().unwrap();
+    oauth_clients
+        .find(client)
+        .first::<OAuthClient>(conn)
+        .optional()?
+        .ok_or(ServiceError::NotFound)
+}
+
+//codes and tokens of the client go with it
+pub fn delete_client(
+    client: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(), ServiceError> {
+    use crate::schema::oauth_clients::dsl::oauth_clients;
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        let deleted = diesel::delete(oauth_clients.find(client))
+            .get_result::<OAuthClient>(conn)
+            .optional()?
+            .ok_or(ServiceError::NotFound)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::ClientDeleted,
+            None,
+            serde_json::json!({ "client_id": deleted.client_id, "name": deleted.name }),
+        )?;
+        Ok(())
+    })
+}
+
+pub fn issue_authorization_code(
+    grant: Grant,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<String, ServiceError> {
+    use crate::schema::oauth_codes::dsl::oauth_codes;
+    let raw_code = generate_token();
+    let now = Utc::now().naive_utc();
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        AuditLog::record(
+            conn,
+            &audit.as_actor(grant.user_id),
+            AuditEventType::ClientAuthorized,
+            Some(grant.user_id),
+            serde_json::json!({ "client_id": grant.client_id, "scope": grant.scope }),
+        )?;
+        diesel::insert_into(oauth_codes)
+            .values(&AuthCodeInsert {
+                code_hash: hash_token(&raw_code),
+                client_id: grant.client_id,
+                user_id: grant.user_id,
+                redirect_uri: grant.redirect_uri,
+                scope: grant.scope,
+                nonce: grant.nonce,
+                code_challenge: grant.code_challenge,
+                auth_time: now,
+                expires_at: now + Duration::seconds(*AUTHORIZATION_CODE_SECONDS),
+            })
+            .execute(conn)?;
+        Ok(raw_code)
+    })
+}
+
+//trades a code for an access token, None for anything the token endpoint answers invalid_grant to.
+//a code presented twice was probably stolen, so the token it got the first time stops working
+pub fn redeem_authorization_code(
+    code: &str,
+    client: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<Option<(AuthCode, User, String)>, ServiceError> {
+    use crate::schema::oauth_codes::dsl::{oauth_codes, used_at};
+    use crate::schema::oauth_tokens::dsl::{code_hash, oauth_tokens};
+    use crate::schema::users::dsl::{deleted_at, users};
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    conn.transaction(|| {
+        let stored = oauth_codes
+            .find(hash_token(code))
+            .for_update()
+            .first::<AuthCode>(conn)
+            .optional()?;
+        let stored = match stored {
+            Some(stored) if stored.client_id == client => stored,
+            _ => return Ok(None),
+        };
+        if stored.used_at.is_some() {
+            let revoked = diesel::delete(oauth_tokens.filter(code_hash.eq(&stored.code_hash)))
+                .execute(conn)?;
+            AuditLog::record(
+                conn,
+                audit,
+                AuditEventType::TokensRevoked,
+                Some(stored.user_id),
+                serde_json::json!({
+                    "client_id": stored.client_id,
+                    "reason": "authorization code reused",
+                    "tokens": revoked,
+                }),
+            )?;
+            return Ok(None);
+        }
+        if stored.expires_at <= now
+            || stored.redirect_uri != redirect_uri
+            || !pkce_matches(code_verifier, &stored.code_challenge)
+        {
+            return Ok(None);
+        }
+        diesel::update(oauth_codes.find(&stored.code_hash))
+            .set(used_at.eq(now))
+            .execute(conn)?;
+        let user = users
+            .find(stored.user_id)
+            .filter(deleted_at.is_null())
+            .first::<User>(conn)
+            .optional()?;
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        let raw_token = generate_token();
+        diesel::insert_into(oauth_tokens)
+            .values(&OAuthTokenInsert {
+                token_hash: hash_token(&raw_token),
+                code_hash: stored.code_hash.clone(),
+                client_id: stored.client_id.clone(),
+                user_id: stored.user_id,
+                scope: stored.scope.clone(),
+                expires_at: now + Duration::minutes(*ACCESS_TOKEN_MINUTES),
+            })
+            .execute(conn)?;
+        Ok(Some((stored, user, raw_token)))
+    })
+}
+
+//the user and granted scope behind a userinfo access token
+pub fn userinfo(token: &str, pool: web::Data<Pool>) -> Result<(User, String), ServiceError> {
+    use crate::schema::oauth_tokens::dsl::{expires_at, oauth_tokens};
+    use crate::schema::users::dsl::{deleted_at, users};
+    let conn = &pool.get().unwrap();
+    let stored = oauth_tokens
+        .find(hash_token(token))
+        .filter(expires_at.gt(Utc::now().naive_utc()))
+        .first::<OAuthToken>(conn)
+        .optional()?
+        .ok_or(ServiceError::Unauthorized)?;
+    let user = users
+        .find(stored.user_id)
+        .filter(deleted_at.is_null())
+        .first::<User>(conn)
+        .optional()?
+        .ok_or(ServiceError::Unauthorized)?;
+    Ok((user, stored.scope))
+}
+
+//expired tokens, and codes once no token of theirs is left to revoke
+pub fn purge_oauth_grants(pool: &Pool) -> Result<usize, ServiceError> {
+    use crate::schema::oauth_codes::dsl as codes;
+    use crate::schema::oauth_tokens::dsl as tokens;
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    let expired_tokens =
+        diesel::delete(tokens::oauth_tokens.filter(tokens::expires_at.le(now))).execute(conn)?;
+    let expired_codes =
+        diesel::delete(codes::oauth_codes.filter(codes::expires_at.le(now)).filter(
+            diesel::dsl::not(diesel::dsl::exists(
+                tokens::oauth_tokens.filter(tokens::code_hash.eq(codes::code_hash)),
+            )),
+        ))
+        .execute(conn)?;
+    Ok(expired_tokens + expired_codes)
+}
+
+//personal access tokens. the raw token is only returned here, scopes have to be permissions the user has
+pub fn create_api_token(
+    owner: i64,
+    mfa: bool,
+    token_data: ApiTokenData,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(ApiToken, String), ServiceError> {
+    use crate::schema::api_tokens::dsl::{api_tokens, user_id};
+    let name = token_data.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(ServiceError::BadRequest(
+            "name needs 1 to 100 characters".to_owned(),
+        ));
+    }
+    let expires_at = match token_data.expires_in_days {
+        Some(days) if !(1..=3650).contains(&days) => {
+            return Err(ServiceError::BadRequest(
+                "expires_in_days has to be between 1 and 3650, or null".to_owned(),
+            ))
+        }
+        Some(days) => Some((Utc::now() + Duration::days(days)).naive_utc()),
+        None => None,
+    };
+    let conn = &pool.get().unwrap();
+    let access = access_of(owner, conn)?;
+    let mut scopes = token_data.scopes;
+    scopes.sort();
+    scopes.dedup();
+    if let Some(scope) = scopes.iter().find(|s| !access.has_permission(s)) {
+        return Err(ServiceError::Forbidden(format!(
+            "you do not have the permission {}",
+            scope
+        )));
+    }
+    let lookup: [u8; 6] = rand::random();
+    let prefix = format!("{}{}", TOKEN_PREFIX, hex::encode(lookup));
+    let raw_token = format!("{}_{}", prefix, generate_token());
+    let new_token = ApiTokenInsert {
+        id: Uuid::new_v4(),
+        user_id: owner,
+        name: name.to_owned(),
+        prefix,
+        token_hash: hash_token(&raw_token),
+        scopes,
+        mfa,
+        expires_at,
+    };
+    conn.transaction(|| {
+        let count = api_tokens
+            .filter(user_id.eq(owner))
+            .select(diesel::dsl::count_star())
+            .get_result::<i64>(conn)?;
+        if count >= MAX_API_TOKENS {
+            return Err(ServiceError::BadRequest(format!(
+                "you already have {} tokens, delete one first",
+                MAX_API_TOKENS
+            )));
+        }
+        let stored = diesel::insert_into(api_tokens)
+            .values(&new_token)
+            .get_result::<ApiToken>(conn)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::ApiTokenCreated,
+            Some(owner),
+            serde_json::json!({
+                "token": stored.id,
+                "name": stored.name,
+                "prefix": stored.prefix,
+                "scopes": stored.scopes,
+                "expires_at": stored.expires_at,
+            }),
+        )?;
+        Ok((stored, raw_token))
+    })
+}
+
+pub fn list_api_tokens(owner: i64, pool: web::Data<Pool>) -> Result<Vec<ApiToken>, ServiceError> {
+    use crate::schema::api_tokens::dsl::{api_tokens, created_at, user_id};
+    let conn = &pool.get().unwrap();
+    Ok(api_tokens
+        .filter(user_id.eq(owner))
+        .order(created_at.asc())
+        .load::<ApiToken>(conn)?)
+}
+
+pub fn delete_api_token(
+    owner: i64,
+    token: Uuid,
+    audit: &AuditContext,
+    pool: web::Data<Pool>,
+) -> Result<(), ServiceError> {
+    use crate::schema::api_tokens::dsl::{api_tokens, user_id};
+    let conn = &pool.get().unwrap();
+    conn.transaction(|| {
+        let deleted = diesel::delete(api_tokens.find(token).filter(user_id.eq(owner)))
+            .get_result::<ApiToken>(conn)
+            .optional()?
+            .ok_or(ServiceError::NotFound)?;
+        AuditLog::record(
+            conn,
+            audit,
+            AuditEventType::ApiTokenRevoked,
+            Some(owner),
+            serde_json::json!({ "token": deleted.id, "name": deleted.name, "prefix": deleted.prefix }),
+        )
+    })
+}
+
+//the user a personal access token acts for and the permissions of its scopes the user still has.
+//None for tokens that are unknown, expired or deleted and for deleted accounts
+pub fn use_api_token(
+    token: &str,
+    ip: Option<String>,
+    pool: web::Data<Pool>,
+) -> Result<Option<(ApiToken, User, Access)>, ServiceError> {
+    use crate::schema::api_tokens::dsl::{
+        api_tokens, expires_at, last_used_at, last_used_ip, prefix, token_hash,
+    };
+    use crate::schema::users::dsl::{deleted_at, users};
+    let lookup = match token_prefix(token) {
+        Some(lookup) => lookup,
+        None => return Ok(None),
+    };
+    let conn = &pool.get().unwrap();
+    let now = Utc::now().naive_utc();
+    let stored = diesel::update(api_tokens)
+        .filter(prefix.eq(lookup))
+        .filter(token_hash.eq(hash_token(token)))
+        .filter(expires_at.is_null().or(expires_at.gt(now)))
+        .set((
+            last_used_at.eq(now),
+            last_used_ip.eq(ip.map(|ip| ip.chars().take(64).collect::<String>())),
+        ))
+        .get_result::<ApiToken>(conn)
+        .optional()?;
+    let stored = match stored {
+        Some(stored) => stored,
+        None => return Ok(None),
+    };
+    let user = match users
+        .find(stored.user_id)
+        .filter(deleted_at.is_null())
+        .first::<User>(conn)
+        .optional()?
+    {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+    let mut access = access_of(user.id, conn)?;
+    access.permissions.retain(|p| stored.scopes.contains(p));
+    //a role stands for more than the scopes, nothing may take a token for an admin
+    access.roles.clear();
+    Ok(Some((stored, user, access)))
+}
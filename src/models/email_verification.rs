@@ -0,0 +1,41 @@
+use super::super::schema::*;
+
+#[derive(Queryable, Debug)]
+pub struct EmailVerification {
+    pub id: i64,
+    pub user_id: i64,
+    pub email: String,
+    pub token_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_verifications"]
+pub struct EmailVerificationInsert {
+    pub user_id: i64,
+    pub email: String,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Queryable, Debug)]
+pub struct EmailChange {
+    pub id: i64,
+    pub user_id: i64,
+    pub new_email: String,
+    pub token_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_changes"]
+pub struct EmailChangeInsert {
+    pub user_id: i64,
+    pub new_email: String,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,121 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Queryable, Debug)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: i64,
+    pub format: String,
+    pub status: String,
+    pub token_hash: String,
+    pub archive: Option<Vec<u8>>,
+    pub created_at: chrono::NaiveDateTime,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+    pub expires_at: chrono::NaiveDateTime,
+    pub downloaded_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "data_exports"]
+pub struct DataExportInsert {
+    pub user_id: i64,
+    pub format: String,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Zip,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Zip => "zip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Zip => "application/zip",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    Pending,
+    Running,
+    Ready,
+    Failed,
+    Downloaded,
+    Expired,
+}
+
+impl ExportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Ready => "ready",
+            Self::Failed => "failed",
+            Self::Downloaded => "downloaded",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+//GET /user/export query
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+//GET /user/export/download query
+#[derive(Debug, Deserialize)]
+pub struct DownloadQuery {
+    pub token: String,
+}
+
+//GET /user/export/{id}, the archive itself only comes from the download link
+#[derive(Debug, Serialize)]
+pub struct ExportView {
+    pub id: Uuid,
+    pub format: String,
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+    pub expires_at: chrono::NaiveDateTime,
+    pub downloaded_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<DataExport> for ExportView {
+    fn from(export: DataExport) -> Self {
+        Self {
+            id: export.id,
+            format: export.format,
+            status: export.status,
+            created_at: export.created_at,
+            finished_at: export.finished_at,
+            expires_at: export.expires_at,
+            downloaded_at: export.downloaded_at,
+        }
+    }
+}
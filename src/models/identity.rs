@@ -0,0 +1,97 @@
+use super::super::schema::*;
+use super::user::User;
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, Serialize, Debug)]
+pub struct UserIdentity {
+    #[serde(skip)]
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_login_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_identities"]
+pub struct UserIdentityInsert {
+    pub user_id: i64,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
+
+#[derive(Queryable, Debug)]
+pub struct ProviderLogin {
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "provider_logins"]
+pub struct ProviderLoginInsert {
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+//who the id token of a provider says the user is
+#[derive(Debug, Clone)]
+pub struct UpstreamIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+//what a login at a provider ends in
+#[derive(Debug)]
+pub enum IdentityLogin {
+    //an account that was linked before
+    Linked(User),
+    //a new account for someone we did not know
+    Created(User),
+    //an account with the same email, only its owner can link it
+    LinkRequired(User, UpstreamIdentity),
+}
+
+//GET /auth/providers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderView {
+    pub name: String,
+    pub display_name: String,
+}
+
+//POST /auth/providers/{name}/callback body, both come from the query the provider redirected to
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProviderCallback {
+    pub code: String,
+    pub state: String,
+}
+
+//POST /auth/providers/{name}/link body
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LinkData {
+    pub link_token: String,
+    pub password: String,
+}
+
+//the link token, like the 2fa challenge token but also naming the identity to link
+#[derive(Serialize, Deserialize)]
+pub struct LinkClaims {
+    pub sub: i64,
+    pub ver: i32,
+    pub typ: String,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub exp: usize,
+}
@@ -1,2 +1,14 @@
+pub mod api_token;
+pub mod audit;
 pub mod dbmethods;
+pub mod email_verification;
+pub mod export;
+pub mod identity;
+pub mod oauth;
+pub mod outbox;
+pub mod password_reset;
+pub mod profile;
+pub mod role;
+pub mod session;
+pub mod two_factor;
 pub mod user;
@@ -0,0 +1,249 @@
+use super::super::schema::*;
+use super::user::User;
+use serde::{Deserialize, Serialize};
+
+//scopes we know, anything else that is asked for is left out of the grant
+pub const SCOPES: &[&str] = &["openid", "profile", "email"];
+
+//the known scopes of a request in a fixed order, None without openid
+pub fn granted_scope(requested: &str) -> Option<String> {
+    let requested = requested.split_whitespace().collect::<Vec<_>>();
+    if !requested.contains(&"openid") {
+        return None;
+    }
+    let granted = SCOPES
+        .iter()
+        .filter(|scope| requested.contains(scope))
+        .copied()
+        .collect::<Vec<_>>();
+    Some(granted.join(" "))
+}
+
+pub fn has_scope(scope: &str, wanted: &str) -> bool {
+    scope.split_whitespace().any(|s| s == wanted)
+}
+
+//exact https urls, plain http only for the local machine. no fragments
+pub fn valid_redirect_uri(uri: &str) -> bool {
+    let rest = match uri.strip_prefix("https://") {
+        Some(rest) => rest,
+        None => match uri.strip_prefix("http://") {
+            Some(rest) => {
+                let host = rest.split(['/', '?']).next().unwrap_or("");
+                let host = host.rsplit_once(':').map_or(host, |(host, port)| {
+                    if port.chars().all(|c| c.is_ascii_digit()) {
+                        host
+                    } else {
+                        ""
+                    }
+                });
+                if !matches!(host, "localhost" | "127.0.0.1" | "[::1]") {
+                    return false;
+                }
+                rest
+            }
+            None => return false,
+        },
+    };
+    !rest.is_empty()
+        && !rest.starts_with('/')
+        && uri.len() <= 2048
+        && !uri.contains('#')
+        && !uri.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub name: String,
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub created_by: Option<i64>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_clients"]
+pub struct OAuthClientInsert {
+    pub client_id: String,
+    pub name: String,
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub created_by: Option<i64>,
+}
+
+//POST /admin/clients body
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ClientData {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    //public clients get no secret and can only use pkce
+    #[serde(default)]
+    pub public: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientView {
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub public: bool,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl From<OAuthClient> for ClientView {
+    fn from(client: OAuthClient) -> Self {
+        Self {
+            public: client.secret_hash.is_none(),
+            client_id: client.client_id,
+            name: client.name,
+            redirect_uris: client.redirect_uris,
+            created_at: client.created_at,
+        }
+    }
+}
+
+#[derive(Queryable, Debug)]
+pub struct AuthCode {
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: i64,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub auth_time: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_codes"]
+pub struct AuthCodeInsert {
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: i64,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub auth_time: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+//what the user agreed to on the consent page, becomes an authorization code
+#[derive(Debug)]
+pub struct Grant {
+    pub client_id: String,
+    pub user_id: i64,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+}
+
+#[derive(Queryable, Debug)]
+pub struct OAuthToken {
+    pub token_hash: String,
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: i64,
+    pub scope: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_tokens"]
+pub struct OAuthTokenInsert {
+    pub token_hash: String,
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: i64,
+    pub scope: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+//GET /oauth/authorize query, everything is optional so a bad request still gets a proper answer
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct AuthorizeRequest {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub prompt: Option<String>,
+}
+
+//POST /oauth/authorize, the login and consent form
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeForm {
+    #[serde(flatten)]
+    pub request: AuthorizeRequest,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    //totp or recovery code, only for users with 2fa
+    pub code: Option<String>,
+    //allow or deny
+    pub decision: Option<String>,
+}
+
+//POST /oauth/token body
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TokenRequest {
+    pub grant_type: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub code_verifier: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub id_token: String,
+    pub scope: String,
+}
+
+//claims about the user, in the id token and from GET /oauth/userinfo. sub is the public id
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserClaims {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+impl UserClaims {
+    pub fn new(user: &User, scope: &str) -> Self {
+        let email = has_scope(scope, "email");
+        Self {
+            sub: user.public_id.to_string(),
+            name: Some(user.name.clone()).filter(|_| has_scope(scope, "profile")),
+            email: Some(user.email.clone()).filter(|_| email),
+            email_verified: Some(user.email_verified_at.is_some()).filter(|_| email),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdClaims {
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub auth_time: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user: UserClaims,
+}
@@ -0,0 +1,48 @@
+use super::super::schema::*;
+
+use crate::mailer::Email;
+
+#[derive(Queryable, Debug)]
+pub struct OutboxEmail {
+    pub id: i64,
+    pub recipient: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub send_after: chrono::NaiveDateTime,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub sent_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_outbox"]
+pub struct OutboxInsert {
+    pub recipient: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: Option<String>,
+}
+
+impl From<Email> for OutboxInsert {
+    fn from(email: Email) -> Self {
+        Self {
+            recipient: email.to,
+            subject: email.subject,
+            text_body: email.text,
+            html_body: email.html,
+        }
+    }
+}
+
+impl From<OutboxEmail> for Email {
+    fn from(row: OutboxEmail) -> Self {
+        Self {
+            to: row.recipient,
+            subject: row.subject,
+            text: row.text_body,
+            html: row.html_body,
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, Debug)]
+pub struct PasswordReset {
+    pub id: i64,
+    pub user_id: i64,
+    pub token_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_resets"]
+pub struct PasswordResetInsert {
+    pub user_id: i64,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+//POST /auth/password-reset body
+#[derive(Deserialize, Serialize)]
+pub struct ResetRequest {
+    pub email: String,
+}
+
+//POST /auth/password-reset/confirm body
+#[derive(Deserialize, Serialize)]
+pub struct ResetConfirm {
+    pub token: String,
+    pub password: String,
+}
@@ -0,0 +1,226 @@
+use super::super::schema::*;
+use super::user::User;
+use crate::extractors::AuthenticatedUser;
+use serde::{Deserialize, Serialize};
+
+//who gets to see a profile field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    //everyone, even without a token
+    Public,
+    //every logged in user with a verified email
+    Authenticated,
+    //users with users:read
+    Admins,
+    //only the user, admins still get the full record
+    #[serde(rename = "self")]
+    Owner,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Authenticated => "authenticated",
+            Self::Admins => "admins",
+            Self::Owner => "self",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "public" => Some(Self::Public),
+            "authenticated" => Some(Self::Authenticated),
+            "admins" => Some(Self::Admins),
+            "self" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn allows(&self, viewer: Relation) -> bool {
+        match viewer {
+            Relation::Owner | Relation::Admin => true,
+            Relation::Authenticated => matches!(self, Self::Public | Self::Authenticated),
+            Relation::Anonymous => *self == Self::Public,
+        }
+    }
+}
+
+//how the one asking stands to the profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Anonymous,
+    Authenticated,
+    Admin,
+    Owner,
+}
+
+impl Relation {
+    //unverified users only count as anonymous, like for every other route they can not use
+    pub fn of(viewer: Option<&AuthenticatedUser>, owner: i64) -> Self {
+        match viewer {
+            Some(v) if v.id == owner => Self::Owner,
+            Some(v) if !v.email_verified => Self::Anonymous,
+            Some(v) if v.has_permission("users:read") => Self::Admin,
+            Some(_) => Self::Authenticated,
+            None => Self::Anonymous,
+        }
+    }
+}
+
+#[derive(Queryable, Debug)]
+pub struct Profile {
+    pub user_id: i64,
+    pub avatar_url: Option<String>,
+    pub email_visibility: String,
+    pub name_visibility: String,
+    pub joined_visibility: String,
+    pub avatar_visibility: String,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name = "profiles"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct ProfileInsert {
+    pub user_id: i64,
+    pub avatar_url: Option<String>,
+    pub email_visibility: String,
+    pub name_visibility: String,
+    pub joined_visibility: String,
+    pub avatar_visibility: String,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl ProfileInsert {
+    pub fn new(user_id: i64, settings: &ProfileSettings) -> Self {
+        Self {
+            user_id,
+            avatar_url: settings.avatar_url.clone(),
+            email_visibility: settings.visibility.email.as_str().to_owned(),
+            name_visibility: settings.visibility.name.as_str().to_owned(),
+            joined_visibility: settings.visibility.joined.as_str().to_owned(),
+            avatar_visibility: settings.visibility.avatar.as_str().to_owned(),
+            updated_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldVisibility {
+    pub email: Visibility,
+    pub name: Visibility,
+    pub joined: Visibility,
+    pub avatar: Visibility,
+}
+
+//same as the column defaults in the profiles migration
+impl Default for FieldVisibility {
+    fn default() -> Self {
+        Self {
+            email: Visibility::Owner,
+            name: Visibility::Authenticated,
+            joined: Visibility::Authenticated,
+            avatar: Visibility::Authenticated,
+        }
+    }
+}
+
+//GET /user/profile
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileSettings {
+    pub avatar_url: Option<String>,
+    pub visibility: FieldVisibility,
+}
+
+impl From<Profile> for ProfileSettings {
+    fn from(profile: Profile) -> Self {
+        let defaults = FieldVisibility::default();
+        let parse = |name: &str, default| Visibility::from_name(name).unwrap_or(default);
+        Self {
+            avatar_url: profile.avatar_url,
+            visibility: FieldVisibility {
+                email: parse(&profile.email_visibility, defaults.email),
+                name: parse(&profile.name_visibility, defaults.name),
+                joined: parse(&profile.joined_visibility, defaults.joined),
+                avatar: parse(&profile.avatar_visibility, defaults.avatar),
+            },
+        }
+    }
+}
+
+//PATCH /user/profile body, only the given fields change. an empty avatar_url removes the avatar
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ProfileChange {
+    pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub visibility: VisibilityChange,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct VisibilityChange {
+    pub email: Option<Visibility>,
+    pub name: Option<Visibility>,
+    pub joined: Option<Visibility>,
+    pub avatar: Option<Visibility>,
+}
+
+//GET /user/{id} for everyone but admins, fields the viewer may not see are left out
+#[derive(Debug, Serialize)]
+pub struct ProfileView {
+    pub id: uuid::Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub joined: Option<chrono::NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+}
+
+impl ProfileView {
+    pub fn project(user: User, settings: ProfileSettings, viewer: Relation) -> Self {
+        let visibility = settings.visibility;
+        Self {
+            id: user.public_id,
+            name: Some(user.name).filter(|_| visibility.name.allows(viewer)),
+            email: Some(user.email).filter(|_| visibility.email.allows(viewer)),
+            joined: Some(user.created_at.date()).filter(|_| visibility.joined.allows(viewer)),
+            avatar_url: settings
+                .avatar_url
+                .filter(|_| visibility.avatar.allows(viewer)),
+        }
+    }
+}
+
+//GET /user/{id} for admins
+#[derive(Debug, Serialize)]
+pub struct FullProfile {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub email: String,
+    pub joined: chrono::NaiveDate,
+    pub avatar_url: Option<String>,
+    pub roles: Vec<String>,
+    pub email_verified: bool,
+    pub two_factor: bool,
+    pub visibility: FieldVisibility,
+}
+
+impl FullProfile {
+    pub fn new(user: User, settings: ProfileSettings, roles: Vec<String>) -> Self {
+        Self {
+            id: user.public_id,
+            name: user.name,
+            email: user.email,
+            joined: user.created_at.date(),
+            avatar_url: settings.avatar_url,
+            roles,
+            email_verified: user.email_verified_at.is_some(),
+            two_factor: user.totp_enabled_at.is_some(),
+            visibility: settings.visibility,
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+
+//role that comes with every permission, also the one the admin 2fa policy is about
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Queryable, Serialize, Debug)]
+pub struct Role {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Queryable, Serialize, Debug)]
+pub struct Permission {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_roles"]
+pub struct UserRoleInsert {
+    pub user_id: i64,
+    pub role_id: i32,
+}
+
+//PUT /users/{id}/role body, the user ends up with exactly this role, null takes all roles
+#[derive(Deserialize, Serialize)]
+pub struct RoleAssignment {
+    pub role: Option<String>,
+}
+
+//roles of a user and the permissions they add up to, goes into the access token
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Access {
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
+
+impl Access {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+}
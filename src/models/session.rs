@@ -0,0 +1,91 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Queryable, Serialize, Debug)]
+pub struct Session {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_seen_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    #[serde(skip)]
+    pub reauth_token_hash: Option<String>,
+    pub reauth_expires_at: Option<chrono::NaiveDateTime>,
+    pub mfa: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct SessionInsert {
+    pub id: Uuid,
+    pub user_id: i64,
+    pub expires_at: chrono::NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub mfa: bool,
+}
+
+#[derive(Queryable, Debug)]
+pub struct RefreshToken {
+    pub id: i64,
+    pub session_id: Uuid,
+    pub token_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "refresh_tokens"]
+pub struct RefreshTokenInsert {
+    pub session_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+//POST /auth/reauth body
+#[derive(Deserialize, Serialize)]
+pub struct ReauthData {
+    pub password: String,
+}
+
+//POST /auth/refresh body
+#[derive(Deserialize, Serialize)]
+pub struct RefreshData {
+    pub refresh_token: String,
+}
+
+//what a successful login or refresh returns
+#[derive(Serialize)]
+pub struct TokenPair {
+    pub token: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub refresh_token: String,
+    pub refresh_expires_at: chrono::NaiveDateTime,
+}
+
+//where the login came from
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_request(req: &actix_web::HttpRequest) -> Self {
+        Self {
+            user_agent: req
+                .headers()
+                .get(actix_web::http::header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.chars().take(255).collect()),
+            ip: req
+                .connection_info()
+                .realip_remote_addr()
+                .map(|v| v.to_owned()),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Queryable, Debug)]
+pub struct RecoveryCode {
+    pub id: i64,
+    pub user_id: i64,
+    pub code_hash: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+pub struct RecoveryCodeInsert {
+    pub user_id: i64,
+    pub code_hash: String,
+}
+
+//one try at the second login step, see verify_second_factor
+#[derive(Queryable, Debug)]
+pub struct MfaChallenge {
+    pub id: Uuid,
+    pub user_id: i64,
+    //codes tried with it that did not pass, and tries still running
+    pub failures: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "mfa_challenges"]
+pub struct MfaChallengeInsert {
+    pub id: Uuid,
+    pub user_id: i64,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+//POST /user/2fa/confirm body
+#[derive(Deserialize, Serialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+//POST /auth/2fa body, code is a totp code or a recovery code
+#[derive(Deserialize, Serialize)]
+pub struct MfaLogin {
+    pub mfa_token: String,
+    pub code: String,
+}
+
+//the challenge token POST /auth hands out when 2fa is on, only good for POST /auth/2fa.
+//logins into deleted accounts get the same kind of token for POST /auth/restore
+#[derive(Serialize, Deserialize)]
+pub struct MfaClaims {
+    pub sub: i64,
+    pub ver: i32,
+    //"mfa" or "restore"
+    pub typ: String,
+    //the mfa_challenges row of an mfa token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<Uuid>,
+    pub exp: usize,
+}
+
+//PUT /admin/2fa-policy body
+#[derive(Deserialize, Serialize)]
+pub struct TwoFactorPolicy {
+    pub require_for_admins: bool,
+}
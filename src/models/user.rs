@@ -11,7 +11,21 @@ pub struct UserChange {
 }
 
 impl Queryable<users::SqlType, diesel::pg::Pg> for UserChange {
-    type Row = (i64, String, String, String, bool, chrono::NaiveDateTime);
+    type Row = (
+        i64,
+        String,
+        String,
+        String,
+        chrono::NaiveDateTime,
+        i32,
+        Option<chrono::NaiveDateTime>,
+        Option<String>,
+        Option<chrono::NaiveDateTime>,
+        Option<i64>,
+        uuid::Uuid,
+        Option<chrono::NaiveDateTime>,
+        Option<chrono::NaiveDateTime>,
+    );
 
     fn build(row: Self::Row) -> Self {
         Self {
@@ -22,15 +36,51 @@ impl Queryable<users::SqlType, diesel::pg::Pg> for UserChange {
     }
 }
 
-#[derive(Queryable, Serialize, Debug)]
+//PATCH /user body, changing email or password needs current_password
+//or a reauth_token
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct UserChangeRequest {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub current_password: Option<String>,
+    pub reauth_token: Option<String>,
+}
+
+impl UserChangeRequest {
+    pub fn changes_credentials(&self) -> bool {
+        self.email.is_some() || self.password.is_some()
+    }
+}
+
+impl From<UserChangeRequest> for UserChange {
+    fn from(request: UserChangeRequest) -> Self {
+        Self {
+            name: request.name,
+            email: request.email,
+            password: request.password,
+        }
+    }
+}
+
+#[derive(Queryable, Serialize, Debug, Clone)]
 pub struct User {
     pub id: i64,
     pub name: String,
     pub email: String,
     pub password: String,
-    //true for admins false for normal users
-    pub clearance: bool,
     pub created_at: chrono::NaiveDateTime,
+    pub token_version: i32,
+    pub email_verified_at: Option<chrono::NaiveDateTime>,
+    #[serde(skip)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<chrono::NaiveDateTime>,
+    #[serde(skip)]
+    pub totp_last_step: Option<i64>,
+    pub public_id: uuid::Uuid,
+    //set while the account waits to be purged
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    pub purge_after: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Deserialize, Insertable, Serialize)]
@@ -58,29 +108,115 @@ impl UserInsert {
 //for hidding fields in response
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SlimUser {
+    pub id: uuid::Uuid,
+    pub name: String,
     pub email: String,
-    pub clearance: bool,
+    pub roles: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purge_after: Option<chrono::NaiveDateTime>,
 }
 
 //like userSchema.toJSON in mongoose
-impl From<User> for SlimUser {
-    fn from(user: User) -> Self {
+impl SlimUser {
+    pub fn new(user: User, roles: Vec<String>) -> Self {
         Self {
+            id: user.public_id,
+            name: user.name,
             email: user.email,
-            clearance: user.clearance,
+            roles,
+            created_at: user.created_at,
+            purge_after: user.purge_after,
         }
     }
 }
 
+//GET /users query
+#[derive(Debug, Default, Deserialize)]
+pub struct UserQuery {
+    pub limit: Option<i64>,
+    //next_cursor of the page before, only good with the same sort
+    pub cursor: Option<String>,
+    pub role: Option<String>,
+    pub created_from: Option<chrono::NaiveDateTime>,
+    pub created_to: Option<chrono::NaiveDateTime>,
+    //substring of the name or email
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: UserSort,
+    //the total costs an extra count over all matches, so only on request
+    #[serde(default)]
+    pub count: bool,
+    //only the deleted accounts that wait to be purged instead of the live ones
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+//only columns keyset pagination works on, a leading - sorts descending
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum UserSort {
+    #[default]
+    #[serde(rename = "id")]
+    IdAsc,
+    #[serde(rename = "-id")]
+    IdDesc,
+    #[serde(rename = "created_at")]
+    CreatedAsc,
+    #[serde(rename = "-created_at")]
+    CreatedDesc,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserPage {
+    pub users: Vec<SlimUser>,
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthData {
     pub email: String,
     pub password: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VerifyData {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EmailData {
+    pub email: String,
+}
+
+//POST /auth/restore body
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RestoreData {
+    pub restore_token: String,
+}
+
+//what unverified users are allowed to do, from auth.unverified_login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnverifiedLogin {
+    //no login until the email is verified
+    Deny,
+    //login works but the token only opens a few routes
+    Restricted,
+}
+
+impl UnverifiedLogin {
+    pub fn admits(self, user: &User) -> bool {
+        user.email_verified_at.is_some() || self == UnverifiedLogin::Restricted
+    }
+}
+
 pub enum FindBy {
     Email(String),
     Id(i64),
+    //the id handed out by the api
+    PublicId(uuid::Uuid),
 }
 
 #[derive(Deserialize)]
@@ -92,11 +228,81 @@ pub struct UserData {
 
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
+    //user id
+    pub sub: i64,
     pub email: String,
-    pub clearance: bool,
+    //role names and the permissions they grant
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+    //token id
+    pub jti: String,
+    //users token_version at the time of issue
+    pub ver: i32,
+    //unverified users get a restricted token
+    pub email_verified: bool,
+    //the login passed the second factor
+    pub mfa: bool,
     pub exp: usize,
 }
 
+//GET /users/search query
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+//a row of dbmethods::search_users, matched are the lowercase words of name and
+//email that matched a word of the query
+#[derive(QueryableByName, Debug)]
+pub struct SearchRow {
+    #[sql_type = "diesel::sql_types::Uuid"]
+    pub public_id: uuid::Uuid,
+    #[sql_type = "diesel::sql_types::Varchar"]
+    pub name: String,
+    #[sql_type = "diesel::sql_types::Varchar"]
+    pub email: String,
+    #[sql_type = "diesel::sql_types::Timestamp"]
+    pub created_at: chrono::NaiveDateTime,
+    #[sql_type = "diesel::sql_types::Float"]
+    pub score: f32,
+    #[sql_type = "diesel::sql_types::Array<diesel::sql_types::Text>"]
+    pub matched: Vec<String>,
+}
+
+//name and email with the matched words in <mark>, html escaped
+#[derive(Debug, Serialize)]
+pub struct Highlight {
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub email: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub score: f32,
+    pub highlight: Highlight,
+}
+
+impl From<SearchRow> for SearchHit {
+    fn from(row: SearchRow) -> Self {
+        Self {
+            highlight: Highlight {
+                name: crate::utils::highlight(&row.name, &row.matched),
+                email: crate::utils::highlight(&row.email, &row.matched),
+            },
+            id: row.public_id,
+            name: row.name,
+            email: row.email,
+            created_at: row.created_at,
+            score: row.score,
+        }
+    }
+}
+
 //testing raw sql
 
 use diesel::sql_types::*;
@@ -0,0 +1,286 @@
+//upstream openid connect providers (keycloak, dex, ..) users can log in with instead of their password.
+//the code flow with pkce and a nonce, the id token is checked against the keys the provider publishes.
+//metadata and keys are read on first use and kept for a while, main hands one Providers to every worker
+use actix_web::client::Client;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    fmt::Display,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    errors::ServiceError,
+    models::identity::{ProviderView, UpstreamIdentity},
+    settings::ProviderSettings,
+};
+
+//metadata and keys are read again after this, or right away when an id token names a key we do not have
+const METADATA_SECONDS: u64 = 60 * 60;
+const REQUEST_SECONDS: u64 = 10;
+
+#[derive(Debug, Clone, Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenAnswer {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+}
+
+#[derive(Deserialize)]
+struct UpstreamClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    //some providers send it as a string
+    email_verified: Option<serde_json::Value>,
+    name: Option<String>,
+    preferred_username: Option<String>,
+}
+
+pub struct Providers {
+    providers: Vec<Provider>,
+}
+
+pub struct Provider {
+    pub settings: ProviderSettings,
+    redirect_uri: String,
+    cache: RwLock<Option<(Instant, Metadata, JwkSet)>>,
+}
+
+impl Providers {
+    pub fn new(settings: &[ProviderSettings], app_url: &str) -> Self {
+        let providers = settings
+            .iter()
+            .map(|settings| {
+                let redirect_uri = if settings.redirect_uri.is_empty() {
+                    format!(
+                        "{}/login/{}/callback",
+                        app_url.trim_end_matches('/'),
+                        settings.name
+                    )
+                } else {
+                    settings.redirect_uri.clone()
+                };
+                Provider {
+                    settings: settings.clone(),
+                    redirect_uri,
+                    cache: RwLock::new(None),
+                }
+            })
+            .collect();
+        Self { providers }
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Provider, ServiceError> {
+        self.providers
+            .iter()
+            .find(|p| p.settings.name == name)
+            .ok_or(ServiceError::NotFound)
+    }
+
+    pub fn list(&self) -> Vec<ProviderView> {
+        self.providers
+            .iter()
+            .map(|p| ProviderView {
+                name: p.settings.name.clone(),
+                display_name: if p.settings.display_name.is_empty() {
+                    p.settings.name.clone()
+                } else {
+                    p.settings.display_name.clone()
+                },
+            })
+            .collect()
+    }
+}
+
+impl Provider {
+    fn issuer(&self) -> &str {
+        self.settings.issuer.trim_end_matches('/')
+    }
+
+    //where to send the browser, the provider sends it back to redirect_uri with a code and the state
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String, ServiceError> {
+        let (metadata, _) = self.metadata(false).await?;
+        let endpoint = &metadata.authorization_endpoint;
+        let params = [
+            ("response_type", "code"),
+            ("client_id", &self.settings.client_id),
+            ("redirect_uri", &self.redirect_uri),
+            ("scope", &self.settings.scope),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", code_challenge),
+            ("code_challenge_method", "S256"),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        Ok(format!("{}{}{}", endpoint, separator, params))
+    }
+
+    //trades the code for an id token and reads the user from it
+    pub async fn exchange(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<UpstreamIdentity, ServiceError> {
+        let (metadata, jwks) = self.metadata(false).await?;
+        let endpoint = &metadata.token_endpoint;
+        let mut request = Client::default()
+            .post(endpoint)
+            .timeout(Duration::from_secs(REQUEST_SECONDS));
+        if !self.settings.client_secret.is_empty() {
+            //client_secret_basic wants both form encoded first (rfc 6749 section 2.3.1)
+            request = request.basic_auth(
+                urlencoding::encode(&self.settings.client_id),
+                Some(&urlencoding::encode(&self.settings.client_secret)),
+            );
+        }
+        let mut response = request
+            .send_form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_uri),
+                ("client_id", &self.settings.client_id),
+                ("code_verifier", code_verifier),
+            ])
+            .await
+            .map_err(|e| upstream(endpoint, e))?;
+        if response.status().is_client_error() {
+            //an old or used code is the users problem, a client the provider does not know is ours
+            return match response.json::<TokenError>().await {
+                Ok(answer) if answer.error == "invalid_grant" => Err(ServiceError::BadRequest(
+                    "the provider did not accept the code, please log in again".to_owned(),
+                )),
+                Ok(answer) => Err(upstream(endpoint, answer.error)),
+                Err(_) => Err(upstream(endpoint, response.status())),
+            };
+        }
+        if !response.status().is_success() {
+            return Err(upstream(endpoint, response.status()));
+        }
+        let answer = response
+            .json::<TokenAnswer>()
+            .await
+            .map_err(|e| upstream(endpoint, e))?;
+        let claims = self
+            .verify_id_token(&answer.id_token, &metadata, jwks)
+            .await?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(upstream(endpoint, "id token is for another login"));
+        }
+        let email_verified = matches!(claims.email_verified, Some(serde_json::Value::Bool(true)))
+            || matches!(claims.email_verified, Some(serde_json::Value::String(ref s)) if s == "true");
+        Ok(UpstreamIdentity {
+            subject: claims.sub,
+            email: claims.email.filter(|e| !e.is_empty()),
+            email_verified,
+            name: claims
+                .name
+                .or(claims.preferred_username)
+                .filter(|n| !n.trim().is_empty()),
+        })
+    }
+
+    async fn verify_id_token(
+        &self,
+        token: &str,
+        metadata: &Metadata,
+        mut jwks: JwkSet,
+    ) -> Result<UpstreamClaims, ServiceError> {
+        let invalid = |reason: &str| upstream(&metadata.token_endpoint, reason);
+        let header = decode_header(token).map_err(|_| invalid("id token is not a jwt"))?;
+        //only what the provider signs with its own keys, never the shared secret kind
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid("id token is not signed with a public key"));
+        }
+        let find = |jwks: &JwkSet| match &header.kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        };
+        let jwk = match find(&jwks) {
+            Some(jwk) => jwk,
+            None => {
+                //the provider may have rotated its keys since we last read them
+                jwks = self.metadata(true).await?.1;
+                find(&jwks).ok_or_else(|| invalid("id token is signed with an unknown key"))?
+            }
+        };
+        if jwk.common.algorithm.is_some_and(|alg| alg != header.alg) {
+            return Err(invalid("id token algorithm does not fit its key"));
+        }
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid("unusable provider key"))?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.settings.client_id]);
+        Ok(decode::<UpstreamClaims>(token, &key, &validation)
+            .map_err(|e| invalid(&format!("id token: {}", e)))?
+            .claims)
+    }
+
+    async fn metadata(&self, reload: bool) -> Result<(Metadata, JwkSet), ServiceError> {
+        if !reload {
+            if let Some((read_at, metadata, jwks)) = &*self.cache.read().unwrap() {
+                if read_at.elapsed() < Duration::from_secs(METADATA_SECONDS) {
+                    return Ok((metadata.clone(), jwks.clone()));
+                }
+            }
+        }
+        let url = format!("{}/.well-known/openid-configuration", self.issuer());
+        let metadata = get_json::<Metadata>(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != self.issuer() {
+            return Err(upstream(
+                &url,
+                format!("issuer is {}, not {}", metadata.issuer, self.issuer()),
+            ));
+        }
+        let jwks = get_json::<JwkSet>(&metadata.jwks_uri).await?;
+        *self.cache.write().unwrap() = Some((Instant::now(), metadata.clone(), jwks.clone()));
+        Ok((metadata, jwks))
+    }
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, ServiceError> {
+    let mut response = Client::default()
+        .get(url)
+        .timeout(Duration::from_secs(REQUEST_SECONDS))
+        .send()
+        .await
+        .map_err(|e| upstream(url, e))?;
+    if !response.status().is_success() {
+        return Err(upstream(url, response.status()));
+    }
+    response
+        .json::<T>()
+        .limit(1 << 20)
+        .await
+        .map_err(|e| upstream(url, e))
+}
+
+fn upstream(url: &str, err: impl Display) -> ServiceError {
+    ServiceError::BadGateway(format!("{}: {}", url, err))
+}
@@ -0,0 +1,43 @@
+use actix_web::guard;
+use actix_web::web::{self, ServiceConfig};
+
+use crate::controllers::admin;
+use crate::middlewares::permission::RequirePermission;
+
+//routes for /admin
+pub fn admin_route_config(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::resource("/admin/2fa-policy")
+            .guard(guard::Get())
+            .wrap(RequirePermission::new("settings:read"))
+            .route(web::get().to(admin::get_2fa_policy)),
+    )
+    .service(
+        web::resource("/admin/2fa-policy")
+            .guard(guard::Put())
+            .wrap(RequirePermission::new("settings:write"))
+            .route(web::put().to(admin::set_2fa_policy)),
+    )
+    .service(
+        web::resource("/admin/clients")
+            .guard(guard::Get())
+            .wrap(RequirePermission::new("settings:read"))
+            .route(web::get().to(admin::get_clients)),
+    )
+    .service(
+        web::resource("/admin/clients")
+            .guard(guard::Post())
+            .wrap(RequirePermission::new("settings:write"))
+            .route(web::post().to(admin::post_client)),
+    )
+    .service(
+        web::resource("/admin/clients/{client_id}")
+            .wrap(RequirePermission::new("settings:write"))
+            .route(web::delete().to(admin::delete_client)),
+    )
+    .service(
+        web::resource("/admin/audit")
+            .wrap(RequirePermission::new("audit:read"))
+            .route(web::get().to(admin::get_audit_events)),
+    );
+}
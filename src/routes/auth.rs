@@ -1,6 +1,6 @@
 use actix_web::web::{self, ServiceConfig};
 
-use crate::controllers::auth;
+use crate::controllers::{auth, providers};
 
 //routes for /auth
 pub fn auth_route_config(cfg: &mut ServiceConfig) {
@@ -8,5 +8,31 @@ pub fn auth_route_config(cfg: &mut ServiceConfig) {
         web::resource("/auth")
             .route(web::post().to(auth::login))
             .route(web::delete().to(auth::logout)),
-    );
+    )
+    .route("/auth/2fa", web::post().to(auth::login_2fa))
+    .route("/auth/restore", web::post().to(auth::restore))
+    .route("/auth/refresh", web::post().to(auth::refresh))
+    .route("/auth/reauth", web::post().to(auth::reauth))
+    .route(
+        "/auth/password-reset",
+        web::post().to(auth::request_password_reset),
+    )
+    .route(
+        "/auth/password-reset/confirm",
+        web::post().to(auth::confirm_password_reset),
+    )
+    .route("/auth/providers", web::get().to(providers::get_providers))
+    .route(
+        "/auth/providers/{name}",
+        web::post().to(providers::start_login),
+    )
+    .route(
+        "/auth/providers/{name}/callback",
+        web::post().to(providers::callback),
+    )
+    .route(
+        "/auth/providers/{name}/link",
+        web::post().to(providers::link),
+    )
+    .route("/.well-known/jwks.json", web::get().to(auth::jwks));
 }
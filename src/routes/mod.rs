@@ -1,4 +1,6 @@
+pub mod admin;
 pub mod auth;
 pub mod not_found;
+pub mod oidc;
 pub mod user;
 pub mod users;
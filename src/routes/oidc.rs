@@ -0,0 +1,22 @@
+use actix_web::web::{self, ServiceConfig};
+
+use crate::controllers::oidc;
+
+//openid connect provider, /oauth and the discovery document
+pub fn oidc_route_config(cfg: &mut ServiceConfig) {
+    cfg.route(
+        "/.well-known/openid-configuration",
+        web::get().to(oidc::discovery),
+    )
+    .service(
+        web::resource("/oauth/authorize")
+            .route(web::get().to(oidc::authorize))
+            .route(web::post().to(oidc::authorize_submit)),
+    )
+    .route("/oauth/token", web::post().to(oidc::token))
+    .service(
+        web::resource("/oauth/userinfo")
+            .route(web::get().to(oidc::userinfo))
+            .route(web::post().to(oidc::userinfo)),
+    );
+}
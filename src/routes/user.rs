@@ -9,6 +9,29 @@ pub fn user_route_config(cfg: &mut ServiceConfig) {
             .route(web::patch().to(user::update_user))
             .route(web::delete().to(user::remove_account)),
     )
+    .route(
+        "/user/email/confirm",
+        web::post().to(user::confirm_email_change),
+    )
+    .route("/user/2fa", web::post().to(user::start_2fa))
+    .route("/user/2fa/confirm", web::post().to(user::confirm_2fa))
+    .route("/user/export", web::get().to(user::export_data))
+    .route(
+        "/user/export/download",
+        web::get().to(user::download_export),
+    )
+    .route("/user/export/{id}", web::get().to(user::export_status))
+    .service(
+        web::resource("/user/tokens")
+            .route(web::get().to(user::get_tokens))
+            .route(web::post().to(user::create_token)),
+    )
+    .route("/user/tokens/{id}", web::delete().to(user::delete_token))
+    .service(
+        web::resource("/user/profile")
+            .route(web::get().to(user::get_profile))
+            .route(web::patch().to(user::update_profile)),
+    )
     .route("/user/{id}", web::get().to(user::get_user_by_id))
     .route("/testing", web::get().to(user::test_route));
 }
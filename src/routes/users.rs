@@ -1,13 +1,40 @@
 use crate::controllers::users;
+use crate::middlewares::permission::RequirePermission;
 
+use actix_web::guard;
 use actix_web::web::{self, ServiceConfig};
 
 //routes
 pub fn users_route_config(cfg: &mut ServiceConfig) {
     cfg.service(
         web::resource("/users")
-            .route(web::post().to(users::post_user))
+            .guard(guard::Post())
+            .route(web::post().to(users::post_user)),
+    )
+    .service(
+        web::resource("/users")
+            .guard(guard::Get())
+            .wrap(RequirePermission::new("users:read"))
             .route(web::get().to(users::get_users)),
     )
-    .route("/users/{id}", web::patch().to(users::change_account_type));
+    .service(
+        web::resource("/users/search")
+            .wrap(RequirePermission::new("users:read"))
+            .route(web::get().to(users::search_users)),
+    )
+    .route("/users/verify-email", web::post().to(users::verify_email))
+    .route(
+        "/users/verify-email/resend",
+        web::post().to(users::resend_verification),
+    )
+    .service(
+        web::resource("/users/{id}/role")
+            .wrap(RequirePermission::new("roles:assign"))
+            .route(web::put().to(users::assign_role)),
+    )
+    .service(
+        web::resource("/users/{id}/restore")
+            .wrap(RequirePermission::new("users:write"))
+            .route(web::post().to(users::restore_user)),
+    );
 }
@@ -1,10 +1,328 @@
+table! {
+    api_tokens (id) {
+        id -> Uuid,
+        user_id -> Int8,
+        name -> Varchar,
+        prefix -> Varchar,
+        token_hash -> Varchar,
+        scopes -> Array<Text>,
+        mfa -> Bool,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        last_used_ip -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    audit_events (id) {
+        id -> Int8,
+        event_type -> Varchar,
+        actor_id -> Nullable<Int8>,
+        target_id -> Nullable<Int8>,
+        data -> Jsonb,
+        created_at -> Timestamp,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        request_id -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    data_exports (id) {
+        id -> Uuid,
+        user_id -> Int8,
+        format -> Varchar,
+        status -> Varchar,
+        token_hash -> Varchar,
+        archive -> Nullable<Bytea>,
+        created_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        expires_at -> Timestamp,
+        downloaded_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    email_changes (id) {
+        id -> Int8,
+        user_id -> Int8,
+        new_email -> Varchar,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    email_outbox (id) {
+        id -> Int8,
+        recipient -> Varchar,
+        subject -> Varchar,
+        text_body -> Text,
+        html_body -> Nullable<Text>,
+        created_at -> Timestamp,
+        send_after -> Timestamp,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        sent_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    email_verifications (id) {
+        id -> Int8,
+        user_id -> Int8,
+        email -> Varchar,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    mfa_challenges (id) {
+        id -> Uuid,
+        user_id -> Int8,
+        failures -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    oauth_clients (client_id) {
+        client_id -> Varchar,
+        name -> Varchar,
+        secret_hash -> Nullable<Varchar>,
+        redirect_uris -> Array<Text>,
+        created_by -> Nullable<Int8>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    oauth_codes (code_hash) {
+        code_hash -> Varchar,
+        client_id -> Varchar,
+        user_id -> Int8,
+        redirect_uri -> Text,
+        scope -> Varchar,
+        nonce -> Nullable<Varchar>,
+        code_challenge -> Varchar,
+        auth_time -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    oauth_tokens (token_hash) {
+        token_hash -> Varchar,
+        code_hash -> Varchar,
+        client_id -> Varchar,
+        user_id -> Int8,
+        scope -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    password_failures (user_id) {
+        user_id -> Int8,
+        failures -> Int4,
+        window_started_at -> Timestamp,
+    }
+}
+
+table! {
+    password_resets (id) {
+        id -> Int8,
+        user_id -> Int8,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    permissions (id) {
+        id -> Int4,
+        name -> Varchar,
+        description -> Varchar,
+    }
+}
+
+table! {
+    profiles (user_id) {
+        user_id -> Int8,
+        avatar_url -> Nullable<Varchar>,
+        email_visibility -> Varchar,
+        name_visibility -> Varchar,
+        joined_visibility -> Varchar,
+        avatar_visibility -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    provider_logins (state_hash) {
+        state_hash -> Varchar,
+        provider -> Varchar,
+        nonce -> Varchar,
+        code_verifier -> Varchar,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int8,
+        user_id -> Int8,
+        code_hash -> Varchar,
+        created_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Int8,
+        session_id -> Uuid,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    role_permissions (role_id, permission_id) {
+        role_id -> Int4,
+        permission_id -> Int4,
+    }
+}
+
+table! {
+    roles (id) {
+        id -> Int4,
+        name -> Varchar,
+        description -> Varchar,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Uuid,
+        user_id -> Int8,
+        created_at -> Timestamp,
+        last_seen_at -> Timestamp,
+        expires_at -> Timestamp,
+        user_agent -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        reauth_token_hash -> Nullable<Varchar>,
+        reauth_expires_at -> Nullable<Timestamp>,
+        mfa -> Bool,
+    }
+}
+
+table! {
+    settings (name) {
+        name -> Varchar,
+        value -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    user_identities (id) {
+        id -> Int8,
+        user_id -> Int8,
+        provider -> Varchar,
+        subject -> Varchar,
+        email -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_login_at -> Timestamp,
+    }
+}
+
+table! {
+    user_roles (user_id, role_id) {
+        user_id -> Int8,
+        role_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Int8,
         name -> Varchar,
         email -> Varchar,
         password -> Varchar,
-        clearance -> Bool,
         created_at -> Timestamp,
+        token_version -> Int4,
+        email_verified_at -> Nullable<Timestamp>,
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled_at -> Nullable<Timestamp>,
+        totp_last_step -> Nullable<Int8>,
+        public_id -> Uuid,
+        deleted_at -> Nullable<Timestamp>,
+        purge_after -> Nullable<Timestamp>,
     }
 }
+
+joinable!(api_tokens -> users (user_id));
+joinable!(data_exports -> users (user_id));
+joinable!(email_changes -> users (user_id));
+joinable!(email_verifications -> users (user_id));
+joinable!(mfa_challenges -> users (user_id));
+joinable!(oauth_clients -> users (created_by));
+joinable!(oauth_codes -> oauth_clients (client_id));
+joinable!(oauth_codes -> users (user_id));
+joinable!(oauth_tokens -> oauth_clients (client_id));
+joinable!(oauth_tokens -> oauth_codes (code_hash));
+joinable!(oauth_tokens -> users (user_id));
+joinable!(password_failures -> users (user_id));
+joinable!(password_resets -> users (user_id));
+joinable!(profiles -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(refresh_tokens -> sessions (session_id));
+joinable!(role_permissions -> permissions (permission_id));
+joinable!(role_permissions -> roles (role_id));
+joinable!(sessions -> users (user_id));
+joinable!(user_identities -> users (user_id));
+joinable!(user_roles -> roles (role_id));
+joinable!(user_roles -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_events,
+    data_exports,
+    email_changes,
+    email_outbox,
+    email_verifications,
+    mfa_challenges,
+    oauth_clients,
+    oauth_codes,
+    oauth_tokens,
+    password_failures,
+    password_resets,
+    permissions,
+    profiles,
+    provider_logins,
+    recovery_codes,
+    refresh_tokens,
+    role_permissions,
+    roles,
+    sessions,
+    settings,
+    user_identities,
+    user_roles,
+    users,
+);
@@ -0,0 +1,545 @@
+//server settings in layers, each one overriding the one before:
+//built in defaults, the toml file, environment variables and command line flags.
+//main loads and validates them once with Settings::load before anything else runs
+use serde::{Deserialize, Serialize};
+use std::{fs, net::ToSocketAddrs, path::PathBuf, sync::OnceLock};
+
+use crate::{keys::KeyRing, models::user::UnverifiedLogin, utils::HashPolicy};
+use jsonwebtoken::Algorithm;
+
+//the secret dev setups run with, everything else has to bring its own
+pub const DEV_SECRET_KEY: &str = "sct07sct07sct07sct07sct07sct07sct07sct07";
+//read when neither --config nor APP_CONFIG name a file, it is fine if it is missing
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+//environment variables for single settings, APP__<SECTION>__<KEY> works for all of them
+const ENV_VARS: &[(&str, &str)] = &[
+    ("APP_ENV", "environment"),
+    ("APP_BIND", "server.bind"),
+    ("APP_WORKERS", "server.workers"),
+    ("APP_URL", "server.app_url"),
+    ("DATABASE_URL", "database.url"),
+    ("DATABASE_POOL_SIZE", "database.pool_size"),
+    ("SECRET_KEY", "auth.secret_key"),
+    ("TOTP_ISSUER", "auth.totp_issuer"),
+    ("UNVERIFIED_LOGIN", "auth.unverified_login"),
+    ("DELETION_GRACE_DAYS", "auth.deletion_grace_days"),
+    ("ARGON2_MEMORY_KIB", "hashing.memory"),
+    ("ARGON2_ITERATIONS", "hashing.iterations"),
+    ("ARGON2_PARALLELISM", "hashing.parallelism"),
+    ("RUST_LOG", "log.level"),
+    ("MAIL_TRANSPORT", "mail.transport"),
+    ("MAIL_FROM", "mail.from"),
+    ("MAIL_DIR", "mail.dir"),
+    ("SMTP_HOST", "mail.smtp.host"),
+    ("SMTP_PORT", "mail.smtp.port"),
+    ("SMTP_USER", "mail.smtp.user"),
+    ("SMTP_PASSWORD", "mail.smtp.password"),
+    ("SMTP_TLS", "mail.smtp.tls"),
+];
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Environment {
+    Dev,
+    Production,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub environment: Environment,
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub auth: AuthSettings,
+    pub jwt: JwtSettings,
+    pub hashing: HashPolicy,
+    pub log: LogSettings,
+    pub mail: MailSettings,
+    //openid connect providers users can log in with next to their password
+    pub providers: Vec<ProviderSettings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub bind: String,
+    //0 starts one worker per cpu core
+    pub workers: usize,
+    //biggest json body we accept, in bytes
+    pub json_limit: usize,
+    //base of links we put in emails
+    pub app_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    pub url: String,
+    pub pool_size: u32,
+    pub connection_timeout_secs: u64,
+    //0 keeps idle connections open
+    pub idle_timeout_secs: u64,
+    //0 never replaces a connection because of its age
+    pub max_lifetime_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSettings {
+    pub secret_key: String,
+    //name authenticator apps show next to the code
+    pub totp_issuer: String,
+    pub unverified_login: UnverifiedLogin,
+    pub access_token_minutes: i64,
+    pub session_days: i64,
+    pub mfa_token_minutes: i64,
+    pub restore_token_minutes: i64,
+    pub reauth_minutes: i64,
+    pub reset_token_minutes: i64,
+    pub verify_token_hours: i64,
+    pub export_download_hours: i64,
+    pub deletion_grace_days: i64,
+    pub authorization_code_seconds: i64,
+    //time to come back from a login at a provider, and to link the account it found afterwards
+    pub provider_login_minutes: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtSettings {
+    //kid of the key access tokens are signed with, empty signs them with HS512 and auth.secret_key
+    pub signing_key: String,
+    //every key here is published in the jwks and accepted
+    pub keys: Vec<JwtKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtKey {
+    pub kid: String,
+    //RS256, ES256, EdDSA and the other RS*, PS* and ES* ones
+    pub algorithm: Algorithm,
+    //pem files, only the signing key needs its private half
+    pub public_key: PathBuf,
+    pub private_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderSettings {
+    //in the urls, /auth/providers/{name}
+    pub name: String,
+    //for the login button, the name when empty
+    #[serde(default)]
+    pub display_name: String,
+    //the provider metadata is read from {issuer}/.well-known/openid-configuration
+    pub issuer: String,
+    pub client_id: String,
+    //empty for clients the provider knows as public
+    #[serde(default)]
+    pub client_secret: String,
+    //where the provider sends the browser back to, {app_url}/login/{name}/callback when empty
+    #[serde(default)]
+    pub redirect_uri: String,
+    #[serde(default = "default_provider_scope")]
+    pub scope: String,
+    //create an account for people the provider knows and we do not
+    #[serde(default = "default_create_users")]
+    pub create_users: bool,
+}
+
+fn default_provider_scope() -> String {
+    "openid email profile".to_owned()
+}
+
+fn default_create_users() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    //env_logger filter
+    pub level: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailSettings {
+    pub transport: MailTransport,
+    //sender of every mail
+    pub from: String,
+    //where the file transport writes its .eml files
+    pub dir: PathBuf,
+    pub smtp: SmtpSettings,
+}
+
+//stdout and memory keep the mails (and the tokens in them) where they do not belong, dev only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailTransport {
+    Stdout,
+    File,
+    Smtp,
+    Memory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpSettings {
+    pub host: String,
+    //0 is 1025 without tls (a local mailhog) and the submission port with it
+    pub port: u16,
+    //no login when empty
+    pub user: String,
+    pub password: String,
+    pub tls: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            //dev has to be asked for, a deployment that forgets APP_ENV must not run on the dev secret
+            environment: Environment::Production,
+            server: ServerSettings::default(),
+            database: DatabaseSettings::default(),
+            auth: AuthSettings::default(),
+            jwt: JwtSettings::default(),
+            hashing: HashPolicy::default(),
+            log: LogSettings::default(),
+            mail: MailSettings::default(),
+            providers: Vec::new(),
+        }
+    }
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:8000".to_owned(),
+            workers: 0,
+            json_limit: 4096,
+            app_url: "http://localhost:8000".to_owned(),
+        }
+    }
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            pool_size: 10,
+            connection_timeout_secs: 30,
+            idle_timeout_secs: 600,
+            max_lifetime_secs: 1800,
+        }
+    }
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        Self {
+            secret_key: DEV_SECRET_KEY.to_owned(),
+            totp_issuer: "rust_api".to_owned(),
+            unverified_login: UnverifiedLogin::Restricted,
+            access_token_minutes: 15,
+            session_days: 60,
+            mfa_token_minutes: 5,
+            restore_token_minutes: 10,
+            reauth_minutes: 10,
+            reset_token_minutes: 60,
+            verify_token_hours: 48,
+            export_download_hours: 24,
+            deletion_grace_days: 30,
+            authorization_code_seconds: 60,
+            provider_login_minutes: 10,
+        }
+    }
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "actix_web=info,actix_server=info,server=info".to_owned(),
+        }
+    }
+}
+
+impl Default for MailSettings {
+    fn default() -> Self {
+        Self {
+            transport: MailTransport::Stdout,
+            from: "no-reply@localhost".to_owned(),
+            dir: PathBuf::from("mail"),
+            smtp: SmtpSettings::default(),
+        }
+    }
+}
+
+impl Default for SmtpSettings {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_owned(),
+            port: 0,
+            user: String::new(),
+            password: String::new(),
+            tls: false,
+        }
+    }
+}
+
+//the settings main loaded, or without main (tests, tools) the ones from the file and environment
+pub fn get() -> &'static Settings {
+    SETTINGS.get_or_init(|| {
+        Settings::from_sources(&[]).unwrap_or_else(|err| panic!("invalid settings: {}", err))
+    })
+}
+
+impl Settings {
+    //for main, takes the command line flags too and refuses settings that do not validate
+    pub fn load() -> Result<&'static Settings, String> {
+        let args = std::env::args().skip(1).collect::<Vec<_>>();
+        let settings = Self::from_sources(&args)?;
+        settings.validate()?;
+        SETTINGS
+            .set(settings)
+            .map_err(|_| "settings were already loaded".to_owned())?;
+        Ok(get())
+    }
+
+    pub fn from_sources(args: &[String]) -> Result<Self, String> {
+        dotenv::dotenv().ok();
+        let cli = parse_args(args)?;
+        let mut tree = toml::Value::try_from(Self::default()).map_err(|e| e.to_string())?;
+        let file = cli
+            .config
+            .clone()
+            .or_else(|| std::env::var("APP_CONFIG").ok().map(PathBuf::from));
+        match file {
+            Some(path) => merge(&mut tree, read_file(&path)?),
+            None if PathBuf::from(DEFAULT_CONFIG_FILE).exists() => {
+                merge(&mut tree, read_file(&PathBuf::from(DEFAULT_CONFIG_FILE))?)
+            }
+            None => {}
+        }
+        for (var, key) in ENV_VARS {
+            if let Ok(value) = std::env::var(var) {
+                set(&mut tree, key, &value).map_err(|e| format!("{}: {}", var, e))?;
+            }
+        }
+        for (var, value) in std::env::vars() {
+            if let Some(path) = var.strip_prefix("APP__") {
+                let key = path.to_lowercase().replace("__", ".");
+                set(&mut tree, &key, &value).map_err(|e| format!("{}: {}", var, e))?;
+            }
+        }
+        for (key, value) in &cli.overrides {
+            set(&mut tree, key, value).map_err(|e| format!("command line: {}", e))?;
+        }
+        tree.try_into::<Self>().map_err(|e| e.to_string())
+    }
+
+    //everything that would only fail later, or worse not fail at all
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        if self.environment != Environment::Dev {
+            if self.auth.secret_key == DEV_SECRET_KEY {
+                errors.push("auth.secret_key is the default, set SECRET_KEY".to_owned());
+            } else if self.auth.secret_key.len() < 32 {
+                errors.push("auth.secret_key needs at least 32 characters".to_owned());
+            }
+            if let MailTransport::Stdout | MailTransport::Memory = self.mail.transport {
+                errors.push(
+                    "mail.transport stdout and memory are for dev, set MAIL_TRANSPORT to smtp or file"
+                        .to_owned(),
+                );
+            }
+        }
+        if self.server.bind.to_socket_addrs().is_err() {
+            errors.push(format!(
+                "server.bind {} is not an address",
+                self.server.bind
+            ));
+        }
+        if self.server.json_limit == 0 {
+            errors.push("server.json_limit has to be more than 0".to_owned());
+        }
+        if !self.server.app_url.starts_with("http://")
+            && !self.server.app_url.starts_with("https://")
+        {
+            errors.push("server.app_url has to be an http(s) url".to_owned());
+        }
+        if self.database.url.is_empty() {
+            errors.push("database.url is missing, set DATABASE_URL".to_owned());
+        }
+        if self.database.pool_size == 0 {
+            errors.push("database.pool_size has to be more than 0".to_owned());
+        }
+        if self.database.connection_timeout_secs == 0 {
+            errors.push("database.connection_timeout_secs has to be more than 0".to_owned());
+        }
+        let lifetimes = [
+            ("access_token_minutes", self.auth.access_token_minutes),
+            ("session_days", self.auth.session_days),
+            ("mfa_token_minutes", self.auth.mfa_token_minutes),
+            ("restore_token_minutes", self.auth.restore_token_minutes),
+            ("reauth_minutes", self.auth.reauth_minutes),
+            ("reset_token_minutes", self.auth.reset_token_minutes),
+            ("verify_token_hours", self.auth.verify_token_hours),
+            ("export_download_hours", self.auth.export_download_hours),
+            ("deletion_grace_days", self.auth.deletion_grace_days),
+            (
+                "authorization_code_seconds",
+                self.auth.authorization_code_seconds,
+            ),
+            ("provider_login_minutes", self.auth.provider_login_minutes),
+        ];
+        for (name, value) in lifetimes.iter().filter(|(_, value)| *value <= 0) {
+            errors.push(format!(
+                "auth.{} has to be more than 0, not {}",
+                name, value
+            ));
+        }
+        let hashing = &self.hashing;
+        if hashing.iterations == 0 || hashing.parallelism == 0 {
+            errors.push(
+                "hashing.iterations and hashing.parallelism have to be more than 0".to_owned(),
+            );
+        } else if hashing.memory < 8 * hashing.parallelism {
+            errors.push("hashing.memory has to be at least 8 KiB per lane".to_owned());
+        }
+        if let Err(err) = KeyRing::load(self) {
+            errors.push(err);
+        }
+        for (i, provider) in self.providers.iter().enumerate() {
+            let name = &provider.name;
+            if name.is_empty()
+                || name.len() > 64
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+            {
+                errors.push(format!(
+                    "providers.name {:?} needs 1 to 64 of a-z, 0-9, - and _",
+                    name
+                ));
+            } else if self.providers[..i].iter().any(|p| &p.name == name) {
+                errors.push(format!("provider {} is listed twice", name));
+            }
+            let https = provider.issuer.starts_with("https://");
+            if !https && !provider.issuer.starts_with("http://") {
+                errors.push(format!(
+                    "provider {}: issuer has to be an http(s) url",
+                    name
+                ));
+            } else if !https && self.environment != Environment::Dev {
+                errors.push(format!(
+                    "provider {}: issuer has to be https outside of dev",
+                    name
+                ));
+            }
+            if provider.client_id.is_empty() {
+                errors.push(format!("provider {}: client_id is missing", name));
+            }
+            if !provider.scope.split_whitespace().any(|s| s == "openid") {
+                errors.push(format!("provider {}: scope has to include openid", name));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CliArgs {
+    config: Option<PathBuf>,
+    //(setting, value) in the order given
+    overrides: Vec<(String, String)>,
+}
+
+//--config <file>, --bind <addr>, --workers <n>, --log <filter>, --env <dev|production>
+//and --set <section.key>=<value> for any other setting
+fn parse_args(args: &[String]) -> Result<CliArgs, String> {
+    let mut cli = CliArgs::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let (flag, inline) = match flag.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+            _ => (flag.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or(format!("{} needs a value", flag))
+        };
+        match flag {
+            "--config" => cli.config = Some(PathBuf::from(value()?)),
+            "--bind" => cli.overrides.push(("server.bind".to_owned(), value()?)),
+            "--workers" => cli.overrides.push(("server.workers".to_owned(), value()?)),
+            "--log" => cli.overrides.push(("log.level".to_owned(), value()?)),
+            "--env" => cli.overrides.push(("environment".to_owned(), value()?)),
+            "--set" => {
+                let setting = value()?;
+                let (key, value) = setting
+                    .split_once('=')
+                    .ok_or(format!("--set {} is not <section.key>=<value>", setting))?;
+                cli.overrides.push((key.to_owned(), value.to_owned()));
+            }
+            other => return Err(format!("unknown flag {}", other)),
+        }
+    }
+    Ok(cli)
+}
+
+fn read_file(path: &PathBuf) -> Result<toml::Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+//tables are merged key by key, anything else is replaced
+fn merge(base: &mut toml::Value, layer: toml::Value) {
+    match (base, layer) {
+        (toml::Value::Table(base), toml::Value::Table(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+//environment and flag values are plain text, they take the type the default has
+fn set(tree: &mut toml::Value, key: &str, raw: &str) -> Result<(), String> {
+    let mut node = tree;
+    for part in key.split('.') {
+        node = node
+            .get_mut(part)
+            .ok_or(format!("there is no setting {}", key))?;
+    }
+    *node = match node {
+        toml::Value::Integer(_) => toml::Value::Integer(
+            raw.parse()
+                .map_err(|_| format!("{} is not a number", raw))?,
+        ),
+        toml::Value::Boolean(_) => toml::Value::Boolean(
+            raw.parse()
+                .map_err(|_| format!("{} is not true or false", raw))?,
+        ),
+        toml::Value::Table(_) => return Err(format!("{} is a section, not a setting", key)),
+        toml::Value::Array(_) => return Err(format!("{} can only be set in the config file", key)),
+        _ => toml::Value::String(raw.to_owned()),
+    };
+    Ok(())
+}
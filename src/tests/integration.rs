@@ -5,7 +5,8 @@ use actix_web::{
     test, web, App,
 };
 use serde::Deserialize;
-use server::{controllers, middlewares, models};
+use server::{controllers, mailer, middlewares, models, totp};
+use std::sync::{Arc, Mutex};
 
 trait BodyTest {
     fn as_str(&self) -> &str;
@@ -15,11 +16,11 @@ impl BodyTest for ResponseBody<Body> {
     fn as_str(&self) -> &str {
         match self {
             ResponseBody::Body(ref b) => match b {
-                Body::Bytes(ref by) => std::str::from_utf8(&by).unwrap(),
+                Body::Bytes(ref by) => std::str::from_utf8(by).unwrap(),
                 _ => panic!(),
             },
             ResponseBody::Other(ref b) => match b {
-                Body::Bytes(by) => std::str::from_utf8(&by).unwrap(),
+                Body::Bytes(by) => std::str::from_utf8(by).unwrap(),
                 _ => panic!(),
             },
         }
@@ -31,6 +32,61 @@ struct Token {
     token: String,
 }
 
+#[derive(Deserialize)]
+struct Tokens {
+    refresh_token: String,
+}
+
+const TEST_PASSWORD: &str = "test_password123";
+
+//who is admin and the admin 2fa policy are shared by the whole database, tests that
+//change them or log in admins without 2fa take turns
+static ADMINS: Mutex<()> = Mutex::new(());
+
+//a fresh user with the password TEST_PASSWORD, the tag goes into name and email
+fn create_test_user(pool: &web::Data<server::db::db::Pool>, tag: &str) -> models::user::User {
+    use models::{dbmethods, user::FindBy};
+    let signup = models::user::UserData {
+        name: tag.to_owned(),
+        email: format!("{}_{}@some_user.com", tag, uuid::Uuid::new_v4().to_simple()),
+        password: TEST_PASSWORD.to_owned(),
+    };
+    let audit = models::audit::AuditContext::default();
+    let user = dbmethods::insert_user(signup, "en", &audit, pool.clone()).unwrap();
+    dbmethods::find_by(FindBy::PublicId(user.id), pool.clone()).unwrap()
+}
+
+fn verify_test_user(pool: &web::Data<server::db::db::Pool>, user: &models::user::User) {
+    use diesel::prelude::*;
+    use server::schema::users::dsl::{email_verified_at, users};
+    diesel::update(users.find(user.id))
+        .set(email_verified_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(&pool.get().unwrap())
+        .unwrap();
+}
+
+//text of the mails queued for an address, oldest first
+fn queued_mails(pool: &web::Data<server::db::db::Pool>, recipient: &str) -> Vec<String> {
+    use diesel::prelude::*;
+    use server::schema::email_outbox::dsl;
+    dsl::email_outbox
+        .filter(dsl::recipient.eq(recipient))
+        .order(dsl::id.asc())
+        .select(dsl::text_body)
+        .load::<String>(&pool.get().unwrap())
+        .unwrap()
+}
+
+//the token of the first link in a mail
+fn mailed_token(text: &str) -> String {
+    let start = text.find("token=").unwrap() + "token=".len();
+    text[start..]
+        .split(|c: char| c.is_whitespace())
+        .next()
+        .unwrap()
+        .to_owned()
+}
+
 #[actix_rt::test]
 async fn test_create_user_at_users_post_route() {
     //connection pool
@@ -38,10 +94,13 @@ async fn test_create_user_at_users_post_route() {
     //post req data
     let user_data =
         models::user::UserInsert::from_details("test", "test@some_user.com", "test_password123");
+    let mailer: web::Data<dyn mailer::Mailer> =
+        web::Data::from(Arc::new(mailer::MemoryMailer::default()) as Arc<dyn mailer::Mailer>);
     //test app
     let mut app = test::init_service(
         App::new()
             .data(pool.clone())
+            .app_data(mailer)
             .route("/users", web::post().to(controllers::users::post_user)),
     )
     .await;
@@ -113,3 +172,1573 @@ async fn test_user_get_route() {
     let resp = test::call_service(&mut app, get_req).await;
     assert_eq!(resp.status(), StatusCode::OK);
 }
+
+#[actix_rt::test]
+async fn test_identity_headers_are_ignored() {
+    let pool = server::db::db::create_connection_pool();
+    let auth_data = models::user::AuthData {
+        email: "test@some_user.com".to_owned(),
+        password: "test_password123".to_owned(),
+    };
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .route("/auth", web::post().to(controllers::auth::login))
+            .configure(server::routes::users::users_route_config),
+    )
+    .await;
+    let login_req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let mut login_resp = test::call_service(&mut app, login_req).await;
+    let auth_token = serde_json::from_str::<Token>(login_resp.take_body().as_str()).unwrap();
+    //a normal user pretending to be an admin
+    let get_req = test::TestRequest::get()
+        .header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", auth_token.token),
+        )
+        .header("user_email", "admin@some_user.com")
+        .header("user_clearance", "admin")
+        .uri("/users")
+        .to_request();
+    let resp = test::call_service(&mut app, get_req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn test_token_is_rejected_after_logout() {
+    let pool = server::db::db::create_connection_pool();
+    let auth_data = models::user::AuthData {
+        email: "test@some_user.com".to_owned(),
+        password: "test_password123".to_owned(),
+    };
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .route("/auth", web::post().to(controllers::auth::login))
+            .route("/auth", web::delete().to(controllers::auth::logout))
+            .route("/user", web::get().to(controllers::user::get_me)),
+    )
+    .await;
+    let login_req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let mut login_resp = test::call_service(&mut app, login_req).await;
+    let auth_token = serde_json::from_str::<Token>(login_resp.take_body().as_str()).unwrap();
+    let bearer = format!("Bearer {}", auth_token.token);
+    let logout_req = test::TestRequest::delete()
+        .header(header::AUTHORIZATION, bearer.clone())
+        .uri("/auth")
+        .to_request();
+    let resp = test::call_service(&mut app, logout_req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    //same token again
+    let get_req = test::TestRequest::get()
+        .header(header::AUTHORIZATION, bearer)
+        .uri("/user")
+        .to_request();
+    let resp = test::call_service(&mut app, get_req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn test_refresh_token_rotation_and_reuse() {
+    let pool = server::db::db::create_connection_pool();
+    let auth_data = models::user::AuthData {
+        email: "test@some_user.com".to_owned(),
+        password: "test_password123".to_owned(),
+    };
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .route("/auth", web::post().to(controllers::auth::login))
+            .route("/auth/refresh", web::post().to(controllers::auth::refresh)),
+    )
+    .await;
+    let login_req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let mut login_resp = test::call_service(&mut app, login_req).await;
+    let first = serde_json::from_str::<Tokens>(login_resp.take_body().as_str()).unwrap();
+    let refresh = |token: &str| {
+        test::TestRequest::post()
+            .set_json(&models::session::RefreshData {
+                refresh_token: token.to_owned(),
+            })
+            .uri("/auth/refresh")
+            .to_request()
+    };
+    let mut resp = test::call_service(&mut app, refresh(&first.refresh_token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let second = serde_json::from_str::<Tokens>(resp.take_body().as_str()).unwrap();
+    //replaying the rotated token revokes the family
+    let resp = test::call_service(&mut app, refresh(&first.refresh_token)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = test::call_service(&mut app, refresh(&second.refresh_token)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_password_hashes_are_salted() {
+    let first = server::utils::hash_password("test_password123").unwrap();
+    let second = server::utils::hash_password("test_password123").unwrap();
+    assert_ne!(first, second);
+    assert!(server::utils::verify_hash(&first, "test_password123").unwrap());
+    assert!(!server::utils::HASH_POLICY.is_weaker(&first));
+    //broken parameters count as weaker instead of panicking
+    assert!(server::utils::HASH_POLICY.is_weaker("$argon2id$v=19$m=1,,t=2$salt$hash"));
+    assert!(server::utils::HASH_POLICY.is_weaker("$argon2id$v=19$é=1,t=2,p=1$salt$hash"));
+}
+
+#[test]
+fn test_settings_layers_and_validation() {
+    use server::settings::{Environment, MailTransport, Settings};
+    let args = |list: &[&str]| list.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+    //without --env the built in secret is refused
+    assert!(Settings::from_sources(&[])
+        .unwrap()
+        .validate()
+        .unwrap_err()
+        .contains("secret_key"));
+    let settings = Settings::from_sources(&args(&[
+        "--env",
+        "dev",
+        "--bind",
+        "127.0.0.1:9000",
+        "--set",
+        "auth.session_days=7",
+        "--set=database.pool_size=3",
+    ]))
+    .unwrap();
+    assert_eq!(settings.server.bind, "127.0.0.1:9000");
+    assert_eq!(settings.auth.session_days, 7);
+    assert_eq!(settings.database.pool_size, 3);
+    assert_eq!(settings.environment, Environment::Dev);
+    assert!(settings.validate().is_ok());
+    //wrong types and unknown settings fail instead of falling back to the default
+    assert!(Settings::from_sources(&args(&["--set", "auth.session_days=week"])).is_err());
+    assert!(Settings::from_sources(&args(&["--set", "auth.sesion_days=7"])).is_err());
+    assert!(Settings::from_sources(&args(&["--port", "80"])).is_err());
+    assert!(Settings::from_sources(&args(&["--set", "mail.transport=smpt"])).is_err());
+    //production refuses the built in secret
+    let mut production = settings;
+    production.environment = Environment::Production;
+    production.auth.secret_key = server::settings::DEV_SECRET_KEY.to_owned();
+    assert!(production.validate().unwrap_err().contains("secret_key"));
+    production.auth.secret_key = "a".repeat(64);
+    //and the mail transports that print tokens or keep them in memory
+    assert!(production
+        .validate()
+        .unwrap_err()
+        .contains("mail.transport"));
+    production.mail.transport = MailTransport::Memory;
+    assert!(production
+        .validate()
+        .unwrap_err()
+        .contains("mail.transport"));
+    production.mail.transport = MailTransport::Smtp;
+    assert!(production.validate().is_ok());
+    production.auth.reauth_minutes = 0;
+    assert!(production
+        .validate()
+        .unwrap_err()
+        .contains("reauth_minutes"));
+}
+
+#[actix_rt::test]
+async fn test_jwt_keys_rotate_and_are_published() {
+    use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+    use server::{
+        keys::KeyRing,
+        settings::{JwtKey, Settings},
+    };
+    let key = |kid: &str, algorithm, file: &str| JwtKey {
+        kid: kid.to_owned(),
+        algorithm,
+        public_key: format!("src/tests/keys/{}.pub.pem", file).into(),
+        private_key: Some(format!("src/tests/keys/{}.pem", file).into()),
+    };
+    let mut settings = Settings::default();
+    settings.jwt.keys = vec![
+        key("old", Algorithm::RS256, "rs256"),
+        key("new", Algorithm::ES256, "es256"),
+        key("next", Algorithm::EdDSA, "ed25519"),
+    ];
+    let claims = serde_json::json!({ "sub": 1, "exp": chrono::Utc::now().timestamp() + 60 });
+    //tokens of every listed key are accepted while they are listed
+    let mut tokens = Vec::new();
+    for kid in ["old", "new", "next"] {
+        settings.jwt.signing_key = kid.to_owned();
+        let ring = KeyRing::load(&settings).unwrap();
+        tokens.push(ring.sign(&claims).unwrap());
+    }
+    let ring = KeyRing::load(&settings).unwrap();
+    assert!(ring.signs_with_public_key());
+    assert!(!KeyRing::load(&Settings::default())
+        .unwrap()
+        .signs_with_public_key());
+    for token in &tokens {
+        assert!(ring.verify::<serde_json::Value>(token).is_ok());
+    }
+    //a service with only the jwks can check them too
+    let jwks: JwkSet = serde_json::from_value(serde_json::to_value(ring.jwks()).unwrap()).unwrap();
+    assert_eq!(jwks.keys.len(), 3);
+    for (token, kid) in tokens.iter().zip(["old", "new", "next"]) {
+        let jwk = jwks.find(kid).unwrap();
+        let header = jsonwebtoken::decode_header(token).unwrap();
+        assert_eq!(header.kid.as_deref(), Some(kid));
+        let key = DecodingKey::from_jwk(jwk).unwrap();
+        let data =
+            jsonwebtoken::decode::<serde_json::Value>(token, &key, &Validation::new(header.alg));
+        assert!(data.is_ok());
+    }
+    //dropping a key from the list retires its tokens, secret tokens stop once a key signs
+    settings.jwt.keys.remove(0);
+    let ring = KeyRing::load(&settings).unwrap();
+    assert!(ring.verify::<serde_json::Value>(&tokens[0]).is_err());
+    assert!(ring.verify::<serde_json::Value>(&tokens[1]).is_ok());
+    let secret_token = jsonwebtoken::encode(
+        &Header::new(Algorithm::HS512),
+        &claims,
+        &EncodingKey::from_secret(settings.auth.secret_key.as_bytes()),
+    )
+    .unwrap();
+    assert!(ring.verify::<serde_json::Value>(&secret_token).is_err());
+    //mixed up key files are caught when loading
+    settings.jwt.keys[0].private_key = Some("src/tests/keys/ed25519.pem".into());
+    settings.jwt.signing_key = "new".to_owned();
+    assert!(KeyRing::load(&settings).is_err());
+    settings.jwt.keys[0].algorithm = Algorithm::EdDSA;
+    assert!(KeyRing::load(&settings).is_err());
+    settings.jwt.signing_key = "gone".to_owned();
+    assert!(KeyRing::load(&settings).is_err());
+
+    let mut app = test::init_service(App::new().wrap(middlewares::auth::Auth).route(
+        "/.well-known/jwks.json",
+        web::get().to(controllers::auth::jwks),
+    ))
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/.well-known/jwks.json")
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn test_password_reset_does_not_reveal_accounts() {
+    let pool = server::db::db::create_connection_pool();
+    let mailer: web::Data<dyn mailer::Mailer> =
+        web::Data::from(Arc::new(mailer::MemoryMailer::default()) as Arc<dyn mailer::Mailer>);
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .app_data(mailer)
+            .wrap(middlewares::auth::Auth)
+            .route(
+                "/auth/password-reset",
+                web::post().to(controllers::auth::request_password_reset),
+            ),
+    )
+    .await;
+    let req = test::TestRequest::post()
+        .set_json(&models::password_reset::ResetRequest {
+            email: "nobody@some_user.com".to_owned(),
+        })
+        .uri("/auth/password-reset")
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+}
+
+#[test]
+fn test_email_templates_fall_back_by_locale() {
+    let vars = [("name", "<b>test</b>"), ("minutes", "60"), ("link", "x")];
+    let german =
+        mailer::templates::render("password_reset", "de-AT", "test@some_user.com", &vars).unwrap();
+    assert_eq!(german.subject, "passwort zurücksetzen");
+    assert!(german.text.contains("<b>test</b>"));
+    assert!(german.html.unwrap().contains("&lt;b&gt;test&lt;/b&gt;"));
+    let fallback =
+        mailer::templates::render("password_reset", "fr", "test@some_user.com", &vars).unwrap();
+    assert_eq!(fallback.subject, "reset your password");
+}
+
+#[actix_rt::test]
+async fn test_email_verification_and_resend() {
+    use models::user::{EmailData, UnverifiedLogin, VerifyData};
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let mailer: web::Data<dyn mailer::Mailer> =
+        web::Data::from(Arc::new(mailer::MemoryMailer::default()) as Arc<dyn mailer::Mailer>);
+    let mut app = test::init_service(
+        App::new()
+            .app_data(pool.clone())
+            .app_data(mailer)
+            .wrap(middlewares::auth::Auth)
+            .configure(server::routes::auth::auth_route_config)
+            .configure(server::routes::user::user_route_config)
+            .configure(server::routes::users::users_route_config),
+    )
+    .await;
+    let user = create_test_user(&pool, "verify");
+    let login = || {
+        test::TestRequest::post()
+            .set_json(&models::user::AuthData {
+                email: user.email.clone(),
+                password: TEST_PASSWORD.to_owned(),
+            })
+            .uri("/auth")
+            .to_request()
+    };
+    let profile = |token: &str| {
+        test::TestRequest::get()
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .uri("/user/profile")
+            .to_request()
+    };
+    //the default lets unverified users log in, but only into a few routes
+    let restricted: Token = test::read_response_json(&mut app, login()).await;
+    let resp = test::call_service(&mut app, profile(&restricted.token)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(!UnverifiedLogin::Deny.admits(&user));
+    assert!(UnverifiedLogin::Restricted.admits(&user));
+    //a resend inside the cooldown, one for an unknown address and a real one all look the same
+    let resend = |email: &str| {
+        test::TestRequest::post()
+            .set_json(&EmailData {
+                email: email.to_owned(),
+            })
+            .uri("/users/verify-email/resend")
+            .to_request()
+    };
+    for email in [user.email.as_str(), "nobody@some_user.com", &user.email] {
+        let resp = test::call_service(&mut app, resend(email)).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    }
+    let mails = queued_mails(&pool, &user.email);
+    assert_eq!(mails.len(), 1);
+    let verify = |token: &str| {
+        test::TestRequest::post()
+            .set_json(&VerifyData {
+                token: token.to_owned(),
+            })
+            .uri("/users/verify-email")
+            .to_request()
+    };
+    let token = mailed_token(&mails[0]);
+    let resp = test::call_service(&mut app, verify(&format!("{}x", token))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&mut app, verify(&token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&mut app, verify(&token)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    //a new login carries the verified email
+    let verified: Token = test::read_response_json(&mut app, login()).await;
+    let resp = test::call_service(&mut app, profile(&verified.token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let user = models::dbmethods::find_by(models::user::FindBy::Id(user.id), pool).unwrap();
+    assert!(UnverifiedLogin::Deny.admits(&user));
+}
+
+#[actix_rt::test]
+async fn test_password_change_needs_reauthentication() {
+    let pool = server::db::db::create_connection_pool();
+    let mailer: web::Data<dyn mailer::Mailer> =
+        web::Data::from(Arc::new(mailer::MemoryMailer::default()) as Arc<dyn mailer::Mailer>);
+    let auth_data = models::user::AuthData {
+        email: "test@some_user.com".to_owned(),
+        password: "test_password123".to_owned(),
+    };
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .app_data(mailer)
+            .wrap(middlewares::auth::Auth)
+            .route("/auth", web::post().to(controllers::auth::login))
+            .route("/user", web::patch().to(controllers::user::update_user)),
+    )
+    .await;
+    let login_req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let mut login_resp = test::call_service(&mut app, login_req).await;
+    let auth_token = serde_json::from_str::<Token>(login_resp.take_body().as_str()).unwrap();
+    let patch_req = test::TestRequest::patch()
+        .header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", auth_token.token),
+        )
+        .set_json(&models::user::UserChangeRequest {
+            password: Some("new_password123".to_owned()),
+            current_password: Some("wrong_password".to_owned()),
+            ..Default::default()
+        })
+        .uri("/user")
+        .to_request();
+    let resp = test::call_service(&mut app, patch_req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+//sending the queued mails spawns on the actix 1 runtime
+#[test]
+fn test_email_change_waits_for_the_new_address() {
+    actix_web::rt::System::new("email_change").block_on(email_change_flow());
+}
+
+async fn email_change_flow() {
+    use models::user::{UserChangeRequest, VerifyData};
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let mailer: web::Data<dyn mailer::Mailer> =
+        web::Data::from(Arc::new(mailer::MemoryMailer::default()) as Arc<dyn mailer::Mailer>);
+    let mut app = test::init_service(
+        App::new()
+            .app_data(pool.clone())
+            .app_data(mailer)
+            .wrap(middlewares::auth::Auth)
+            .configure(server::routes::auth::auth_route_config)
+            .configure(server::routes::user::user_route_config),
+    )
+    .await;
+    let user = create_test_user(&pool, "mover");
+    verify_test_user(&pool, &user);
+    let req = test::TestRequest::post()
+        .set_json(&models::user::AuthData {
+            email: user.email.clone(),
+            password: TEST_PASSWORD.to_owned(),
+        })
+        .uri("/auth")
+        .to_request();
+    let login: Token = test::read_response_json(&mut app, req).await;
+    let bearer = format!("Bearer {}", login.token);
+    let patch = |change: UserChangeRequest| {
+        test::TestRequest::patch()
+            .header(header::AUTHORIZATION, bearer.as_str())
+            .set_json(&change)
+            .uri("/user")
+            .to_request()
+    };
+    let new_email = user.email.replacen("mover_", "moved_", 1);
+    //a part that fails takes the email change and its mails with it
+    let resp = test::call_service(
+        &mut app,
+        patch(UserChangeRequest {
+            email: Some(new_email.clone()),
+            name: Some("x".repeat(101)),
+            current_password: Some(TEST_PASSWORD.to_owned()),
+            ..Default::default()
+        }),
+    )
+    .await;
+    assert!(!resp.status().is_success());
+    assert!(queued_mails(&pool, &new_email).is_empty());
+    let sent_before = queued_mails(&pool, &user.email).len();
+    let changed: serde_json::Value = test::read_response_json(
+        &mut app,
+        patch(UserChangeRequest {
+            email: Some(new_email.clone()),
+            current_password: Some(TEST_PASSWORD.to_owned()),
+            ..Default::default()
+        }),
+    )
+    .await;
+    assert_eq!(changed["email"], user.email.as_str());
+    assert_eq!(changed["pending_email"], new_email.as_str());
+    //the confirmation goes to the new address, a notice to the old one
+    let confirmation = queued_mails(&pool, &new_email);
+    assert_eq!(confirmation.len(), 1);
+    let notices = queued_mails(&pool, &user.email);
+    assert_eq!(notices.len(), sent_before + 1);
+    assert!(notices.last().unwrap().contains(&new_email));
+    let confirm = |token: &str| {
+        test::TestRequest::post()
+            .header(header::AUTHORIZATION, bearer.as_str())
+            .set_json(&VerifyData {
+                token: token.to_owned(),
+            })
+            .uri("/user/email/confirm")
+            .to_request()
+    };
+    let token = mailed_token(&confirmation[0]);
+    let resp = test::call_service(&mut app, confirm(&format!("{}x", token))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let fresh: Token = test::read_response_json(&mut app, confirm(&token)).await;
+    //the old token is logged out, the one handed back carries the new address
+    let me = |token: &str| {
+        test::TestRequest::get()
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .uri("/user")
+            .to_request()
+    };
+    let resp = test::call_service(&mut app, me(&login.token)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let me: serde_json::Value = test::read_response_json(&mut app, me(&fresh.token)).await;
+    assert_eq!(me["email"], new_email.as_str());
+    assert_eq!(me["email_verified"], true);
+}
+
+#[test]
+fn test_totp_matches_rfc6238() {
+    //base32 of the rfc test secret "12345678901234567890", T = 59s is step 1
+    let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    assert_eq!(totp::code_at(secret, 1).as_deref(), Some("287082"));
+    assert_eq!(totp::verify(secret, "287082", 2), Some(1));
+    assert_eq!(totp::verify(secret, "287082", 3), None);
+}
+
+#[test]
+fn test_second_factor_login() {
+    let _admins = ADMINS.lock().unwrap_or_else(|err| err.into_inner());
+    actix_web::rt::System::new("second_factor").block_on(second_factor_login());
+}
+
+async fn second_factor_login() {
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let audit = models::audit::AuditContext::default();
+    let mut app = test::init_service(
+        App::new()
+            .app_data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .configure(server::routes::auth::auth_route_config)
+            .configure(server::routes::user::user_route_config)
+            .configure(server::routes::users::users_route_config)
+            .configure(server::routes::admin::admin_route_config),
+    )
+    .await;
+    let user = create_test_user(&pool, "twofactor");
+    verify_test_user(&pool, &user);
+    models::dbmethods::assign_role(user.id, Some("admin"), &audit, pool.clone()).unwrap();
+    let post = |uri: &str, token: Option<&str>, body: serde_json::Value| {
+        let mut req = test::TestRequest::post().uri(uri).set_json(&body);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        req.to_request()
+    };
+    let login = || {
+        let body = serde_json::json!({ "email": user.email, "password": TEST_PASSWORD });
+        post("/auth", None, body)
+    };
+    let users_page = |token: &str| {
+        test::TestRequest::get()
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .uri("/users")
+            .to_request()
+    };
+    let set_policy = |token: &str, on: bool| {
+        test::TestRequest::put()
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .set_json(&models::two_factor::TwoFactorPolicy {
+                require_for_admins: on,
+            })
+            .uri("/admin/2fa-policy")
+            .to_request()
+    };
+    //with the policy on an admin without 2fa is turned away
+    let plain: Token = test::read_response_json(&mut app, login()).await;
+    let resp = test::call_service(&mut app, set_policy(&plain.token, true)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&mut app, users_page(&plain.token)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let started: serde_json::Value = test::read_response_json(
+        &mut app,
+        post("/user/2fa", Some(&plain.token), serde_json::json!({})),
+    )
+    .await;
+    let secret = started["secret"].as_str().unwrap().to_owned();
+    let code = totp::code_at(&secret, totp::current_step()).unwrap();
+    let confirmed: serde_json::Value = test::read_response_json(
+        &mut app,
+        post(
+            "/user/2fa/confirm",
+            Some(&plain.token),
+            serde_json::json!({ "code": code }),
+        ),
+    )
+    .await;
+    let recovery = confirmed["recovery_codes"].as_array().unwrap().clone();
+    //the password alone now only gets a challenge
+    let second_step = |mfa_token: &serde_json::Value, code: &str| {
+        let body = serde_json::json!({ "mfa_token": mfa_token, "code": code });
+        post("/auth/2fa", None, body)
+    };
+    let challenge: serde_json::Value = test::read_response_json(&mut app, login()).await;
+    assert_eq!(challenge["mfa_required"], true);
+    assert!(challenge.get("token").is_none());
+    let mfa_token = &challenge["mfa_token"];
+    //the code that turned 2fa on was used already
+    let resp = test::call_service(&mut app, second_step(mfa_token, &code)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let recovery_code = |n: usize| recovery[n].as_str().unwrap().to_owned();
+    let mut resp = test::call_service(&mut app, second_step(mfa_token, &recovery_code(0))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let with_mfa = serde_json::from_str::<Token>(resp.take_body().as_str()).unwrap();
+    let resp = test::call_service(&mut app, users_page(&with_mfa.token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&mut app, set_policy(&with_mfa.token, false)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    //a passed challenge and a used recovery code do not work again
+    let resp = test::call_service(&mut app, second_step(mfa_token, &recovery_code(1))).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let challenge: serde_json::Value = test::read_response_json(&mut app, login()).await;
+    let mfa_token = &challenge["mfa_token"];
+    let resp = test::call_service(&mut app, second_step(mfa_token, &recovery_code(0))).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    //a challenge is burned after a few wrong codes, the right one does not help then
+    for _ in 1..server::utils::MFA_CHALLENGE_FAILURES {
+        let resp = test::call_service(&mut app, second_step(mfa_token, "wrong")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+    let resp = test::call_service(&mut app, second_step(mfa_token, &recovery_code(1))).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    //and too many wrong codes lock the user out of new challenges as well
+    let challenge: serde_json::Value = test::read_response_json(&mut app, login()).await;
+    let mfa_token = &challenge["mfa_token"];
+    let mut status = StatusCode::UNAUTHORIZED;
+    for _ in 0..server::utils::MFA_CHALLENGE_FAILURES {
+        status = test::call_service(&mut app, second_step(mfa_token, "wrong"))
+            .await
+            .status();
+        if status != StatusCode::UNAUTHORIZED {
+            break;
+        }
+    }
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let resp = test::call_service(&mut app, second_step(mfa_token, &recovery_code(1))).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn test_failed_login_is_audited() {
+    use diesel::prelude::*;
+    use server::schema::audit_events::dsl::{audit_events, event_type, request_id, target_id};
+    let pool = server::db::db::create_connection_pool();
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .route("/auth", web::post().to(controllers::auth::login)),
+    )
+    .await;
+    let request = uuid::Uuid::new_v4().to_string();
+    let login_req = test::TestRequest::post()
+        .header("x-request-id", request.as_str())
+        .set_json(&models::user::AuthData {
+            email: "test@some_user.com".to_owned(),
+            password: "wrong_password".to_owned(),
+        })
+        .uri("/auth")
+        .to_request();
+    let resp = test::call_service(&mut app, login_req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let conn = pool.get().unwrap();
+    let (event, target) = audit_events
+        .filter(request_id.eq(&request))
+        .select((event_type, target_id))
+        .first::<(String, Option<i64>)>(&conn)
+        .unwrap();
+    assert_eq!(event, "login_failed");
+    assert!(target.is_some());
+}
+
+#[actix_rt::test]
+async fn test_wrong_passwords_lock_the_account() {
+    use diesel::prelude::*;
+    use server::schema::audit_events::dsl::{audit_events, event_type, target_id};
+    use server::utils::PASSWORD_FAILURES;
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let mailer: web::Data<dyn mailer::Mailer> =
+        web::Data::from(Arc::new(mailer::MemoryMailer::default()) as Arc<dyn mailer::Mailer>);
+    let user = create_test_user(&pool, "lockout");
+    verify_test_user(&pool, &user);
+    let mut app = test::init_service(
+        App::new()
+            .app_data(pool.clone())
+            .app_data(mailer)
+            .wrap(middlewares::auth::Auth)
+            .configure(server::routes::auth::auth_route_config)
+            .configure(server::routes::user::user_route_config),
+    )
+    .await;
+    let login = || {
+        test::TestRequest::post()
+            .uri("/auth")
+            .set_json(&serde_json::json!({ "email": user.email, "password": TEST_PASSWORD }))
+            .to_request()
+    };
+    let session: Token = test::read_response_json(&mut app, login()).await;
+    let reauth = |password: &str| {
+        test::TestRequest::post()
+            .uri("/auth/reauth")
+            .header(header::AUTHORIZATION, format!("Bearer {}", session.token))
+            .set_json(&serde_json::json!({ "password": password }))
+            .to_request()
+    };
+    for _ in 1..PASSWORD_FAILURES {
+        let resp = test::call_service(&mut app, reauth("wrong_password")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+    //current_password counts towards the same lock
+    let req = test::TestRequest::patch()
+        .uri("/user")
+        .header(header::AUTHORIZATION, format!("Bearer {}", session.token))
+        .set_json(&models::user::UserChangeRequest {
+            password: Some("new_password123".to_owned()),
+            current_password: Some("wrong_password".to_owned()),
+            ..Default::default()
+        })
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    //from now on even the right password is turned away, at the login too
+    let resp = test::call_service(&mut app, reauth("wrong_password")).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let resp = test::call_service(&mut app, reauth(TEST_PASSWORD)).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let resp = test::call_service(&mut app, login()).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let conn = pool.get().unwrap();
+    let audited = audit_events
+        .filter(target_id.eq(user.id))
+        .filter(event_type.eq("reauth_failed"))
+        .count()
+        .get_result::<i64>(&conn)
+        .unwrap();
+    assert_eq!(audited, PASSWORD_FAILURES as i64 + 2);
+    //the lock ends with the window
+    {
+        use server::schema::password_failures::dsl::{password_failures, window_started_at};
+        diesel::update(password_failures.find(user.id))
+            .set(window_started_at.eq(chrono::Utc::now().naive_utc() - chrono::Duration::hours(1)))
+            .execute(&conn)
+            .unwrap();
+    }
+    let resp = test::call_service(&mut app, reauth(TEST_PASSWORD)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_users_are_paged_with_a_cursor() {
+    use models::user::{UserData, UserQuery, UserSort};
+    let pool = server::db::db::create_connection_pool();
+    let audit = models::audit::AuditContext::default();
+    let tag = uuid::Uuid::new_v4().to_simple().to_string();
+    for n in 0..3 {
+        let user = UserData {
+            name: format!("pager {}", n),
+            email: format!("pager{}_{}@some_user.com", n, tag),
+            password: "test_password123".to_owned(),
+        };
+        models::dbmethods::insert_user(user, "en", &audit, web::Data::new(pool.clone())).unwrap();
+    }
+    let query = |cursor: Option<String>| UserQuery {
+        limit: Some(2),
+        cursor,
+        q: Some(tag.clone()),
+        sort: UserSort::CreatedDesc,
+        count: true,
+        ..Default::default()
+    };
+    let first = models::dbmethods::get_users(query(None), web::Data::new(pool.clone())).unwrap();
+    assert_eq!(first.total, Some(3));
+    assert_eq!(first.users.len(), 2);
+    assert!(first.users[0].email.starts_with("pager2_"));
+    let second =
+        models::dbmethods::get_users(query(first.next_cursor), web::Data::new(pool.clone()))
+            .unwrap();
+    assert_eq!(second.users.len(), 1);
+    assert!(second.users[0].email.starts_with("pager0_"));
+    assert_eq!(second.next_cursor, None);
+}
+
+#[test]
+fn test_user_search_tolerates_typos() {
+    use models::user::{SearchHit, UserData};
+    let pool = server::db::db::create_connection_pool();
+    let audit = models::audit::AuditContext::default();
+    let tag = uuid::Uuid::new_v4().to_simple().to_string();
+    let user = UserData {
+        name: "John Smith".to_owned(),
+        email: format!("smith_{}@some_user.com", tag),
+        password: "test_password123".to_owned(),
+    };
+    let user =
+        models::dbmethods::insert_user(user, "en", &audit, web::Data::new(pool.clone())).unwrap();
+    let rows = models::dbmethods::search_users(
+        &format!("Jon Smtih {}", tag),
+        Some(5),
+        web::Data::new(pool.clone()),
+    )
+    .unwrap();
+    let hit = SearchHit::from(rows.into_iter().next().unwrap());
+    assert_eq!(hit.id, user.id);
+    assert_eq!(hit.highlight.name, "<mark>John</mark> <mark>Smith</mark>");
+    assert!(models::dbmethods::search_users("@@", None, web::Data::new(pool)).is_err());
+}
+
+#[test]
+fn test_role_changes_keep_an_admin() {
+    use diesel::prelude::*;
+    use models::dbmethods::assign_role;
+    use server::errors::ServiceError;
+    use server::schema::{audit_events, roles, user_roles};
+    let _admins = ADMINS.lock().unwrap_or_else(|err| err.into_inner());
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let admins = || {
+        user_roles::table
+            .inner_join(roles::table)
+            .filter(roles::name.eq("admin"))
+            .select(user_roles::user_id)
+            .load::<i64>(&pool.get().unwrap())
+            .unwrap()
+    };
+    //start without any admin
+    diesel::delete(user_roles::table.filter(user_roles::user_id.eq_any(admins())))
+        .execute(&pool.get().unwrap())
+        .unwrap();
+    let first = create_test_user(&pool, "first_admin");
+    let second = create_test_user(&pool, "second_admin");
+    let audit = models::audit::AuditContext::default();
+    assign_role(first.id, Some("admin"), &audit, pool.clone()).unwrap();
+    //the only admin stays one, whoever asks
+    let own = assign_role(first.id, None, &audit.as_actor(first.id), pool.clone());
+    assert!(matches!(own, Err(ServiceError::Forbidden(msg)) if msg.contains("demote yourself")));
+    let other = assign_role(first.id, None, &audit.as_actor(second.id), pool.clone());
+    assert!(matches!(other, Err(ServiceError::Forbidden(msg)) if msg.contains("last admin")));
+    //the audit log has who gave which role to whom
+    let request = uuid::Uuid::new_v4().to_string();
+    let by_first = models::audit::AuditContext {
+        actor_id: Some(first.id),
+        request_id: Some(request.clone()),
+        ..Default::default()
+    };
+    let access = assign_role(second.id, Some("admin"), &by_first, pool.clone()).unwrap();
+    assert_eq!(access.roles, vec!["admin".to_owned()]);
+    let (actor, target, data) = audit_events::table
+        .filter(audit_events::request_id.eq(&request))
+        .filter(audit_events::event_type.eq("role_changed"))
+        .select((
+            audit_events::actor_id,
+            audit_events::target_id,
+            audit_events::data,
+        ))
+        .first::<(Option<i64>, Option<i64>, serde_json::Value)>(&pool.get().unwrap())
+        .unwrap();
+    assert_eq!((actor, target), (Some(first.id), Some(second.id)));
+    assert_eq!(data, serde_json::json!({ "from": [], "to": ["admin"] }));
+    //the admin api only shows and takes public ids
+    let query = models::audit::AuditQuery {
+        target: Some(second.public_id),
+        event: Some(models::audit::AuditEventType::RoleChanged),
+        ..Default::default()
+    };
+    let page = server::audit::AuditLog::search(query, pool.clone()).unwrap();
+    assert_eq!(page.events[0].actor_id, Some(first.public_id));
+    assert_eq!(page.events[0].target_id, Some(second.public_id));
+    //two admins demoting each other at once leave one of them
+    let barrier = Arc::new(std::sync::Barrier::new(2));
+    let demotions = vec![(first.id, second.id), (second.id, first.id)]
+        .into_iter()
+        .map(|(target, actor)| {
+            let (pool, barrier) = (pool.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                let audit = models::audit::AuditContext::default().as_actor(actor);
+                barrier.wait();
+                assign_role(target, None, &audit, pool)
+            })
+        })
+        .collect::<Vec<_>>();
+    let passed = demotions
+        .into_iter()
+        .map(|demotion| demotion.join().unwrap())
+        .filter(Result::is_ok)
+        .count();
+    assert_eq!(passed, 1);
+    assert_eq!(admins().len(), 1);
+}
+
+#[actix_rt::test]
+async fn test_users_are_looked_up_by_public_id() {
+    let pool = server::db::db::create_connection_pool();
+    let auth_data = models::user::AuthData {
+        email: "test@some_user.com".to_owned(),
+        password: "test_password123".to_owned(),
+    };
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .route("/auth", web::post().to(controllers::auth::login))
+            .configure(server::routes::user::user_route_config),
+    )
+    .await;
+    let login_req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let mut login_resp = test::call_service(&mut app, login_req).await;
+    let auth_token = serde_json::from_str::<Token>(login_resp.take_body().as_str()).unwrap();
+    let bearer = format!("Bearer {}", auth_token.token);
+    let me_req = test::TestRequest::get()
+        .header(header::AUTHORIZATION, bearer.as_str())
+        .uri("/user")
+        .to_request();
+    let mut me_resp = test::call_service(&mut app, me_req).await;
+    let me = serde_json::from_str::<serde_json::Value>(me_resp.take_body().as_str()).unwrap();
+    let public_id = me["id"].as_str().unwrap().to_owned();
+    assert!(uuid::Uuid::parse_str(&public_id).is_ok());
+    let get_req = test::TestRequest::get()
+        .header(header::AUTHORIZATION, bearer.as_str())
+        .uri(&format!("/user/{}", public_id))
+        .to_request();
+    let resp = test::call_service(&mut app, get_req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    //the sequential ids are not accepted anymore
+    let get_req = test::TestRequest::get()
+        .header(header::AUTHORIZATION, bearer.as_str())
+        .uri("/user/1")
+        .to_request();
+    let resp = test::call_service(&mut app, get_req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_profile_fields_follow_visibility() {
+    use models::profile::{FieldVisibility, ProfileSettings, ProfileView, Relation, Visibility};
+    let user = models::user::User {
+        id: 1,
+        name: "Jane".to_owned(),
+        email: "jane@some_user.com".to_owned(),
+        password: String::new(),
+        created_at: chrono::NaiveDate::from_ymd_opt(2021, 11, 13)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap(),
+        token_version: 0,
+        email_verified_at: None,
+        totp_secret: None,
+        totp_enabled_at: None,
+        totp_last_step: None,
+        public_id: uuid::Uuid::new_v4(),
+        deleted_at: None,
+        purge_after: None,
+    };
+    let settings = ProfileSettings {
+        avatar_url: Some("https://img.some_user.com/jane.png".to_owned()),
+        visibility: FieldVisibility {
+            email: Visibility::Owner,
+            name: Visibility::Public,
+            joined: Visibility::Authenticated,
+            avatar: Visibility::Admins,
+        },
+    };
+    let fields = |viewer| {
+        let view = ProfileView::project(user.clone(), settings.clone(), viewer);
+        let mut keys = serde_json::to_value(view)
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.join(",")
+    };
+    assert_eq!(fields(Relation::Anonymous), "id,name");
+    assert_eq!(fields(Relation::Authenticated), "id,joined,name");
+    assert_eq!(fields(Relation::Owner), "avatar_url,email,id,joined,name");
+}
+
+#[test]
+fn test_deleted_accounts_can_be_restored_until_purged() {
+    use diesel::prelude::*;
+    use models::{dbmethods, user::FindBy};
+    use server::schema::users::dsl::{purge_after, users};
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let audit = models::audit::AuditContext::default();
+    let first = create_test_user(&pool, "leaver");
+    let email = first.email.clone();
+    assert!(dbmethods::delete_account(first.id, &audit, pool.clone())
+        .unwrap()
+        .is_some());
+    //the password still works, but only to cancel the deletion
+    let login = models::user::AuthData {
+        email: email.clone(),
+        password: TEST_PASSWORD.to_owned(),
+    };
+    let deleted = dbmethods::login_user(login, &audit, pool.clone()).unwrap();
+    assert_eq!(deleted.id, first.id);
+    assert!(deleted.deleted_at.is_some());
+    assert!(dbmethods::restore_account(first.id, Some(-1), &audit, pool.clone()).is_err());
+    dbmethods::restore_account(first.id, Some(deleted.token_version), &audit, pool.clone())
+        .unwrap();
+    //while it is deleted only restoring finds it by its public id
+    dbmethods::delete_account(first.id, &audit, pool.clone()).unwrap();
+    assert!(dbmethods::find_by(FindBy::PublicId(first.public_id), pool.clone()).is_err());
+    let tombstone = dbmethods::find_with_deleted(first.public_id, pool.clone()).unwrap();
+    assert!(tombstone.deleted_at.is_some());
+    //and the email is free for a new account
+    let signup = models::user::UserData {
+        name: "leaver".to_owned(),
+        email,
+        password: TEST_PASSWORD.to_owned(),
+    };
+    dbmethods::insert_user(signup, "en", &audit, pool.clone()).unwrap();
+    assert!(dbmethods::restore_account(first.id, None, &audit, pool.clone()).is_err());
+    let conn = pool.get().unwrap();
+    diesel::update(users.find(first.id))
+        .set(purge_after.eq(chrono::Utc::now().naive_utc()))
+        .execute(&conn)
+        .unwrap();
+    assert!(dbmethods::purge_deleted_accounts(&pool).unwrap() >= 1);
+    assert!(dbmethods::find_by(FindBy::Id(first.id), pool.clone()).is_err());
+}
+
+#[test]
+fn test_queued_export_downloads_once() {
+    use models::{audit::AuditEventType, dbmethods, export::ExportFormat};
+    use server::audit::AuditLog;
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let audit = models::audit::AuditContext::default();
+    let user = create_test_user(&pool, "exporter");
+    let admin = create_test_user(&pool, "exportadmin");
+    //one event from the user, one an admin caused
+    let from = |actor: i64, ip: &str| models::audit::AuditContext {
+        actor_id: Some(actor),
+        ip: Some(ip.to_owned()),
+        user_agent: Some(format!("agent of {}", actor)),
+        request_id: None,
+    };
+    let events = &[
+        (
+            from(user.id, "198.51.100.7"),
+            AuditEventType::ProfileUpdated,
+        ),
+        (from(admin.id, "203.0.113.9"), AuditEventType::TokensRevoked),
+    ];
+    for (ctx, event) in events {
+        let data = serde_json::json!({});
+        AuditLog::record(&pool.get().unwrap(), ctx, *event, Some(user.id), data).unwrap();
+    }
+    let (export, token) =
+        dbmethods::start_export(user.id, ExportFormat::Json, pool.clone()).unwrap();
+    assert_eq!(export.status, "pending");
+    //only one export at a time
+    assert!(dbmethods::start_export(user.id, ExportFormat::Json, pool.clone()).is_err());
+    dbmethods::run_pending_exports(&pool).unwrap();
+    let (format, archive) = dbmethods::take_export(&token, &audit, pool.clone()).unwrap();
+    assert_eq!(format, ExportFormat::Json);
+    let data = serde_json::from_slice::<serde_json::Value>(&archive).unwrap();
+    assert_eq!(data["user"]["email"], user.email.as_str());
+    assert!(data["user"].get("password").is_none());
+    let events = data["audit_events"].as_array().unwrap();
+    assert!(events
+        .iter()
+        .any(|event| event["by_you"] == true && event["ip"] == "198.51.100.7"));
+    assert!(events
+        .iter()
+        .any(|event| event["event"] == "tokens_revoked" && event["ip"].is_null()));
+    let text = String::from_utf8(archive).unwrap();
+    assert!(!text.contains("203.0.113.9") && !text.contains(&format!("agent of {}", admin.id)));
+    assert!(dbmethods::take_export(&token, &audit, pool).is_err());
+}
+
+#[actix_rt::test]
+async fn test_oidc_code_flow_with_pkce() {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use models::{
+        dbmethods,
+        oauth::{ClientData, IdClaims, TokenResponse},
+    };
+    use sha2::{Digest, Sha256};
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let audit = models::audit::AuditContext::default();
+    let user = create_test_user(&pool, "oidc");
+    verify_test_user(&pool, &user);
+    let email = user.email.clone();
+    let client = ClientData {
+        name: "local client".to_owned(),
+        redirect_uris: vec!["http://localhost:9999/callback".to_owned()],
+        public: true,
+    };
+    let (client, secret) = dbmethods::create_client(client, &audit, pool.clone()).unwrap();
+    assert!(secret.is_none());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .configure(server::routes::oidc::oidc_route_config),
+    )
+    .await;
+    let verifier = "a-verifier-that-is-long-enough-for-pkce-1234567890";
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    let query = format!(
+        "response_type=code&client_id={}&redirect_uri={}&scope=openid%20email&state=st&nonce=nc\
+         &code_challenge={}&code_challenge_method=S256",
+        client.client_id,
+        urlencoding::encode("http://localhost:9999/callback"),
+        challenge
+    );
+    let req = test::TestRequest::get()
+        .uri(&format!("/oauth/authorize?{}", query))
+        .to_request();
+    let mut resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.take_body().as_str().contains("local client"));
+    //a redirect_uri the client did not register never gets a redirect
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/oauth/authorize?{}",
+            query.replace("callback", "elsewhere")
+        ))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let login = |password: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/authorize")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .set_payload(format!(
+                "{}&email={}&password={}&decision=allow",
+                query,
+                urlencoding::encode(&email),
+                password
+            ))
+            .to_request()
+    };
+    let resp = test::call_service(&mut app, login("wrong_password")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = test::call_service(&mut app, login(TEST_PASSWORD)).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let location = resp
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(location.starts_with("http://localhost:9999/callback?code="));
+    assert!(location.ends_with("&state=st"));
+    let code = location["http://localhost:9999/callback?code=".len()..]
+        .split('&')
+        .next()
+        .unwrap()
+        .to_owned();
+    let exchange = |verifier: &str| {
+        test::TestRequest::post()
+            .uri("/oauth/token")
+            .header(
+                header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .set_payload(format!(
+                "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&code_verifier={}",
+                code,
+                urlencoding::encode("http://localhost:9999/callback"),
+                client.client_id,
+                verifier
+            ))
+            .to_request()
+    };
+    let resp = test::call_service(&mut app, exchange(&verifier.replace('a', "b"))).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let mut resp = test::call_service(&mut app, exchange(verifier)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let tokens = serde_json::from_str::<TokenResponse>(resp.take_body().as_str()).unwrap();
+    assert_eq!(tokens.scope, "openid email");
+    let id_token = server::utils::KEYS
+        .verify::<IdClaims>(&tokens.id_token)
+        .unwrap()
+        .claims;
+    assert_eq!(id_token.aud, client.client_id);
+    assert_eq!(id_token.nonce.as_deref(), Some("nc"));
+    assert_eq!(id_token.user.sub, user.public_id.to_string());
+    assert_eq!(id_token.user.email.as_deref(), Some(email.as_str()));
+    assert!(id_token.user.name.is_none());
+    let userinfo = || {
+        test::TestRequest::get()
+            .uri("/oauth/userinfo")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", tokens.access_token),
+            )
+            .to_request()
+    };
+    let mut resp = test::call_service(&mut app, userinfo()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info = serde_json::from_str::<serde_json::Value>(resp.take_body().as_str()).unwrap();
+    assert_eq!(info["email_verified"], true);
+    //using the code again revokes the access token it was traded for
+    let resp = test::call_service(&mut app, exchange(verifier)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&mut app, userinfo()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    dbmethods::delete_client(&client.client_id, &audit, pool).unwrap();
+}
+
+//awc and test::start need the actix 1 runtime, actix_rt::test is the newer one
+#[test]
+fn test_login_with_an_upstream_provider() {
+    actix_web::rt::System::new("upstream").block_on(upstream_provider_login());
+}
+
+async fn upstream_provider_login() {
+    use actix_web::{HttpRequest, HttpResponse};
+    use diesel::prelude::*;
+    use server::{
+        keys::KeyRing,
+        providers::Providers,
+        settings::{JwtKey, ProviderSettings, Settings},
+    };
+    use std::{collections::HashMap, sync::Mutex};
+    //a provider like keycloak that knows the codes the test hands out, by code: (nonce, challenge, claims)
+    type Grants = Arc<Mutex<HashMap<String, (String, String, serde_json::Value)>>>;
+    let grants = Grants::default();
+    let mut upstream_settings = Settings::default();
+    upstream_settings.jwt.signing_key = "upstream".to_owned();
+    upstream_settings.jwt.keys = vec![JwtKey {
+        kid: "upstream".to_owned(),
+        algorithm: jsonwebtoken::Algorithm::RS256,
+        public_key: "src/tests/keys/rs256.pub.pem".into(),
+        private_key: Some("src/tests/keys/rs256.pem".into()),
+    }];
+    let upstream_keys = Arc::new(KeyRing::load(&upstream_settings).unwrap());
+    let upstream = {
+        let grants = grants.clone();
+        test::start(move || {
+            let (grants, keys) = (grants.clone(), upstream_keys.clone());
+            let jwks = keys.clone();
+            App::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(|req: HttpRequest| async move {
+                        let issuer = format!("http://{}", req.connection_info().host());
+                        Ok::<_, actix_web::Error>(HttpResponse::Ok().json(serde_json::json!({
+                            "issuer": issuer,
+                            "authorization_endpoint": format!("{}/auth", issuer),
+                            "token_endpoint": format!("{}/token", issuer),
+                            "jwks_uri": format!("{}/jwks", issuer),
+                        })))
+                    }),
+                )
+                .route(
+                    "/jwks",
+                    web::get().to(move || {
+                        let jwks = jwks.clone();
+                        async move { Ok::<_, actix_web::Error>(HttpResponse::Ok().json(jwks.jwks())) }
+                    }),
+                )
+                .route(
+                    "/token",
+                    web::post().to(
+                        move |req: HttpRequest, form: web::Form<HashMap<String, String>>| {
+                            let (grants, keys) = (grants.clone(), keys.clone());
+                            async move {
+                                use base64::{engine::general_purpose::STANDARD, Engine};
+                                let basic = format!("Basic {}", STANDARD.encode("rust_api:s3cret"));
+                                if req.headers().get(header::AUTHORIZATION).unwrap() != &basic {
+                                    return Ok(HttpResponse::Unauthorized()
+                                        .json(serde_json::json!({ "error": "invalid_client" })));
+                                }
+                                let grant = grants.lock().unwrap().remove(&form["code"]);
+                                let (nonce, challenge, mut claims) = match grant {
+                                    Some(grant) => grant,
+                                    None => {
+                                        return Ok(HttpResponse::BadRequest()
+                                            .json(serde_json::json!({ "error": "invalid_grant" })))
+                                    }
+                                };
+                                assert!(server::utils::pkce_matches(
+                                    &form["code_verifier"],
+                                    &challenge
+                                ));
+                                claims["iss"] =
+                                    format!("http://{}", req.connection_info().host()).into();
+                                claims["aud"] = "rust_api".into();
+                                claims["exp"] = (chrono::Utc::now().timestamp() + 300).into();
+                                claims["nonce"] = nonce.into();
+                                Ok::<_, actix_web::Error>(HttpResponse::Ok().json(serde_json::json!({
+                                    "access_token": "unused",
+                                    "token_type": "Bearer",
+                                    "id_token": keys.sign(&claims).unwrap(),
+                                })))
+                            }
+                        },
+                    ),
+                )
+        })
+    };
+    let provider = |name: &str, create_users| ProviderSettings {
+        name: name.to_owned(),
+        display_name: String::new(),
+        issuer: upstream.url(""),
+        client_id: "rust_api".to_owned(),
+        client_secret: "s3cret".to_owned(),
+        redirect_uri: String::new(),
+        scope: "openid email profile".to_owned(),
+        create_users,
+    };
+    let providers = web::Data::new(Providers::new(
+        &[provider("mock", true), provider("closed", false)],
+        "http://localhost:3000",
+    ));
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let mailer: web::Data<dyn mailer::Mailer> =
+        web::Data::from(Arc::new(mailer::MemoryMailer::default()) as Arc<dyn mailer::Mailer>);
+    let mut app = test::init_service(
+        App::new()
+            .app_data(pool.clone())
+            .app_data(mailer)
+            .app_data(providers)
+            .wrap(middlewares::auth::Auth)
+            .configure(server::routes::auth::auth_route_config),
+    )
+    .await;
+    let req = test::TestRequest::get().uri("/auth/providers").to_request();
+    let listed: serde_json::Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(listed["providers"][1]["display_name"], "closed");
+    //the test plays the browser: start, let the provider hand out a code, come back with it
+    let run = uuid::Uuid::new_v4().to_simple().to_string();
+    let new_email = format!("upstream_{}@some_user.com", run);
+    let login = |name: &str, claims: serde_json::Value, resp: serde_json::Value| {
+        let url = resp["authorization_url"].as_str().unwrap().to_owned();
+        let query = url.split_once('?').unwrap().1.split('&');
+        let param = |key: &str| {
+            let value = query
+                .clone()
+                .find_map(|p| p.strip_prefix(&format!("{}=", key)));
+            urlencoding::decode(value.unwrap()).unwrap().into_owned()
+        };
+        assert_eq!(
+            param("redirect_uri"),
+            format!("http://localhost:3000/login/{}/callback", name)
+        );
+        let code = uuid::Uuid::new_v4().to_string();
+        grants.lock().unwrap().insert(
+            code.clone(),
+            (param("nonce"), param("code_challenge"), claims),
+        );
+        let body = serde_json::json!({ "code": code, "state": resp["state"] });
+        test::TestRequest::post()
+            .uri(&format!("/auth/providers/{}/callback", name))
+            .set_json(&body)
+            .to_request()
+    };
+    let start = |name: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/auth/providers/{}", name))
+            .to_request()
+    };
+    //someone new gets an account, with the email verified if the provider says so
+    let claims = serde_json::json!({ "sub": format!("new-{}", run), "email": new_email,
+        "email_verified": true, "name": "Upstream User" });
+    for _ in 0..2 {
+        let started: serde_json::Value = test::read_response_json(&mut app, start("mock")).await;
+        let resp = test::call_service(&mut app, login("mock", claims.clone(), started)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut resp = resp;
+        assert!(resp.take_body().as_str().contains("refresh_token"));
+    }
+    let created = {
+        use server::schema::users::dsl::{email, users};
+        users
+            .filter(email.eq(&new_email))
+            .load::<models::user::User>(&pool.get().unwrap())
+            .unwrap()
+    };
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].name, "Upstream User");
+    assert!(created[0].email_verified_at.is_some());
+    //a state works once
+    let started: serde_json::Value = test::read_response_json(&mut app, start("mock")).await;
+    let resp = test::call_service(&mut app, login("mock", claims.clone(), started.clone())).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&mut app, login("mock", claims, started)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    //an email that has an account is only linked with its password
+    let owner = create_test_user(&pool, "owned");
+    let claims = serde_json::json!({ "sub": format!("owned-{}", run), "email": owner.email,
+        "email_verified": true });
+    let started: serde_json::Value = test::read_response_json(&mut app, start("mock")).await;
+    let linking: serde_json::Value =
+        test::read_response_json(&mut app, login("mock", claims.clone(), started)).await;
+    assert_eq!(linking["link_required"], true);
+    assert!(linking.get("refresh_token").is_none());
+    let link = |name: &str, password: &str| {
+        let body = serde_json::json!({ "link_token": linking["link_token"], "password": password });
+        test::TestRequest::post()
+            .uri(&format!("/auth/providers/{}/link", name))
+            .set_json(&body)
+            .to_request()
+    };
+    let resp = test::call_service(&mut app, link("mock", "wrong_password")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = test::call_service(&mut app, link("closed", TEST_PASSWORD)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = test::call_service(&mut app, link("mock", TEST_PASSWORD)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    //from now on the provider login alone is enough
+    let started: serde_json::Value = test::read_response_json(&mut app, start("mock")).await;
+    let mut resp = test::call_service(&mut app, login("mock", claims, started)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.take_body().as_str().contains("refresh_token"));
+    //without create_users only known people get in
+    let claims = serde_json::json!({ "sub": format!("closed-{}", run),
+        "email": format!("closed_{}@some_user.com", run) });
+    let started: serde_json::Value = test::read_response_json(&mut app, start("closed")).await;
+    let resp = test::call_service(&mut app, login("closed", claims, started)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn test_personal_access_tokens() {
+    //the admin below logs in without 2fa
+    let _admins = ADMINS.lock().unwrap_or_else(|err| err.into_inner());
+    actix_web::rt::System::new("api_tokens").block_on(personal_access_tokens());
+}
+
+async fn personal_access_tokens() {
+    use diesel::prelude::*;
+    use models::dbmethods;
+    let pool = web::Data::new(server::db::db::create_connection_pool());
+    let audit = models::audit::AuditContext::default();
+    let user = create_test_user(&pool, "pat");
+    verify_test_user(&pool, &user);
+    let mut app = test::init_service(
+        App::new()
+            .app_data(pool.clone())
+            .wrap(middlewares::auth::Auth)
+            .configure(server::routes::auth::auth_route_config)
+            .configure(server::routes::user::user_route_config)
+            .configure(server::routes::users::users_route_config),
+    )
+    .await;
+    let auth_data = models::user::AuthData {
+        email: user.email.clone(),
+        password: TEST_PASSWORD.to_owned(),
+    };
+    let req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let login: Token = test::read_response_json(&mut app, req).await;
+    let bearer = |token: &str| format!("Bearer {}", token);
+    let create = |body: serde_json::Value, token: &str| {
+        test::TestRequest::post()
+            .uri("/user/tokens")
+            .header(header::AUTHORIZATION, bearer(token))
+            .set_json(&body)
+            .to_request()
+    };
+    //scopes are limited to the permissions the user has
+    let body = serde_json::json!({ "name": "ci", "scopes": ["users:read"] });
+    let resp = test::call_service(&mut app, create(body.clone(), &login.token)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    dbmethods::assign_role(user.id, Some("admin"), &audit, pool.clone()).unwrap();
+    let req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let login: Token = test::read_response_json(&mut app, req).await;
+    let mut resp = test::call_service(&mut app, create(body.clone(), &login.token)).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let scoped = serde_json::from_str::<serde_json::Value>(resp.take_body().as_str()).unwrap();
+    let scoped_token = scoped["token"].as_str().unwrap().to_owned();
+    assert!(scoped_token.starts_with(scoped["prefix"].as_str().unwrap()));
+    let resp = test::call_service(&mut app, create(body, &login.token)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body = serde_json::json!({ "name": "backup", "expires_in_days": 30 });
+    let plain: serde_json::Value =
+        test::read_response_json(&mut app, create(body, &login.token)).await;
+    let plain_token = plain["token"].as_str().unwrap().to_owned();
+    let get = |uri: &str, token: &str| {
+        test::TestRequest::get()
+            .uri(uri)
+            .header(header::AUTHORIZATION, bearer(token))
+            .to_request()
+    };
+    //a token acts for the user, with only the permissions of its scopes and none of the roles
+    let me: serde_json::Value =
+        test::read_response_json(&mut app, get("/user", &scoped_token)).await;
+    assert_eq!(me["roles"], serde_json::json!([]));
+    let resp = test::call_service(&mut app, get("/users", &scoped_token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    //the admin 2fa policy still holds for the token of an admin
+    let policy = |on: &str| {
+        dbmethods::set_setting(dbmethods::REQUIRE_ADMIN_2FA, on, &audit, pool.clone()).unwrap()
+    };
+    policy("true");
+    let resp = test::call_service(&mut app, get("/users", &scoped_token)).await;
+    policy("false");
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = test::call_service(&mut app, get("/users", &plain_token)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    //and can not manage tokens, nor log out a session
+    let resp = test::call_service(&mut app, get("/user/tokens", &scoped_token)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body = serde_json::json!({ "name": "more" });
+    let resp = test::call_service(&mut app, create(body, &scoped_token)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::delete()
+        .uri("/auth")
+        .header(header::AUTHORIZATION, bearer(&plain_token))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let mut tampered = plain_token.clone();
+    tampered.pop();
+    tampered.push(if plain_token.ends_with('0') { '1' } else { '0' });
+    let resp = test::call_service(&mut app, get("/user", &tampered)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let mut resp = test::call_service(&mut app, get("/user/tokens", &login.token)).await;
+    let listed = resp.take_body().as_str().to_owned();
+    assert!(!listed.contains(&plain_token) && !listed.contains("token_hash"));
+    let listed = serde_json::from_str::<serde_json::Value>(&listed).unwrap();
+    assert_eq!(listed["tokens"].as_array().unwrap().len(), 2);
+    assert!(listed["tokens"][1]["last_used_at"].is_string());
+    //losing the role takes the permission from the token too, deleting it ends it
+    {
+        use server::schema::user_roles::dsl::{user_id, user_roles};
+        diesel::delete(user_roles.filter(user_id.eq(user.id)))
+            .execute(&pool.get().unwrap())
+            .unwrap();
+    }
+    let resp = test::call_service(&mut app, get("/users", &scoped_token)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::post()
+        .set_json(&auth_data)
+        .uri("/auth")
+        .to_request();
+    let login: Token = test::read_response_json(&mut app, req).await;
+    let req = test::TestRequest::delete()
+        .uri(&format!("/user/tokens/{}", plain["id"].as_str().unwrap()))
+        .header(header::AUTHORIZATION, bearer(&login.token))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&mut app, get("/user", &plain_token)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    //a password change deletes the tokens that are left
+    let body = serde_json::json!({ "name": "laptop" });
+    let laptop: serde_json::Value =
+        test::read_response_json(&mut app, create(body, &login.token)).await;
+    let laptop_token = laptop["token"].as_str().unwrap().to_owned();
+    let resp = test::call_service(&mut app, get("/user", &laptop_token)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let change = models::user::UserChange {
+        name: None,
+        email: None,
+        password: Some(format!("{}-changed", TEST_PASSWORD)),
+    };
+    dbmethods::user_update(user.id, change, "en", &audit, pool.clone()).unwrap();
+    for token in &[laptop_token, scoped_token] {
+        let resp = test::call_service(&mut app, get("/user", token)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,59 @@
+//RFC 6238 time based one time passwords, sha1 / 30 seconds / 6 digits
+//like every authenticator app expects
+use hmac::{Hmac, Mac, NewMac};
+use sha1::Sha1;
+
+pub const STEP_SECONDS: u64 = 30;
+pub const DIGITS: u32 = 6;
+//codes from one step before or after still count, for clock drift
+const DRIFT_STEPS: i64 = 1;
+
+//random 160 bit secret, base32 like authenticator apps want it
+pub fn generate_secret() -> String {
+    let bytes: [u8; 20] = rand::random();
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes)
+}
+
+pub fn otpauth_uri(secret: &str, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(issuer),
+        urlencoding::encode(account),
+        secret,
+        urlencoding::encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+pub fn current_step() -> i64 {
+    chrono::Utc::now().timestamp() / STEP_SECONDS as i64
+}
+
+pub fn code_at(secret: &str, step: i64) -> Option<String> {
+    let key = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).ok()?;
+    mac.update(&(step as u64).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    //dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    Some(format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    ))
+}
+
+//returns the step the code belongs to, callers should refuse steps that were
+//already used so a code can not be replayed
+pub fn verify(secret: &str, code: &str, now_step: i64) -> Option<i64> {
+    let code = code.trim();
+    (now_step - DRIFT_STEPS..=now_step + DRIFT_STEPS)
+        .find(|step| code_at(secret, *step).as_deref() == Some(code))
+}
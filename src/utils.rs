@@ -1,77 +1,334 @@
-use actix_web::HttpRequest;
-use chrono::{Duration, Utc};
+use chrono::{Duration, NaiveDateTime, Utc};
 use jsonwebtoken::{
     decode, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
 };
 
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
 use crate::{
     errors::ServiceError,
-    models::user::{Claims, SlimUser},
+    keys::KeyRing,
+    models::{
+        identity::LinkClaims,
+        oauth::{AuthCode, IdClaims, UserClaims},
+        role::Access,
+        session::Session,
+        two_factor::MfaClaims,
+        user::{Claims, UnverifiedLogin, User},
+    },
+    settings,
 };
 
+//all of these come from the loaded settings, see settings.rs for what each one means
 lazy_static::lazy_static! {
-    pub static ref SECRET_KEY: String = std::env::var("SECRET_KEY").unwrap_or_else(|_| "sct07".repeat(8));
+    pub static ref SECRET_KEY: String = settings::get().auth.secret_key.clone();
+    //signs access tokens, main already checked that it loads
+    pub static ref KEYS: KeyRing = KeyRing::load(settings::get()).unwrap_or_else(|err| panic!("{}", err));
+    pub static ref HASH_POLICY: HashPolicy = settings::get().hashing;
+    //base of links we put in emails
+    pub static ref APP_URL: String = settings::get().server.app_url.clone();
+    //name authenticator apps show next to the code
+    pub static ref TOTP_ISSUER: String = settings::get().auth.totp_issuer.clone();
+    //deny blocks login until the email is verified
+    pub static ref UNVERIFIED_LOGIN: UnverifiedLogin = settings::get().auth.unverified_login;
+    //days a deleted account can still be restored before it is purged
+    pub static ref DELETION_GRACE_DAYS: i64 = settings::get().auth.deletion_grace_days;
+    //how long a login stays valid without being refreshed
+    pub static ref SESSION_DAYS: i64 = settings::get().auth.session_days;
+    //lifetime of the bearer token itself
+    pub static ref ACCESS_TOKEN_MINUTES: i64 = settings::get().auth.access_token_minutes;
+    //time to enter the 2fa code after the password
+    pub static ref MFA_TOKEN_MINUTES: i64 = settings::get().auth.mfa_token_minutes;
+    //time to cancel the deletion after logging into a deleted account
+    pub static ref RESTORE_TOKEN_MINUTES: i64 = settings::get().auth.restore_token_minutes;
+    //how long a step-up token from POST /auth/reauth works
+    pub static ref REAUTH_MINUTES: i64 = settings::get().auth.reauth_minutes;
+    //how long a password reset link works
+    pub static ref RESET_TOKEN_MINUTES: i64 = settings::get().auth.reset_token_minutes;
+    //how long a finished data export waits for its one download
+    pub static ref EXPORT_DOWNLOAD_HOURS: i64 = settings::get().auth.export_download_hours;
+    //how long an openid connect authorization code can be traded for tokens
+    pub static ref AUTHORIZATION_CODE_SECONDS: i64 = settings::get().auth.authorization_code_seconds;
+    //time to come back from a provider login, and to link the account it found
+    pub static ref PROVIDER_LOGIN_MINUTES: i64 = settings::get().auth.provider_login_minutes;
+    //how long an email verification link works
+    pub static ref VERIFY_TOKEN_HOURS: i64 = settings::get().auth.verify_token_hours;
+}
+
+//argon2id cost parameters new password hashes are created with
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HashPolicy {
+    //in KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for HashPolicy {
+    fn default() -> Self {
+        Self {
+            memory: 19456,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
 }
 
-const SALT: &str = "supersecretsalt";
+impl HashPolicy {
+    //true if the encoded hash is not argon2id or is cheaper than this policy
+    pub fn is_weaker(&self, hash: &str) -> bool {
+        //$argon2id$v=19$m=19456,t=2,p=1$salt$hash
+        let parts: Vec<&str> = hash.split('$').collect();
+        if parts.len() != 6 || parts[1] != "argon2id" {
+            return true;
+        }
+        let mut params = (0, 0, 0);
+        for (key, value) in parts[3]
+            .split(',')
+            .filter_map(|param| param.split_once('='))
+        {
+            let value = value.parse::<u32>().unwrap_or(0);
+            match key {
+                "m" => params.0 = value,
+                "t" => params.1 = value,
+                "p" => params.2 = value,
+                _ => {}
+            }
+        }
+        params.0 < self.memory || params.1 < self.iterations || params.2 < self.parallelism
+    }
+}
+
+//recovery codes handed out when 2fa is turned on
+pub const RECOVERY_CODES: usize = 10;
+//minimum time between two verification mails
+pub const VERIFY_RESEND_SECONDS: i64 = 60;
+//wrong codes one mfa token takes before it stops working
+pub const MFA_CHALLENGE_FAILURES: i32 = 5;
+//wrong codes for one user within MFA_LOCK_MINUTES before the second step is locked for them
+pub const MFA_USER_FAILURES: i64 = 10;
+pub const MFA_LOCK_MINUTES: i64 = 15;
+//wrong passwords for one user within PASSWORD_LOCK_MINUTES of the first before every password check is locked
+pub const PASSWORD_FAILURES: i32 = 10;
+pub const PASSWORD_LOCK_MINUTES: i64 = 15;
 
 pub fn hash_password(passwd: &str) -> Result<String, ServiceError> {
+    let policy = *HASH_POLICY;
     let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        mem_cost: policy.memory,
+        time_cost: policy.iterations,
+        lanes: policy.parallelism,
         secret: SECRET_KEY.as_bytes(),
         ..Default::default()
     };
-    argon2::hash_encoded(passwd.as_bytes(), &SALT.as_bytes(), &config).map_err(|err| {
+    let salt: [u8; 16] = rand::random();
+    argon2::hash_encoded(passwd.as_bytes(), &salt, &config).map_err(|err| {
         dbg!(err);
         ServiceError::InternalServerError
     })
 }
 
 pub fn verify_hash(hash: &str, passwd: &str) -> Result<bool, ServiceError> {
-    argon2::verify_encoded_ext(hash, &passwd.as_bytes(), &SECRET_KEY.as_bytes(), &[]).map_err(
-        |err| {
-            dbg!(err);
-            ServiceError::Unauthorized
-        },
-    )
+    argon2::verify_encoded_ext(hash, passwd.as_bytes(), SECRET_KEY.as_bytes(), &[]).map_err(|err| {
+        dbg!(err);
+        ServiceError::Unauthorized
+    })
 }
 
-pub fn parse_request(req: HttpRequest) -> (String, String) {
-    let mut email = String::new();
-    let mut clearance = String::new();
-    if let Some(e) = req.headers().get("user_email") {
-        email = e.to_str().unwrap().to_owned();
-    }
-    if let Some(c) = req.headers().get("user_clearance") {
-        clearance = c.to_str().unwrap().to_owned();
-    }
-    (email, clearance)
+//returns the token and when it expires
+pub fn create_jwt(
+    user: &User,
+    session: &Session,
+    access: &Access,
+) -> Result<(String, NaiveDateTime), ServiceError> {
+    let expire = Utc::now() + Duration::minutes(*ACCESS_TOKEN_MINUTES);
+    let claims = Claims {
+        sub: user.id,
+        email: user.email.clone(),
+        roles: access.roles.clone(),
+        permissions: access.permissions.clone(),
+        jti: session.id.to_string(),
+        ver: user.token_version,
+        email_verified: user.email_verified_at.is_some(),
+        mfa: session.mfa,
+        exp: expire.timestamp() as usize,
+    };
+
+    let token = KEYS.sign(&claims)?;
+    Ok((token, expire.naive_utc()))
 }
 
-pub fn create_jwt(user_data: SlimUser) -> Result<String, ServiceError> {
-    let expire = Utc::now()
-        .checked_add_signed(Duration::days(60))
-        .unwrap()
-        .timestamp();
+pub fn decode_jwt(token: String) -> Result<TokenData<Claims>, ServiceError> {
+    KEYS.verify(&token)
+}
 
-    let claims = Claims {
-        exp: expire as usize,
-        email: user_data.email,
-        clearance: user_data.clearance,
+//the iss of id tokens, also what the discovery document says
+pub fn issuer() -> String {
+    APP_URL.trim_end_matches('/').to_owned()
+}
+
+//id token for an openid connect client, signed like access tokens so clients can check it with the jwks
+pub fn create_id_token(user: &User, code: &AuthCode) -> Result<String, ServiceError> {
+    let now = Utc::now();
+    let claims = IdClaims {
+        iss: issuer(),
+        aud: code.client_id.clone(),
+        exp: (now + Duration::minutes(*ACCESS_TOKEN_MINUTES)).timestamp() as usize,
+        iat: now.timestamp() as usize,
+        auth_time: code.auth_time.and_utc().timestamp() as usize,
+        nonce: code.nonce.clone(),
+        user: UserClaims::new(user, &code.scope),
     };
+    KEYS.sign(&claims)
+}
+
+//challenge token for the second login step, challenge is its mfa_challenges row
+pub fn create_mfa_token(
+    user: &User,
+    challenge: Uuid,
+) -> Result<(String, NaiveDateTime), ServiceError> {
+    create_challenge_token(user, "mfa", Some(challenge), *MFA_TOKEN_MINUTES)
+}
 
-    let header = Header::new(Algorithm::HS512);
+pub fn decode_mfa_token(token: &str) -> Result<MfaClaims, ServiceError> {
+    decode_challenge_token(token, "mfa")
+}
 
-    Ok(encode(
-        &header,
+//what a login into a deleted account gets instead of tokens, only good for POST /auth/restore
+pub fn create_restore_token(user: &User) -> Result<(String, NaiveDateTime), ServiceError> {
+    create_challenge_token(user, "restore", None, *RESTORE_TOKEN_MINUTES)
+}
+
+pub fn decode_restore_token(token: &str) -> Result<MfaClaims, ServiceError> {
+    decode_challenge_token(token, "restore")
+}
+
+//what a provider login gets for an email that has an account, only good for POST /auth/providers/{name}/link
+pub fn create_link_token(
+    user: &User,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> Result<(String, NaiveDateTime), ServiceError> {
+    let expire = Utc::now() + Duration::minutes(*PROVIDER_LOGIN_MINUTES);
+    let claims = LinkClaims {
+        sub: user.id,
+        ver: user.token_version,
+        typ: "link".to_owned(),
+        provider: provider.to_owned(),
+        subject: subject.to_owned(),
+        email: email.map(str::to_owned),
+        exp: expire.timestamp() as usize,
+    };
+    let token = encode(
+        &Header::new(Algorithm::HS512),
         &claims,
         &EncodingKey::from_secret(SECRET_KEY.as_ref()),
-    )?)
+    )?;
+    Ok((token, expire.naive_utc()))
 }
 
-pub fn decode_jwt(token: String) -> Result<TokenData<Claims>, ServiceError> {
-    Ok(decode::<Claims>(
-        &token,
+pub fn decode_link_token(token: &str) -> Result<LinkClaims, ServiceError> {
+    let data = decode::<LinkClaims>(
+        token,
+        &DecodingKey::from_secret(SECRET_KEY.as_ref()),
+        &Validation::new(Algorithm::HS512),
+    )
+    .map_err(|_| ServiceError::Unauthorized)?;
+    if data.claims.typ != "link" {
+        return Err(ServiceError::Unauthorized);
+    }
+    Ok(data.claims)
+}
+
+//challenge tokens are only ever read by us, so they stay on the secret and out of the jwks
+fn create_challenge_token(
+    user: &User,
+    typ: &str,
+    jti: Option<Uuid>,
+    minutes: i64,
+) -> Result<(String, NaiveDateTime), ServiceError> {
+    let expire = Utc::now() + Duration::minutes(minutes);
+    let claims = MfaClaims {
+        sub: user.id,
+        ver: user.token_version,
+        typ: typ.to_owned(),
+        jti,
+        exp: expire.timestamp() as usize,
+    };
+    let token = encode(
+        &Header::new(Algorithm::HS512),
+        &claims,
+        &EncodingKey::from_secret(SECRET_KEY.as_ref()),
+    )?;
+    Ok((token, expire.naive_utc()))
+}
+
+fn decode_challenge_token(token: &str, typ: &str) -> Result<MfaClaims, ServiceError> {
+    let data = decode::<MfaClaims>(
+        token,
         &DecodingKey::from_secret(SECRET_KEY.as_ref()),
         &Validation::new(Algorithm::HS512),
-    )?)
+    )
+    .map_err(|_| ServiceError::Unauthorized)?;
+    if data.claims.typ != typ {
+        return Err(ServiceError::Unauthorized);
+    }
+    Ok(data.claims)
+}
+
+//random opaque token for refresh tokens and the like, hex encoded
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    hex::encode(bytes)
+}
+
+//opaque tokens are only ever stored as their sha256
+pub fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+//pkce S256, the verifier has to hash to the challenge the authorization started with
+pub fn pkce_matches(verifier: &str, challenge: &str) -> bool {
+    (43..=128).contains(&verifier.len()) && pkce_challenge(verifier) == challenge
+}
+
+pub fn pkce_challenge(verifier: &str) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use sha2::{Digest, Sha256};
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+//wraps the words of text that are in matched (lowercase) in <mark>, the rest is html escaped
+pub fn highlight(text: &str, matched: &[String]) -> String {
+    use crate::mailer::templates::escape_html;
+    let mut out = String::with_capacity(text.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, out: &mut String| {
+        if word.is_empty() {
+            return;
+        }
+        if matched.contains(&word.to_lowercase()) {
+            out.push_str("<mark>");
+            out.push_str(&escape_html(word));
+            out.push_str("</mark>");
+        } else {
+            out.push_str(&escape_html(word));
+        }
+        word.clear();
+    };
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            out.push_str(&escape_html(&c.to_string()));
+        }
+    }
+    flush(&mut word, &mut out);
+    out
 }